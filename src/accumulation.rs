@@ -0,0 +1,311 @@
+//! Accumulation conveyors with zero pressure zones
+//!
+//! An accumulation conveyor is one station over several grid cells, a zone
+//! per cell with a fotocell at its front end and a motor under it. A zone
+//! runs while it is empty or the zone ahead of it runs, so details close up
+//! zone by zone without ever pushing on each other.
+//!
+//! The station holds a [`Register`] position per zone, see [`RegisterZones`].
+//! A detail moves on to the position of the next zone when it clears the
+//! fotocell of its zone, and is handed over from the last zone with the same
+//! [`ShiftOver`] as a transfer station. Stations push into an accumulation
+//! conveyor whenever its first zone is free, whatever else it is doing.
+
+use std::borrow::Cow;
+
+use avian3d::prelude::CollisionLayers;
+use bevy::prelude::*;
+
+use crate::{
+    fotocell::{FotocellAssets, FotocellBundle, FotocellKind},
+    io::{Dio, DioPin, IoDevices, NodeId, Switch},
+    physics::PhysLayer,
+    shiftreg::{Register, RegisterPosition, RegisterZones, ShiftOver},
+    sysorder::ScanSet,
+    tbana::{
+        in_auto, AutoMode, Direction, ManualJog, Movimot, MovimotDQ, PushTo, RunState,
+        StartSending, StopRunning, TBanaAssets, TransportBana, TransportState,
+        TransportWheelBundle, WHEEL_HEIGHT,
+    },
+};
+
+pub struct AccumulationPlugin;
+
+impl Plugin for AccumulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Zone>();
+        app.add_systems(FixedUpdate, control_zones.in_set(ScanSet::Control));
+        app.add_observer(on_insert_accumulation);
+    }
+}
+
+/// Most zones one accumulation conveyor can have
+pub const MAX_ZONES: usize = 8;
+/// Zone fotocells, in the order of the inputs of a conveyor
+pub const ZONE_SENSOR_NAMES: [&str; MAX_ZONES] = [
+    "zone_0", "zone_1", "zone_2", "zone_3", "zone_4", "zone_5", "zone_6", "zone_7",
+];
+/// Zone motor outputs, in the order of the outputs of a conveyor
+pub const ZONE_MOTOR_SIGNALS: [&str; 3 * MAX_ZONES] = [
+    "zone 0 fw",
+    "zone 0 rev",
+    "zone 0 rapid",
+    "zone 1 fw",
+    "zone 1 rev",
+    "zone 1 rapid",
+    "zone 2 fw",
+    "zone 2 rev",
+    "zone 2 rapid",
+    "zone 3 fw",
+    "zone 3 rev",
+    "zone 3 rapid",
+    "zone 4 fw",
+    "zone 4 rev",
+    "zone 4 rapid",
+    "zone 5 fw",
+    "zone 5 rev",
+    "zone 5 rapid",
+    "zone 6 fw",
+    "zone 6 rev",
+    "zone 6 rapid",
+    "zone 7 fw",
+    "zone 7 rev",
+    "zone 7 rapid",
+];
+
+/// Wheels of a zone, around its center
+const ZONE_WHEELS_Z: [f32; 2] = [-0.5, 0.5];
+/// Zone fotocell, just before the end of the zone
+const ZONE_SENSOR_Z: f32 = 0.9;
+/// Length of the conveyor bed under one zone
+const ZONE_BED: f32 = 2.0;
+
+#[derive(Component, Debug)]
+/// Zone logic of an accumulation conveyor
+pub struct AccumulationConveyor {
+    /// zone fotocells at the last scan, first zone first
+    occupied: Vec<bool>,
+    infeed_free: bool,
+}
+
+impl AccumulationConveyor {
+    pub fn new(zones: u8) -> Self {
+        Self {
+            occupied: vec![false; zones as usize],
+            infeed_free: false,
+        }
+    }
+
+    /// The first zone is free, a station may push a detail in
+    pub fn accepts(&self) -> bool {
+        self.infeed_free
+    }
+}
+
+#[derive(Component, Reflect, Clone, Copy, Debug, Deref)]
+/// Zone a fotocell or wheel of an accumulation conveyor belongs to, `0` at the infeed
+pub struct Zone(pub u8);
+
+#[derive(Event, Clone)]
+/// Build an accumulation conveyor on `entity`, its zones `pitch` apart
+/// along its local z from its transform on
+pub struct InsertAccumulation {
+    pub entity: Entity,
+    pub name: Cow<'static, str>,
+    pub zones: u8,
+    pub pitch: f32,
+    /// a fotocell per zone
+    pub inputs: Vec<Dio>,
+    /// forward, reverse and rapid of a motor per zone
+    pub outputs: Vec<Dio>,
+    pub transform: Transform,
+    pub register_pos: RegisterPosition,
+    pub push_to: Option<PushTo>,
+}
+
+fn on_insert_accumulation(
+    spawn: On<InsertAccumulation>,
+    mut cmd: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    fotocell_assets: Res<FotocellAssets>,
+    tbana_assets: Res<TBanaAssets>,
+) {
+    let zones = spawn.zones as usize;
+    if zones == 0 || spawn.inputs.len() != zones || spawn.outputs.len() != 3 * zones {
+        warn!(
+            "{}: {zones} zones need {zones} inputs and {} outputs",
+            spawn.name,
+            3 * zones
+        );
+        return;
+    }
+    let zone_z = |zone: usize| zone as f32 * spawn.pitch;
+
+    let mut children = Vec::new();
+    for (zone, dio) in spawn.inputs.iter().enumerate() {
+        let mut transform = Transform::from_xyz(0.45, 0.6, zone_z(zone) + ZONE_SENSOR_Z);
+        transform.rotate_local_y(-90_f32.to_radians());
+        let name = ZONE_SENSOR_NAMES[zone];
+        let fotocell = FotocellBundle::new(
+            name,
+            dio.pin,
+            &fotocell_assets,
+            dio.node,
+            FotocellKind::Reflex,
+            0.8,
+        );
+        children.push(cmd.spawn((fotocell, transform, Zone(zone as u8))).id());
+    }
+    let phys_layers = CollisionLayers::new(PhysLayer::Actuator, PhysLayer::Detail);
+    for (zone, dios) in spawn.outputs.chunks(3).enumerate() {
+        let dq = MovimotDQ {
+            forward: dios[0],
+            reverse: dios[1],
+            rapid: dios[2],
+        };
+        for z in ZONE_WHEELS_Z {
+            let mut transform = Transform::from_xyz(0.0, WHEEL_HEIGHT, zone_z(zone) + z);
+            transform.rotate_local_y(90_f32.to_radians());
+            let wheel = TransportWheelBundle::new(&tbana_assets, dq, transform);
+            children.push(cmd.spawn((wheel, phys_layers, Zone(zone as u8))).id());
+        }
+    }
+    let length = zone_z(zones - 1) + ZONE_BED;
+    let bed = cmd
+        .spawn((
+            Mesh3d(meshes.add(Extrusion::new(Rectangle::default(), length))),
+            MeshMaterial3d(tbana_assets.station_material()),
+            Transform::from_xyz(0.0, 0.0, zone_z(zones - 1) / 2.0),
+        ))
+        .id();
+    children.push(bed);
+
+    let mut station = cmd.entity(spawn.entity);
+    station
+        .insert((
+            TransportBana,
+            AccumulationConveyor::new(spawn.zones),
+            AutoMode::new(true),
+            RunState::Ready,
+            ManualJog::default(),
+            TransportState::default(),
+            Direction::Forward,
+            Name::new(spawn.name.clone()),
+            spawn.transform,
+            Visibility::default(),
+            spawn.register_pos,
+            RegisterZones(spawn.zones as u16),
+        ))
+        .add_children(&children)
+        .observe(on_start_discharge)
+        .observe(on_stop_accumulation);
+    if let Some(push_to) = spawn.push_to {
+        station.insert(push_to);
+    }
+}
+
+fn on_start_discharge(trigger: On<StartSending>, mut states: Query<&mut TransportState>) {
+    if let Ok(mut state) = states.get_mut(trigger.entity) {
+        *state = TransportState::Sending;
+    }
+}
+
+fn on_stop_accumulation(
+    trigger: On<StopRunning>,
+    mut conveyors: Query<(&mut TransportState, &Children)>,
+    motors: Query<&Movimot>,
+    mut io: ResMut<IoDevices>,
+) {
+    let Ok((mut state, children)) = conveyors.get_mut(trigger.0) else {
+        return;
+    };
+    *state = TransportState::NotReady;
+    for motor in children.iter().filter_map(|e| motors.get(e).ok()) {
+        for dio in [motor.dq.forward, motor.dq.reverse, motor.dq.rapid] {
+            io.set_output_bit(dio.node, dio.pin, false);
+        }
+    }
+}
+
+/// Follow the details from zone to zone in the register and run the zones
+/// that may run, while the conveyor is in auto
+fn control_zones(
+    mut cmd: Commands,
+    mut conveyors: Query<(
+        Entity,
+        &mut AccumulationConveyor,
+        &mut TransportState,
+        &RegisterPosition,
+        &AutoMode,
+        &RunState,
+        Option<&PushTo>,
+        &Children,
+    )>,
+    sensors: Query<(&Zone, &NodeId, &DioPin), With<Switch>>,
+    motors: Query<(&Zone, &Movimot)>,
+    mut io: ResMut<IoDevices>,
+    mut reg: ResMut<Register>,
+) {
+    for (entity, mut conveyor, mut state, pos, auto, run, push_to, children) in conveyors.iter_mut()
+    {
+        let pos = pos.as_usize();
+        let n = conveyor.occupied.len();
+        let mut occupied = vec![false; n];
+        for (zone, node, pin) in children.iter().filter_map(|e| sensors.get(e).ok()) {
+            if let Some(seen) = occupied.get_mut(zone.0 as usize) {
+                *seen = io.get_input_bit(*node, *pin) == Some(true);
+            }
+        }
+
+        // front zone first, so a detail follows the one ahead into its position
+        let mut remembered = occupied.clone();
+        for i in (0..n).rev() {
+            if !conveyor.occupied[i] || occupied[i] {
+                continue;
+            }
+            if i + 1 == n {
+                if *state == TransportState::Sending {
+                    *state = TransportState::NotReady;
+                    if let Some(to) = push_to {
+                        cmd.trigger(ShiftOver {
+                            from: entity,
+                            to: to.0,
+                        });
+                    }
+                }
+                continue;
+            }
+            let (here, ahead) = (pos + i, pos + i + 1);
+            if !reg.occupied(here) {
+                continue;
+            }
+            if reg.occupied(ahead) {
+                // the detail ahead is handed over at the end of this scan
+                remembered[i] = true;
+            } else {
+                reg.move_slot(here, ahead);
+            }
+        }
+        conveyor.occupied = remembered;
+        conveyor.infeed_free = !reg.occupied(pos) && !occupied[0];
+
+        // manual jog and stops own the motors outside of auto
+        if !in_auto(auto, run) {
+            continue;
+        }
+        if *state == TransportState::NotReady && occupied[n - 1] && reg.occupied(pos + n - 1) {
+            *state = TransportState::ReadySend;
+        }
+        let mut running = vec![false; n];
+        let mut ahead_runs = *state == TransportState::Sending;
+        for i in (0..n).rev() {
+            running[i] = !occupied[i] || ahead_runs;
+            ahead_runs = running[i];
+        }
+        for (zone, motor) in children.iter().filter_map(|e| motors.get(e).ok()) {
+            let run = running.get(zone.0 as usize).copied().unwrap_or_default();
+            io.set_output_bit(motor.dq.forward.node, motor.dq.forward.pin, run);
+            io.set_output_bit(motor.dq.reverse.node, motor.dq.reverse.pin, false);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Analog sensors, a laser distance sensor and a load cell, that write
+//! scaled measurements to input words like an analog input card
+//!
+//! A measurement gets its noise, is rounded to the resolution of the sensor
+//! and scaled to `0..=full_scale` over the measuring range, the way an S7
+//! card maps 4-20 mA to `0..=27648`.
+//!
+//! The layout mounts them on stations, see [`crate::layout::DeviceKind`].
+//! Their words skip the input filter, so programs never read half of a
+//! new measurement.
+
+use std::borrow::Cow;
+
+use avian3d::prelude::*;
+use bevy::{color::palettes::css, prelude::*};
+use rand::Rng;
+
+use crate::{
+    io::{FieldIo, Io, IoAddress, Width},
+    physics::PhysLayer,
+    sensor::{cast_distance, SensingShape},
+    simclock::SimRng,
+    sysorder::ScanSet,
+};
+
+pub struct AnalogPlugin;
+
+impl Plugin for AnalogPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (measure_distance, weigh).in_set(ScanSet::Input),
+        );
+        app.add_systems(Update, render_analog);
+    }
+}
+
+/// Word of a measurement at the top of its range
+pub const FULL_SCALE: u16 = 27648;
+
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+/// How a measurement in meters or kilograms becomes an input word
+pub struct AnalogScaling {
+    /// measurement at word 0
+    pub min: f32,
+    /// measurement at `full_scale`
+    pub max: f32,
+    pub full_scale: u16,
+    /// smallest step the sensor tells apart, 0 for none
+    pub resolution: f32,
+    /// largest noise added to a measurement, uniformly distributed
+    pub noise: f32,
+}
+
+impl AnalogScaling {
+    /// Noise free scaling of `min..=max` to `0..=FULL_SCALE`
+    pub fn new(min: f32, max: f32) -> Self {
+        Self {
+            min,
+            max,
+            full_scale: FULL_SCALE,
+            resolution: 0.0,
+            noise: 0.0,
+        }
+    }
+    pub fn with_resolution(mut self, resolution: f32) -> Self {
+        self.resolution = resolution;
+        self
+    }
+    pub fn with_noise(mut self, noise: f32) -> Self {
+        self.noise = noise;
+        self
+    }
+    /// Measurement as the sensor reports it, `noise` is the sampled noise
+    pub fn quantize(&self, value: f32, noise: f32) -> f32 {
+        let value = value + noise;
+        if self.resolution > 0.0 {
+            (value / self.resolution).round() * self.resolution
+        } else {
+            value
+        }
+    }
+    /// Input word of a reported measurement, clamped to the range
+    /// ```
+    /// # use cybercrab::analog::AnalogScaling;
+    /// let scaling = AnalogScaling::new(0.0, 2.0).with_resolution(0.01);
+    /// assert_eq!(scaling.to_word(1.0), 13824);
+    /// assert_eq!(scaling.to_word(-1.0), 0);
+    /// assert_eq!(scaling.to_word(3.0), 27648);
+    /// assert!((scaling.quantize(0.123, 0.0) - 0.12).abs() < 1e-6);
+    /// ```
+    pub fn to_word(&self, value: f32) -> u16 {
+        let span = self.max - self.min;
+        if span <= 0.0 {
+            return 0;
+        }
+        let fraction = ((value - self.min) / span).clamp(0.0, 1.0);
+        (fraction * self.full_scale as f32).round() as u16
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy)]
+/// Input word an analog sensor writes to
+pub struct AnalogInput {
+    pub address: IoAddress,
+    pub scaling: AnalogScaling,
+    /// last reported measurement
+    pub value: f32,
+}
+
+impl AnalogInput {
+    /// Analog inputs are words, like `%IW4` or `N1:%IW0`
+    pub fn new(address: IoAddress, scaling: AnalogScaling) -> Result<Self> {
+        if address.kind != Io::Input || address.width != Width::Word {
+            return Err(format!("{address} is not an input word").into());
+        }
+        Ok(Self {
+            address,
+            scaling,
+            value: scaling.min,
+        })
+    }
+    /// Report `value` with noise and resolution applied
+    fn publish(&mut self, value: f32, field: &mut FieldIo, rng: &mut SimRng) {
+        let scaling = self.scaling;
+        let noise = if scaling.noise > 0.0 {
+            rng.random_range(-scaling.noise..=scaling.noise)
+        } else {
+            0.0
+        };
+        self.value = scaling.quantize(value, noise);
+        if let Err(err) = field.write(self.address, scaling.to_word(self.value)) {
+            warn_once!("analog input: {err}");
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy)]
+/// Measures the distance to the nearest detail along local +z
+pub struct LaserDistance {
+    pub range: f32,
+}
+
+#[derive(Bundle)]
+pub struct LaserDistanceBundle {
+    pub laser: LaserDistance,
+    pub input: AnalogInput,
+    pub name: Name,
+}
+
+impl LaserDistanceBundle {
+    /// Reports `0..=range` meters, nothing in range reads as `range`
+    pub fn new(
+        name: impl Into<Cow<'static, str>>,
+        address: IoAddress,
+        range: f32,
+        resolution: f32,
+        noise: f32,
+    ) -> Result<Self> {
+        let scaling = AnalogScaling::new(0.0, range)
+            .with_resolution(resolution)
+            .with_noise(noise);
+        Ok(Self {
+            laser: LaserDistance { range },
+            input: AnalogInput::new(address, scaling)?,
+            name: Name::new(name),
+        })
+    }
+}
+
+fn measure_distance(
+    spatial: SpatialQuery,
+    lasers: Query<(Entity, &LaserDistance, &GlobalTransform, &mut AnalogInput)>,
+    mut field: ResMut<FieldIo>,
+    mut rng: ResMut<SimRng>,
+) {
+    for (entity, laser, transform, mut input) in lasers {
+        let filter =
+            SpatialQueryFilter::from_mask(PhysLayer::Detail).with_excluded_entities([entity]);
+        let distance = cast_distance(
+            &spatial,
+            transform.translation(),
+            -transform.forward(),
+            laser.range,
+            SensingShape::Ray,
+            &filter,
+        );
+        input.publish(distance.unwrap_or(laser.range), &mut field, &mut rng);
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy)]
+/// Platform with its top face at local y = 0, it weighs the details resting
+/// on it
+pub struct LoadCell {
+    pub half_extents: Vec2,
+}
+
+/// How far above the platform a detail still rests on it
+const RESTING_GAP: f32 = 0.02;
+
+#[derive(Bundle)]
+pub struct LoadCellBundle {
+    pub load_cell: LoadCell,
+    pub input: AnalogInput,
+    pub name: Name,
+}
+
+impl LoadCellBundle {
+    /// Reports `0..=capacity` kilograms
+    pub fn new(
+        name: impl Into<Cow<'static, str>>,
+        address: IoAddress,
+        half_extents: Vec2,
+        capacity: f32,
+        resolution: f32,
+        noise: f32,
+    ) -> Result<Self> {
+        let scaling = AnalogScaling::new(0.0, capacity)
+            .with_resolution(resolution)
+            .with_noise(noise);
+        Ok(Self {
+            load_cell: LoadCell { half_extents },
+            input: AnalogInput::new(address, scaling)?,
+            name: Name::new(name),
+        })
+    }
+}
+
+/// Details weigh their rigid body mass, details without a body the mass of
+/// their collider at its density
+fn weigh(
+    spatial: SpatialQuery,
+    cells: Query<(&LoadCell, &GlobalTransform, &mut AnalogInput)>,
+    bodies: Query<(&Collider, Option<&ComputedMass>, Option<&ColliderDensity>)>,
+    mut field: ResMut<FieldIo>,
+    mut rng: ResMut<SimRng>,
+) {
+    let filter = SpatialQueryFilter::from_mask(PhysLayer::Detail);
+    for (cell, transform, mut input) in cells {
+        let size = cell.half_extents * 2.0;
+        let volume = Collider::cuboid(size.x, RESTING_GAP, size.y);
+        let center = transform.transform_point(Vec3::Y * RESTING_GAP / 2.0);
+        let resting = spatial.shape_intersections(&volume, center, transform.rotation(), &filter);
+        let mass: f32 = resting
+            .into_iter()
+            .filter_map(|entity| bodies.get(entity).ok())
+            .map(|(collider, mass, density)| match mass {
+                Some(mass) => mass.value(),
+                None => collider.mass(density.map_or(1.0, |density| density.0)),
+            })
+            .sum();
+        input.publish(mass, &mut field, &mut rng);
+    }
+}
+
+fn render_analog(
+    mut gizmos: Gizmos,
+    lasers: Query<(&LaserDistance, &GlobalTransform, &AnalogInput)>,
+    cells: Query<(&LoadCell, &GlobalTransform), With<AnalogInput>>,
+) {
+    for (laser, transform, input) in lasers {
+        let start = transform.translation();
+        let length = input.value.clamp(0.0, laser.range);
+        gizmos.line(start, start - transform.forward() * length, css::RED);
+    }
+    for (cell, transform) in cells {
+        let size = Vec3::new(
+            cell.half_extents.x * 2.0,
+            RESTING_GAP,
+            cell.half_extents.y * 2.0,
+        );
+        let center = transform.transform_point(Vec3::Y * RESTING_GAP / 2.0);
+        let box_transform = Transform::from_translation(center)
+            .with_rotation(transform.rotation())
+            .with_scale(size);
+        gizmos.cuboid(box_transform, css::ORANGE);
+    }
+}
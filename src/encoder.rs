@@ -0,0 +1,202 @@
+//! Incremental encoders on the [`Movimot`] wheels
+//!
+//! The count follows the wheel as the motor outputs drive it, four counts per
+//! pulse like a x4 counter. An encoder either puts its A and B channels on two
+//! inputs, the control program has to see every edge then, or hands its count
+//! to a counter module that shows it as an input word.
+//!
+//! Quadrature inputs show one edge per scan at most, an encoder that turns
+//! faster is limited to one count per scan instead of counting backwards.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use crate::{
+    io::{Dio, DioPin, FieldIo, Io, IoAddress, NodeId, Switch, SwitchSet},
+    safety::SafetyRelay,
+    sysorder::ScanSet,
+    tbana::{Movimot, WHEEL_RADIUS},
+};
+
+pub struct EncoderPlugin;
+
+impl Plugin for EncoderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(on_add_encoder);
+        app.add_systems(
+            FixedUpdate,
+            (mount_encoders, count_pulses)
+                .chain()
+                .in_set(ScanSet::Input),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderOutput {
+    /// A and B in quadrature, A leads while the wheel runs forward
+    Quadrature { a: Dio, b: Dio },
+    /// 16 bit counter, the count wraps around
+    Counter(IoAddress),
+}
+
+#[derive(Component, Debug)]
+/// Encoder on the shaft of a wheel with a [`Movimot`]
+pub struct Encoder {
+    pub pulses_per_rev: u32,
+    pub output: EncoderOutput,
+    /// counts since start, fractions of a count included
+    position: f64,
+    channels: Option<[Entity; 2]>,
+}
+
+impl Encoder {
+    pub fn new(pulses_per_rev: u32, output: EncoderOutput) -> Self {
+        Self {
+            pulses_per_rev,
+            output,
+            position: 0.0,
+            channels: None,
+        }
+    }
+    pub fn count(&self) -> i64 {
+        self.position.floor() as i64
+    }
+    /// Channels A and B of a count, one of them changes per count
+    /// ```
+    /// # use cybercrab::encoder::Encoder;
+    /// let forward: Vec<_> = (0..5).map(Encoder::channels_at).collect();
+    /// assert_eq!(forward, [(false, false), (true, false), (true, true), (false, true), (false, false)]);
+    /// assert_eq!(Encoder::channels_at(-1), (false, true));
+    /// ```
+    pub fn channels_at(count: i64) -> (bool, bool) {
+        match count.rem_euclid(4) {
+            0 => (false, false),
+            1 => (true, false),
+            2 => (true, true),
+            _ => (false, true),
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy)]
+/// Puts an [`Encoder`] on the first wheel of the parent station driven by the
+/// motor with this forward output, once the station has its wheels
+pub struct EncoderMount {
+    pub forward: Dio,
+    pub pulses_per_rev: u32,
+    pub output: EncoderOutput,
+}
+
+#[derive(Component, Debug)]
+/// Channel A or B of an [`Encoder`], wired to an input like any switch
+pub struct EncoderChannel;
+
+/// Spawn the A and B channels of quadrature encoders as children
+fn on_add_encoder(trigger: On<Add, Encoder>, mut cmd: Commands, mut encoders: Query<&mut Encoder>) {
+    let entity = trigger.event_target();
+    let Ok(mut encoder) = encoders.get_mut(entity) else {
+        return;
+    };
+    let EncoderOutput::Quadrature { a, b } = encoder.output else {
+        return;
+    };
+    let channel = |name: &'static str, dio: Dio| {
+        (
+            EncoderChannel,
+            Switch,
+            Name::new(name),
+            dio.node,
+            dio.pin,
+            ChildOf(entity),
+        )
+    };
+    let a = cmd.spawn(channel("A", a)).id();
+    let b = cmd.spawn(channel("B", b)).id();
+    encoder.channels = Some([a, b]);
+}
+
+fn mount_encoders(
+    mut cmd: Commands,
+    mounts: Query<(Entity, &EncoderMount, &ChildOf)>,
+    stations: Query<&Children>,
+    motors: Query<&Movimot>,
+) {
+    for (entity, mount, parent) in mounts.iter() {
+        let Ok(children) = stations.get(parent.parent()) else {
+            continue;
+        };
+        let driven = |child: &Entity| {
+            motors
+                .get(*child)
+                .is_ok_and(|motor| motor.dq.forward == mount.forward)
+        };
+        let Some(wheel) = children.iter().find(driven) else {
+            continue;
+        };
+        cmd.entity(wheel)
+            .insert(Encoder::new(mount.pulses_per_rev, mount.output));
+        cmd.entity(entity).despawn();
+    }
+}
+
+fn count_pulses(
+    mut cmd: Commands,
+    mut encoders: Query<(&mut Encoder, &Movimot)>,
+    channels: Query<(&NodeId, &DioPin), With<EncoderChannel>>,
+    mut field: ResMut<FieldIo>,
+    safety: Res<SafetyRelay>,
+    time: Res<Time>,
+) {
+    for (mut encoder, motor) in encoders.iter_mut() {
+        let speed = if safety.outputs_enabled() {
+            motor.surface_speed(&field)
+        } else {
+            0.0
+        };
+        let revs = speed * time.delta_secs() / (TAU * WHEEL_RADIUS);
+        let mut counts = 4.0 * encoder.pulses_per_rev as f64 * revs as f64;
+        let quadrature = matches!(encoder.output, EncoderOutput::Quadrature { .. });
+        if quadrature && counts.abs() > 1.0 {
+            warn_once!("quadrature encoder turns faster than one count per scan");
+            counts = counts.clamp(-1.0, 1.0);
+        }
+        if counts == 0.0 {
+            continue;
+        }
+        let before = encoder.count();
+        encoder.position += counts;
+        let count = encoder.count();
+        if count == before {
+            continue;
+        }
+        match encoder.output {
+            EncoderOutput::Counter(address) => {
+                let value = count.rem_euclid(1 << 16) as u16;
+                if let Err(err) = field.write(address, value) {
+                    warn_once!("encoder counter: {err}");
+                }
+            }
+            EncoderOutput::Quadrature { .. } => {
+                let Some(entities) = encoder.channels else {
+                    continue;
+                };
+                let (a, b) = Encoder::channels_at(count);
+                for (entity, closed) in entities.into_iter().zip([a, b]) {
+                    let current = channels
+                        .get(entity)
+                        .ok()
+                        .and_then(|(node, pin)| field.get_input_bit(*node, *pin));
+                    if current != Some(closed) {
+                        cmd.trigger(SwitchSet {
+                            entity,
+                            closed,
+                            kind: Io::Input,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! IO nodes as devices on a fieldbus
+//!
+//! Sensors and actuators live on the field side, [`FieldIo`], control
+//! programs on the process image, [`IoDevices`]. Every node exchanges the
+//! two once per update cycle, and what it sends arrives after the
+//! transmission delay. The exchange runs at the start of every scan, so
+//! cycles shorter than a scan see the field as it was at that scan.
+//!
+//! Inputs arriving at the process image pass the input filter of their pin,
+//! see [`IOStore::apply_filters`], so filter times are rounded up to scans.
+//! Input words of the layout devices arrive whole, unfiltered.
+
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use bitvec::vec::BitVec;
+
+use crate::{
+    io::{FieldIo, IOStore, Io, IoDevices, NodeId},
+    layout::{ensure_io_size, ApplyLayout, Layout},
+    sysorder::ScanSet,
+};
+
+pub struct FieldbusPlugin;
+
+impl Plugin for FieldbusPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FieldIo>();
+        app.init_resource::<Fieldbus>();
+        app.add_systems(FixedUpdate, exchange.in_set(ScanSet::Input));
+        app.add_observer(on_apply_layout);
+        app.add_observer(on_set_node_online);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// What the process image shows for the inputs of a node that is offline
+pub enum OfflineInputs {
+    /// keep the last values that arrived
+    #[default]
+    Freeze,
+    /// drop all inputs to 0
+    Zero,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Timing of one node, zero cycle and delay is an ideal bus that
+/// exchanges every scan
+pub struct BusConfig {
+    pub cycle: Duration,
+    pub delay: Duration,
+    pub offline_inputs: OfflineInputs,
+}
+
+#[derive(Debug)]
+/// Images of one cycle on their way over the bus
+struct Telegram {
+    arrival: Duration,
+    inputs: Option<BitVec<u8>>,
+    outputs: Option<BitVec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct BusNode {
+    pub config: BusConfig,
+    online: bool,
+    next_cycle: Duration,
+    in_flight: VecDeque<Telegram>,
+    /// when the last telegram arrived
+    pub last_update: Option<Duration>,
+}
+
+impl BusNode {
+    pub fn new(config: BusConfig) -> Self {
+        Self {
+            config,
+            online: true,
+            next_cycle: Duration::ZERO,
+            in_flight: VecDeque::new(),
+            last_update: None,
+        }
+    }
+    pub fn online(&self) -> bool {
+        self.online
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+/// Every node on the bus, one per declared io module
+pub struct Fieldbus {
+    pub nodes: HashMap<NodeId, BusNode>,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+/// Take a node off the bus or bring it back, like pulling its cable
+pub struct SetNodeOnline {
+    pub node: NodeId,
+    pub online: bool,
+}
+
+/// Copy `bits` into `store`, through [`IOStore::set`] so forced pins stay
+fn copy_bits(bits: &BitVec<u8>, store: &mut IOStore) {
+    for (idx, bit) in bits.iter().enumerate() {
+        store.set(idx, *bit);
+    }
+}
+
+fn clear_store(store: Option<&mut IOStore>) {
+    if let Some(store) = store {
+        for idx in 0..store.state.len() {
+            store.set(idx, false);
+        }
+    }
+}
+
+/// Start of the last cycle at or before `now`
+fn last_cycle_start(next_cycle: Duration, cycle: Duration, now: Duration) -> Duration {
+    if cycle.is_zero() {
+        return now;
+    }
+    let missed = (now - next_cycle).as_nanos() / cycle.as_nanos();
+    next_cycle + Duration::from_nanos((missed * cycle.as_nanos()) as u64)
+}
+
+fn exchange(
+    time: Res<Time>,
+    mut bus: ResMut<Fieldbus>,
+    mut field: ResMut<FieldIo>,
+    mut io: ResMut<IoDevices>,
+) {
+    let now = time.elapsed();
+    for (node, bus_node) in bus.nodes.iter_mut() {
+        if !bus_node.online {
+            continue;
+        }
+        if bus_node.next_cycle <= now {
+            let config = bus_node.config;
+            let start = last_cycle_start(bus_node.next_cycle, config.cycle, now);
+            bus_node.next_cycle = start + config.cycle;
+            bus_node.in_flight.push_back(Telegram {
+                arrival: start + config.delay,
+                inputs: field
+                    .digital_inputs
+                    .get(node)
+                    .map(|store| store.state.clone()),
+                outputs: io
+                    .digital_outputs
+                    .get(node)
+                    .map(|store| store.state.clone()),
+            });
+        }
+        while bus_node
+            .in_flight
+            .front()
+            .is_some_and(|telegram| telegram.arrival <= now)
+        {
+            let Some(telegram) = bus_node.in_flight.pop_front() else {
+                break;
+            };
+            if let (Some(bits), Some(store)) = (&telegram.inputs, io.digital_inputs.get_mut(node)) {
+                for (idx, bit) in bits.iter().enumerate() {
+                    store.set_raw(idx, *bit, now);
+                }
+            }
+            if let (Some(bits), Some(store)) =
+                (&telegram.outputs, field.digital_outputs.get_mut(node))
+            {
+                copy_bits(bits, store);
+            }
+            bus_node.last_update = Some(now);
+        }
+    }
+    for store in io.digital_inputs.values_mut() {
+        store.apply_filters(now);
+    }
+}
+
+/// An offline node drops its outputs like a real node does when its
+/// watchdog runs out, its inputs freeze or drop to zero
+fn on_set_node_online(
+    trigger: On<SetNodeOnline>,
+    mut bus: ResMut<Fieldbus>,
+    mut field: ResMut<FieldIo>,
+    mut io: ResMut<IoDevices>,
+) {
+    let node = trigger.node;
+    let Some(bus_node) = bus.nodes.get_mut(&node) else {
+        return;
+    };
+    if bus_node.online == trigger.online {
+        return;
+    }
+    bus_node.online = trigger.online;
+    bus_node.in_flight.clear();
+    if trigger.online {
+        // the next scan starts a new cycle
+        bus_node.next_cycle = Duration::ZERO;
+        return;
+    }
+    info!("N{} went offline", node.0);
+    clear_store(field.digital_outputs.get_mut(&node));
+    if bus_node.config.offline_inputs == OfflineInputs::Zero {
+        clear_store(io.digital_inputs.get_mut(&node));
+    }
+}
+
+/// Keep one bus node per module, nodes that stay keep their state
+fn on_apply_layout(
+    _trigger: On<ApplyLayout>,
+    layout: Res<Layout>,
+    mut bus: ResMut<Fieldbus>,
+    mut field: ResMut<FieldIo>,
+) {
+    bus.nodes
+        .retain(|node, _| layout.modules.iter().any(|module| module.node == *node));
+    for module in layout.modules.iter() {
+        bus.nodes
+            .entry(module.node)
+            .or_insert_with(|| BusNode::new(module.bus))
+            .config = module.bus;
+    }
+    ensure_io_size(&mut field.digital_inputs, layout.io_size(Io::Input));
+    ensure_io_size(&mut field.digital_outputs, layout.io_size(Io::Output));
+}
@@ -5,9 +5,13 @@ use bevy::{color::palettes::css, prelude::*};
 // use bevy_polyline::{material::PolylineMaterialHandle, polyline::PolylineHandle, prelude::*};
 
 use crate::{
-    io::{DioPin, Io, IoDevices, NodeId, Switch, SwitchSet},
-    sensor::{on_sensor_switch, SensorPosition},
-    sysorder::InitSet,
+    io::{DioPin, FieldIo, NodeId, Switch},
+    physics::PhysLayer,
+    sensor::{
+        cast_distance, on_sensor_switch, report, SensingShape, SensorFault, SensorPosition,
+        SensorSignal, SwitchingPoint,
+    },
+    sysorder::{InitSet, ScanSet},
 };
 
 pub struct FotocellPlugin;
@@ -19,6 +23,9 @@ impl Plugin for FotocellPlugin {
         app.add_systems(Startup, load_fotocell_assets.in_set(InitSet::LoadAssets));
         app.add_observer(on_sensor_switch);
         app.register_type::<SensorPosition>();
+        app.register_type::<FotocellKind>();
+        app.add_observer(on_add_fotocell);
+        app.add_systems(FixedUpdate, sense_beams.in_set(ScanSet::Input));
         app.add_systems(Update, render_fotocell_detector);
     }
 }
@@ -46,7 +53,8 @@ fn load_fotocell_assets(
         Rectangle::new(0.02, 0.02),
         LASER_VERTS[0].z - 0.01,
     ));
-    material_assets.add(StandardMaterial {
+    fotocell_assets.reflector = mesh_assets.add(Cuboid::new(0.06, 0.06, 0.01));
+    fotocell_assets.foto_materials.emmiter = material_assets.add(StandardMaterial {
         base_color: css::HOT_PINK.into(),
         ..Default::default()
     });
@@ -59,20 +67,157 @@ fn load_fotocell_assets(
 #[derive(Component)]
 pub struct DetectorRay;
 
-pub fn on_fotocell_blocked(trigger: On<CollisionStart>, mut cmd: Commands) {
-    cmd.trigger(SwitchSet {
-        entity: trigger.event_target(),
-        closed: true,
-        kind: Io::Input,
-    });
+/// Reflectors and receivers tolerate this much misalignment of the beam
+const BEAM_TOLERANCE: f32 = 10.0 * std::f32::consts::PI / 180.0;
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Reflect, Default)]
+pub enum FotocellKind {
+    /// emitter and receiver in one housing, the beam bounces off a reflector
+    #[default]
+    Reflex,
+    /// separate emitter and receiver facing each other
+    ThroughBeam,
+    /// sees the light the object itself scatters back, no reflector
+    Diffuse,
 }
 
-pub fn on_fotocell_unblocked(trigger: On<CollisionEnd>, mut cmd: Commands) {
-    cmd.trigger(SwitchSet {
-        entity: trigger.event_target(),
-        closed: false,
-        kind: Io::Input,
-    });
+impl FotocellKind {
+    /// Longest working range in meters, typical of small industrial sensors
+    pub const fn max_range(self) -> f32 {
+        match self {
+            Self::Reflex => 6.0,
+            Self::ThroughBeam => 20.0,
+            Self::Diffuse => 0.5,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+/// Reflector, or receiver, of the fotocell that is its parrent
+pub struct Reflector;
+
+/// Give reflex and through beam fotocells their reflector, `range` in
+/// front of the emitter
+fn on_add_fotocell(
+    trigger: On<Add, Fotocell>,
+    mut cmd: Commands,
+    mut fotocells: Query<&mut Fotocell>,
+    fotocell_assets: Res<FotocellAssets>,
+) {
+    let entity = trigger.event_target();
+    let Ok(mut fotocell) = fotocells.get_mut(entity) else {
+        return;
+    };
+    let (name, mesh, material) = match fotocell.kind {
+        FotocellKind::Diffuse => return,
+        FotocellKind::Reflex => (
+            "reflector",
+            &fotocell_assets.reflector,
+            &fotocell_assets.foto_materials.reflector,
+        ),
+        FotocellKind::ThroughBeam => (
+            "receiver",
+            &fotocell_assets.emmiter,
+            &fotocell_assets.foto_materials.reflector,
+        ),
+    };
+    let reflector = cmd
+        .spawn((
+            Reflector,
+            Name::new(name),
+            Mesh3d(mesh.clone()),
+            MeshMaterial3d(material.clone()),
+            Transform::from_xyz(0.0, 0.0, fotocell.range),
+            ChildOf(entity),
+        ))
+        .id();
+    fotocell.reflector = Some(reflector);
+}
+
+/// Whether the fotocell detects something and the distance to it. Beams
+/// detect while no light reaches the receiver, diffuse fotocells while
+/// something is within their switching point. Any collider is seen, except
+/// other sensors
+fn sense(
+    spatial: &SpatialQuery,
+    entity: Entity,
+    fotocell: &Fotocell,
+    transform: &GlobalTransform,
+    detected: bool,
+    reflectors: &Query<&Transform, With<Reflector>>,
+) -> (bool, Option<f32>) {
+    let origin = transform.translation();
+    let forward = -transform.forward();
+    let mask = [
+        PhysLayer::Detail,
+        PhysLayer::Component,
+        PhysLayer::Actuator,
+        PhysLayer::Station,
+    ];
+    let filter = SpatialQueryFilter::from_mask(mask).with_excluded_entities([entity]);
+    let max_range = fotocell.kind.max_range();
+    if fotocell.kind == FotocellKind::Diffuse {
+        let reach = fotocell.switching.reach().min(max_range);
+        let hit = cast_distance(spatial, origin, forward, reach, fotocell.shape, &filter);
+        return (fotocell.switching.switch(detected, hit), hit);
+    }
+    let Some(target) = fotocell
+        .reflector
+        .and_then(|reflector| reflectors.get(reflector).ok())
+    else {
+        return (true, None);
+    };
+    // the reflector is a child, so this holds before transforms propagate
+    let to_target = transform.transform_point(target.translation) - origin;
+    let distance = to_target.length();
+    // out of range or misaligned, the light never comes back
+    if distance > max_range || forward.angle_between(to_target) > BEAM_TOLERANCE {
+        return (true, None);
+    }
+    let Ok(direction) = Dir3::new(to_target) else {
+        return (true, None);
+    };
+    let hit = cast_distance(
+        spatial,
+        origin,
+        direction,
+        distance,
+        fotocell.shape,
+        &filter,
+    );
+    (hit.is_some(), hit)
+}
+
+/// Runs once per fixed step, right after the physics step of the last one,
+/// so no position of a detail goes unseen
+fn sense_beams(
+    mut cmd: Commands,
+    spatial: SpatialQuery,
+    mut fotocells: Query<(
+        Entity,
+        &Fotocell,
+        &GlobalTransform,
+        &mut SensorSignal,
+        Option<&SensorFault>,
+    )>,
+    reflectors: Query<&Transform, With<Reflector>>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    for (entity, fotocell, transform, mut signal, fault) in fotocells.iter_mut() {
+        let (detected, distance) = sense(
+            &spatial,
+            entity,
+            fotocell,
+            transform,
+            signal.detected,
+            &reflectors,
+        );
+        if signal.distance != distance {
+            signal.distance = distance;
+        }
+        report(&mut cmd, entity, &mut signal, detected, now, fault);
+    }
 }
 
 #[derive(Default, Reflect, GizmoConfigGroup)]
@@ -80,20 +225,47 @@ struct DetectorGizmos;
 
 fn render_fotocell_detector(
     mut gizmos: Gizmos<DetectorGizmos>,
-    q: Query<(&Fotocell, &GlobalTransform, &NodeId, &DioPin)>,
-    devices: Res<IoDevices>,
+    q: Query<(
+        &Fotocell,
+        &GlobalTransform,
+        &NodeId,
+        &DioPin,
+        &SensorSignal,
+        Option<&SensorFault>,
+    )>,
+    reflectors: Query<&GlobalTransform, With<Reflector>>,
+    devices: Res<FieldIo>,
 ) {
-    for (fc, transform, address, pin) in q {
+    for (fc, transform, address, pin, signal, fault) in q {
         let start = transform.translation();
-        let end = start - transform.forward() * fc.range;
+        let (direction, length) = match fc
+            .reflector
+            .and_then(|reflector| reflectors.get(reflector).ok())
+        {
+            Some(reflector) => {
+                let to_reflector = reflector.translation() - start;
+                (to_reflector.normalize_or_zero(), to_reflector.length())
+            }
+            None => (
+                -transform.forward().as_vec3(),
+                fc.switching.reach().min(fc.kind.max_range()),
+            ),
+        };
+        // the beam ends where it hits something
+        let end = start + direction * signal.distance.unwrap_or(length).min(length);
 
-        let color = match devices.digital_inputs.get(address) {
-            Some(device) => match device.get(pin.as_usize()) {
-                Some(true) => css::GREEN,
-                Some(false) => css::PURPLE,
-                None => css::GRAY, // no value at pin number
-            },
-            None => css::DARK_GRAY, // no device
+        let bit = devices
+            .digital_inputs
+            .get(address)
+            .map(|device| device.get(pin.as_usize()));
+        let color = match (fault, bit) {
+            (Some(SensorFault::WireBreak), _) => css::RED,
+            (Some(_), Some(Some(true))) => css::YELLOW,
+            (Some(_), _) => css::ORANGE,
+            (None, Some(Some(true))) => css::GREEN,
+            (None, Some(Some(false))) => css::PURPLE,
+            (None, Some(None)) => css::GRAY, // no value at pin number
+            (None, None) => css::DARK_GRAY,  // no device
         };
         // let color = match devices.digital_inputs.get(k)
 
@@ -111,9 +283,7 @@ pub struct FotocellBundle {
     pub io_pin: DioPin,
     pub mesh: Mesh3d,
     material: MeshMaterial3d<StandardMaterial>,
-    simbody: RigidBody,
-    collider: Collider,
-    collision_marker: CollisionEventsEnabled,
+    signal: SensorSignal,
 }
 
 impl FotocellBundle {
@@ -122,28 +292,36 @@ impl FotocellBundle {
         io_slot: DioPin,
         fotocell_assets: &FotocellAssets,
         device: NodeId,
+        kind: FotocellKind,
         range: f32,
     ) -> Self {
-        let collider = Collider::segment(
-            Vec3::ZERO,
-            Vec3 {
-                z: range,
-                ..default()
-            },
-        );
         Self {
-            fotocell_mark: Fotocell { range },
+            fotocell_mark: Fotocell {
+                kind,
+                range,
+                shape: default(),
+                switching: SwitchingPoint::new(range),
+                reflector: None,
+            },
             name: Name::new(name),
             io_pin: io_slot,
             device,
             mesh: Mesh3d(fotocell_assets.emmiter.clone()),
             material: MeshMaterial3d(fotocell_assets.foto_materials.emmiter.clone()),
             switch: default(),
-            simbody: RigidBody::Kinematic,
-            collision_marker: CollisionEventsEnabled,
-            collider,
+            signal: default(),
         }
     }
+    /// Cast a sphere instead of a ray, for wide beams
+    pub fn with_shape(mut self, shape: SensingShape) -> Self {
+        self.fotocell_mark.shape = shape;
+        self
+    }
+    /// Switching point of a diffuse fotocell, by default at its range
+    pub fn with_switching(mut self, switching: SwitchingPoint) -> Self {
+        self.fotocell_mark.switching = switching;
+        self
+    }
     pub fn with_translation(self, translation: Vec3) -> (Self, Transform) {
         (self, Transform::from_translation(translation))
     }
@@ -166,9 +344,15 @@ pub struct FotocellAssets {
 }
 
 #[derive(Component)]
-/// Adds fotocell behaivor to a Entity
+/// Adds fotocell behaivor to a Entity, the beam points along local +z
 pub struct Fotocell {
+    kind: FotocellKind,
+    /// distance to the reflector or receiver, sensing distance when diffuse
     range: f32,
+    shape: SensingShape,
+    /// where a diffuse fotocell switches
+    switching: SwitchingPoint,
+    reflector: Option<Entity>,
 }
 
 // Fotocell,
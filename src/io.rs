@@ -1,3 +1,5 @@
+use std::{fmt, str::FromStr, time::Duration};
+
 use bevy::{platform::collections::HashMap, prelude::*};
 use bitvec::vec::BitVec;
 
@@ -58,15 +60,57 @@ impl Iterator for DIOModule {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Filter time of a digital input, a change reaches the process image once
+/// the field has held it this long. Real DI cards default to about 3 ms
+pub struct InputFilter {
+    pub on_delay: Duration,
+    pub off_delay: Duration,
+}
+
+impl InputFilter {
+    pub fn symmetric(delay: Duration) -> Self {
+        Self {
+            on_delay: delay,
+            off_delay: delay,
+        }
+    }
+    /// How long the field has to hold `value`
+    pub fn delay(&self, value: bool) -> Duration {
+        if value {
+            self.on_delay
+        } else {
+            self.off_delay
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct IOStore {
     pub state: BitVec<u8>,
     taken: BitVec<u8>,
+    /// pins held at their value, writes to them are ignored
+    forced: BitVec<u8>,
+    /// field value of every pin before the input filter
+    raw: BitVec<u8>,
+    /// when each raw bit last changed
+    raw_since: Vec<Duration>,
+    /// filter of pins without one of their own
+    pub filter: InputFilter,
+    pub pin_filters: HashMap<usize, InputFilter>,
 }
 
 impl IOStore {
+    /// Writes to forced pins and pins past the end are dropped, the write
+    /// skips the input filter
     pub fn set(&mut self, idx: usize, value: bool) {
-        self.state.set(idx, value);
+        if self.is_forced(idx) {
+            return;
+        }
+        if let Some(mut bit) = self.state.get_mut(idx) {
+            *bit = value;
+            self.raw.set(idx, value);
+        }
     }
     pub fn get(&self, idx: usize) -> Option<bool> {
         self.state.get(idx).map(|v| *v)
@@ -74,9 +118,90 @@ impl IOStore {
     pub fn new(size: usize) -> Self {
         let mut state = BitVec::new();
         let mut taken = BitVec::new();
+        let mut forced = BitVec::new();
         state.resize(size, false);
         taken.resize(size, false);
-        Self { state, taken }
+        forced.resize(size, false);
+        Self {
+            raw: state.clone(),
+            raw_since: vec![Duration::ZERO; size],
+            state,
+            taken,
+            forced,
+            filter: default(),
+            pin_filters: HashMap::new(),
+        }
+    }
+    /// Grow or shrink the store, new pins are free, off and not forced
+    pub fn resize(&mut self, size: usize) {
+        self.state.resize(size, false);
+        self.taken.resize(size, false);
+        self.forced.resize(size, false);
+        self.raw.resize(size, false);
+        self.raw_since.resize(size, Duration::ZERO);
+    }
+    pub fn is_forced(&self, idx: usize) -> bool {
+        self.forced.get(idx).is_some_and(|forced| *forced)
+    }
+    /// Hold a pin at `value` whatever writes to it, `None` releases it
+    pub fn force(&mut self, idx: usize, value: Option<bool>) {
+        if idx >= self.state.len() {
+            return;
+        }
+        self.forced.set(idx, value.is_some());
+        if let Some(value) = value {
+            self.state.set(idx, value);
+        }
+    }
+    pub fn n_forced(&self) -> usize {
+        self.forced.count_ones()
+    }
+    /// Field value of a pin before the input filter
+    pub fn raw(&self, idx: usize) -> Option<bool> {
+        self.raw.get(idx).map(|v| *v)
+    }
+    pub fn filter_of(&self, idx: usize) -> InputFilter {
+        self.pin_filters.get(&idx).copied().unwrap_or(self.filter)
+    }
+    /// Field value of a pin at `now`, [`Self::apply_filters`] passes it on
+    /// once it has been stable for the filter time
+    pub fn set_raw(&mut self, idx: usize, value: bool, now: Duration) {
+        let Some(mut bit) = self.raw.get_mut(idx) else {
+            return;
+        };
+        if *bit != value {
+            *bit = value;
+            self.raw_since[idx] = now;
+        }
+    }
+    /// Pass on the raw values that have been stable long enough, pulses
+    /// shorter than the filter time never reach the pins
+    /// ```
+    /// # use std::time::Duration;
+    /// # use cybercrab::io::{IOStore, InputFilter};
+    /// let ms = Duration::from_millis;
+    /// let mut store = IOStore::new(8);
+    /// store.filter = InputFilter::symmetric(ms(20));
+    /// store.set_raw(0, true, ms(0));
+    /// store.set_raw(0, false, ms(10));
+    /// store.apply_filters(ms(30));
+    /// assert_eq!(store.get(0), Some(false));
+    /// store.set_raw(0, true, ms(40));
+    /// store.apply_filters(ms(50));
+    /// assert_eq!((store.get(0), store.raw(0)), (Some(false), Some(true)));
+    /// store.apply_filters(ms(60));
+    /// assert_eq!(store.get(0), Some(true));
+    /// ```
+    pub fn apply_filters(&mut self, now: Duration) {
+        for idx in 0..self.raw.len() {
+            let raw = self.raw[idx];
+            if raw == self.state[idx] || self.is_forced(idx) {
+                continue;
+            }
+            if now.saturating_sub(self.raw_since[idx]) >= self.filter_of(idx).delay(raw) {
+                self.state.set(idx, raw);
+            }
+        }
     }
     pub fn take_pin(&mut self, idx: usize) -> Option<DioPin> {
         let mut is_taken = self.taken.get_mut(idx)?;
@@ -98,6 +223,7 @@ impl Iterator for IOStore {
 }
 
 #[derive(Resource, Default, Debug)]
+/// Process image, the pins as control programs see them
 pub struct IoDevices {
     pub digital_inputs: HashMap<NodeId, IOStore>,
     pub digital_outputs: HashMap<NodeId, IOStore>,
@@ -108,16 +234,154 @@ impl IoDevices {
         let device = self.digital_inputs.get(&node)?;
         device.get(pin.as_usize())
     }
+    /// Input as the field shows it, before the input filter
+    pub fn get_raw_input_bit(&self, node: NodeId, pin: DioPin) -> Option<bool> {
+        let device = self.digital_inputs.get(&node)?;
+        device.raw(pin.as_usize())
+    }
     pub fn get_output_bit(&self, node: NodeId, pin: DioPin) -> Option<bool> {
         let device = self.digital_outputs.get(&node)?;
         device.get(pin.as_usize())
     }
+    /// Writes to missing devices are dropped, the layout io check reports them
     pub fn set_output_bit(&mut self, node: NodeId, pin: DioPin, value: bool) {
-        let device = self.digital_outputs.get_mut(&node).unwrap();
-        device.set(pin.as_usize(), value);
+        if let Some(device) = self.digital_outputs.get_mut(&node) {
+            device.set(pin.as_usize(), value);
+        }
+    }
+    pub fn get_bit(&self, kind: Io, dio: Dio) -> Option<bool> {
+        match kind {
+            Io::Input => self.get_input_bit(dio.node, dio.pin),
+            Io::Output => self.get_output_bit(dio.node, dio.pin),
+        }
+    }
+    /// Like [`Self::set_output_bit`] but reports missing devices and pins instead of panicking
+    pub fn try_set_bit(&mut self, kind: Io, dio: Dio, value: bool) -> Result<()> {
+        self.pin_store(kind, dio)?.set(dio.pin.as_usize(), value);
+        Ok(())
+    }
+    /// Hold a pin at `value` until released with `None`, programs and
+    /// sensors can not change a forced pin
+    /// ```
+    /// # use cybercrab::io::{Dio, DioPin, IOStore, Io, IoDevices, NodeId};
+    /// let mut io = IoDevices::default();
+    /// io.digital_inputs.insert(NodeId(0), IOStore::new(8));
+    /// let dio = Dio { node: NodeId(0), pin: DioPin(3) };
+    /// io.force(Io::Input, dio, Some(true)).unwrap();
+    /// io.try_set_bit(Io::Input, dio, false).unwrap();
+    /// assert_eq!(io.get_bit(Io::Input, dio), Some(true));
+    /// io.force(Io::Input, dio, None).unwrap();
+    /// io.try_set_bit(Io::Input, dio, false).unwrap();
+    /// assert_eq!(io.get_bit(Io::Input, dio), Some(false));
+    /// ```
+    pub fn force(&mut self, kind: Io, dio: Dio, value: Option<bool>) -> Result<()> {
+        self.pin_store(kind, dio)?.force(dio.pin.as_usize(), value);
+        Ok(())
+    }
+    /// The forced value of a pin, `None` if it is not forced
+    pub fn forced(&self, kind: Io, dio: Dio) -> Option<bool> {
+        let store = self.store(kind, dio.node)?;
+        let idx = dio.pin.as_usize();
+        if !store.is_forced(idx) {
+            return None;
+        }
+        store.get(idx)
+    }
+    pub fn n_forced(&self) -> usize {
+        let stores = self
+            .digital_inputs
+            .values()
+            .chain(self.digital_outputs.values());
+        stores.map(IOStore::n_forced).sum()
+    }
+    pub fn release_forces(&mut self) {
+        let stores = self
+            .digital_inputs
+            .values_mut()
+            .chain(self.digital_outputs.values_mut());
+        for store in stores {
+            store.forced.fill(false);
+        }
+    }
+    fn store(&self, kind: Io, node: NodeId) -> Option<&IOStore> {
+        match kind {
+            Io::Input => self.digital_inputs.get(&node),
+            Io::Output => self.digital_outputs.get(&node),
+        }
+    }
+    /// The store holding `dio`, reports missing devices and pins
+    fn pin_store(&mut self, kind: Io, dio: Dio) -> Result<&mut IOStore> {
+        let stores = match kind {
+            Io::Input => &mut self.digital_inputs,
+            Io::Output => &mut self.digital_outputs,
+        };
+        let device = stores.get_mut(&dio.node).ok_or(format!(
+            "no device N{} for {}",
+            dio.node.0,
+            dio.address(kind)
+        ))?;
+        if dio.pin.as_usize() >= device.state.len() {
+            return Err(format!("device N{} has no {}", dio.node.0, dio.address(kind)).into());
+        }
+        Ok(device)
+    }
+    /// Read a bit, byte or word, bytes and words come back zero extended
+    pub fn read(&self, address: IoAddress) -> Option<u16> {
+        let store = self.store(address.kind, address.node)?;
+        let byte = |idx: u16| -> Option<u16> {
+            let start = idx as usize * 8;
+            let bits = store.state.get(start..start + 8)?;
+            Some(bits.iter().rev().fold(0, |acc, bit| acc << 1 | *bit as u16))
+        };
+        match address.width {
+            Width::Bit => store.get(address.dio()?.pin.as_usize()).map(u16::from),
+            Width::Byte => byte(address.byte),
+            Width::Word => Some(byte(address.byte)? << 8 | byte(address.byte.checked_add(1)?)?),
+        }
+    }
+    /// Write a bit, byte or word, reports addresses outside of the image
+    /// ```
+    /// # use cybercrab::io::{IOStore, IoDevices, NodeId};
+    /// let mut io = IoDevices::default();
+    /// io.digital_outputs.insert(NodeId(0), IOStore::new(32));
+    /// io.write("%QW0".parse().unwrap(), 0x1234).unwrap();
+    /// assert_eq!(io.read("%QB0".parse().unwrap()), Some(0x12));
+    /// assert_eq!(io.read("%Q1.2".parse().unwrap()), Some(1));
+    /// assert!(io.write("%QW3".parse().unwrap(), 0).is_err());
+    /// ```
+    pub fn write(&mut self, address: IoAddress, value: u16) -> Result<()> {
+        let stores = match address.kind {
+            Io::Input => &mut self.digital_inputs,
+            Io::Output => &mut self.digital_outputs,
+        };
+        let store = stores
+            .get_mut(&address.node)
+            .ok_or(format!("no device N{} for {address}", address.node.0))?;
+        let (start, bits, value) = match address.width {
+            Width::Bit => (
+                address.byte as usize * 8 + address.bit as usize,
+                1,
+                value & 1,
+            ),
+            Width::Byte => (address.byte as usize * 8, 8, value & 0xff),
+            // big endian, the high byte comes first
+            Width::Word => (address.byte as usize * 8, 16, value.rotate_left(8)),
+        };
+        if start + bits > store.state.len() {
+            return Err(format!("device N{} has no {address}", address.node.0).into());
+        }
+        for i in 0..bits {
+            store.set(start + i, value >> i & 1 == 1);
+        }
+        Ok(())
     }
 }
 
+#[derive(Resource, Default, Debug, Deref, DerefMut)]
+/// Pins as sensors and actuators see them, the fieldbus carries them to and
+/// from the [`IoDevices`] process image
+pub struct FieldIo(pub IoDevices);
+
 #[derive(Component, Reflect, Clone, Copy, Deref, DerefMut, Debug, PartialEq, Eq)]
 pub struct DioPin(pub u16);
 
@@ -139,7 +403,7 @@ pub struct SwitchSet {
 
 // pub struct DigitalSensor
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Io {
     Input,
     Output,
@@ -153,17 +417,183 @@ pub struct UIOveride {
     pub kind: Io,
 }
 
-#[derive(Bundle, Reflect, Clone, Copy, Debug)]
+#[derive(Bundle, Reflect, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Dio {
     pub node: NodeId,
     pub pin: DioPin,
 }
 
+impl Dio {
+    /// Address of this pin as a bit of the input or output image
+    pub fn address(self, kind: Io) -> IoAddress {
+        IoAddress {
+            kind,
+            node: self.node,
+            width: Width::Bit,
+            byte: self.pin.0 / 8,
+            bit: (self.pin.0 % 8) as u8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Width {
+    Bit,
+    Byte,
+    Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// PLC style address into the process image of one node
+///
+/// Parses IEC style `%I3.4`, `%IX3.4`, `%QB2`, `%IW64` and Siemens style
+/// `I 3.4`, `QW 12`, also with the German `E`/`A`. A `N<node>:` prefix picks
+/// the node, without it the address is on node 0. Words are big endian, like
+/// on a Siemens PLC `%IW64` is made of `%IB64` and `%IB65`.
+/// ```
+/// # use cybercrab::io::{Io, IoAddress, Width};
+/// let address: IoAddress = "N2:%Q12.3".parse().unwrap();
+/// assert_eq!(address.kind, Io::Output);
+/// assert_eq!(address.node.0, 2);
+/// assert_eq!(address.dio().unwrap().pin.0, 12 * 8 + 3);
+/// assert_eq!(address.to_string(), "N2:%Q12.3");
+/// let word: IoAddress = "IW 64".parse().unwrap();
+/// assert_eq!((word.width, word.byte), (Width::Word, 64));
+/// assert_eq!(word.to_string(), "%IW64");
+/// assert!("%I3.8".parse::<IoAddress>().is_err());
+/// assert!("%IW3.1".parse::<IoAddress>().is_err());
+/// ```
+pub struct IoAddress {
+    pub kind: Io,
+    pub node: NodeId,
+    pub width: Width,
+    pub byte: u16,
+    /// bit in the byte, 0 unless `width` is [`Width::Bit`]
+    pub bit: u8,
+}
+
+impl IoAddress {
+    pub fn parse(text: &str) -> Result<Self> {
+        let bad = |why: &str| -> BevyError { format!("bad io address '{text}': {why}").into() };
+        let mut rest = text.trim();
+        let mut node = NodeId(0);
+        if let Some((prefix, tail)) = rest.split_once(':') {
+            let number = prefix
+                .strip_prefix(['N', 'n'])
+                .ok_or_else(|| bad("node prefix is written N<node>:"))?;
+            node = NodeId(number.parse().map_err(|_| bad("node is not a number"))?);
+            rest = tail.trim_start();
+        }
+        let iec = rest.starts_with('%');
+        rest = rest.trim_start_matches('%');
+        let mut chars = rest.chars();
+        let kind = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('I') => Io::Input,
+            Some('Q') => Io::Output,
+            Some('E') if !iec => Io::Input,
+            Some('A') if !iec => Io::Output,
+            _ => return Err(bad("expected I or Q")),
+        };
+        rest = chars.as_str();
+        let width = match rest.chars().next().map(|c| c.to_ascii_uppercase()) {
+            Some('X') => Some(Width::Bit),
+            Some('B') => Some(Width::Byte),
+            Some('W') => Some(Width::Word),
+            Some(c) if c.is_ascii_alphabetic() => return Err(bad("size must be X, B or W")),
+            _ => None,
+        };
+        if width.is_some() {
+            rest = &rest[1..];
+        }
+        let width = width.unwrap_or(Width::Bit);
+        let numbers = rest.trim();
+        let (byte, bit) = match (width, numbers.split_once('.')) {
+            (Width::Bit, Some((byte, bit))) => (byte, bit),
+            (Width::Bit, None) => return Err(bad("bit addresses are written byte.bit")),
+            (_, Some(_)) => return Err(bad("byte and word addresses have no bit")),
+            (_, None) => (numbers, "0"),
+        };
+        let byte: u16 = byte.parse().map_err(|_| bad("byte is not a number"))?;
+        let bit: u8 = bit.parse().map_err(|_| bad("bit is not a number"))?;
+        if bit > 7 {
+            return Err(bad("bit must be 0 to 7"));
+        }
+        if width == Width::Bit && byte > u16::MAX / 8 {
+            return Err(bad("byte is out of range"));
+        }
+        Ok(Self {
+            kind,
+            node,
+            width,
+            byte,
+            bit,
+        })
+    }
+
+    /// The pin of a bit address
+    pub fn dio(self) -> Option<Dio> {
+        (self.width == Width::Bit).then(|| Dio {
+            node: self.node,
+            pin: DioPin(self.byte * 8 + self.bit as u16),
+        })
+    }
+
+    /// Every pin of a bit, byte or word, pins past the last one are left out
+    /// ```
+    /// # use cybercrab::io::IoAddress;
+    /// let word: IoAddress = "N1:%IW2".parse().unwrap();
+    /// let pins: Vec<_> = word.pins().map(|dio| dio.pin.0).collect();
+    /// assert_eq!(pins, (16..32).collect::<Vec<_>>());
+    /// assert_eq!("%I0.3".parse::<IoAddress>().unwrap().pins().count(), 1);
+    /// ```
+    pub fn pins(self) -> impl Iterator<Item = Dio> {
+        let (start, bits) = match self.width {
+            Width::Bit => (self.byte as u32 * 8 + self.bit as u32, 1),
+            Width::Byte => (self.byte as u32 * 8, 8),
+            Width::Word => (self.byte as u32 * 8, 16),
+        };
+        let node = self.node;
+        (start..start + bits).map_while(move |pin| {
+            let pin = DioPin(u16::try_from(pin).ok()?);
+            Some(Dio { node, pin })
+        })
+    }
+}
+
+impl FromStr for IoAddress {
+    type Err = BevyError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        Self::parse(text)
+    }
+}
+
+impl fmt::Display for IoAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.node.0 != 0 {
+            write!(f, "N{}:", self.node.0)?;
+        }
+        let kind = match self.kind {
+            Io::Input => "I",
+            Io::Output => "Q",
+        };
+        match self.width {
+            Width::Bit => write!(f, "%{kind}{}.{}", self.byte, self.bit),
+            Width::Byte => write!(f, "%{kind}B{}", self.byte),
+            Width::Word => write!(f, "%{kind}W{}", self.byte),
+        }
+    }
+}
+
 fn on_ui_overide(
     trigger: On<UIOveride>,
     q: Query<(Entity, &NodeId, &DioPin), With<Switch>>,
     mut cmd: Commands,
 ) {
+    // outputs are written to the process image directly, only sensors are switches
+    if trigger.kind != Io::Input {
+        return;
+    }
     let target_address = trigger.address;
     let target_pin = trigger.pin;
     let switches = q.iter().filter_map(|(id, address, pin)| -> Option<Entity> {
@@ -185,7 +615,7 @@ fn on_ui_overide(
 pub fn on_bit_set(
     trigger: On<SwitchSet>,
     q: Query<(&NodeId, &DioPin), With<Switch>>,
-    mut io: ResMut<IoDevices>,
+    mut io: ResMut<FieldIo>,
 ) {
     let switch_id = trigger.entity;
     let Ok((address, pin)) = q.get(switch_id) else {
@@ -0,0 +1,283 @@
+//! Declared IO modules, pin allocation and the IO list report
+//!
+//! Every pin a station uses has to sit on a declared module and may only be
+//! wired to one signal. [`check`] finds the places where that does not hold,
+//! [`io_list_csv`] writes the list electrical engineering wires from.
+
+use std::fmt;
+
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::{
+    fieldbus::BusConfig,
+    io::{Dio, DioPin, InputFilter, Io, IoAddress, NodeId},
+    plc::{Tag, TagTable},
+};
+
+/// IO list written by the layout editor
+pub const IO_LIST_FILE: &str = "io_list.csv";
+/// Most pins of one kind a module can have, the last one is `%I8191.7`
+pub const MAX_MODULE_PINS: usize = u16::MAX as usize + 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Remote IO module on a fieldbus node
+pub struct IoModule {
+    pub node: NodeId,
+    pub name: String,
+    pub inputs: usize,
+    pub outputs: usize,
+    pub bus: BusConfig,
+    /// input filter of pins without one of their own
+    pub filter: InputFilter,
+    pub pin_filters: Vec<(DioPin, InputFilter)>,
+}
+
+impl IoModule {
+    pub fn size(&self, kind: Io) -> usize {
+        match kind {
+            Io::Input => self.inputs,
+            Io::Output => self.outputs,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A pin wired to one signal of a station
+pub struct IoUse {
+    pub address: IoAddress,
+    pub owner: String,
+    pub signal: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IoConflict {
+    /// one pin wired to two signals
+    Overlap {
+        address: IoAddress,
+        first: String,
+        second: String,
+    },
+    /// pin on a node without a module
+    MissingDevice { address: IoAddress, owner: String },
+    /// pin past the last pin of its module
+    OutOfRange {
+        address: IoAddress,
+        owner: String,
+        size: usize,
+    },
+    /// two modules declared on one node
+    DuplicateModule { node: NodeId },
+}
+
+impl fmt::Display for IoConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overlap {
+                address,
+                first,
+                second,
+            } => write!(f, "{address} is used by {first} and {second}"),
+            Self::MissingDevice { address, owner } => {
+                write!(f, "{address} of {owner}: no module on N{}", address.node.0)
+            }
+            Self::OutOfRange {
+                address,
+                owner,
+                size,
+            } => write!(
+                f,
+                "{address} of {owner}: N{} only has {size} {:?}s",
+                address.node.0, address.kind
+            ),
+            Self::DuplicateModule { node } => write!(f, "N{} is declared twice", node.0),
+        }
+    }
+}
+
+pub fn find_module(modules: &[IoModule], node: NodeId) -> Option<&IoModule> {
+    modules.iter().find(|module| module.node == node)
+}
+
+/// Everything wrong with the wiring, in address order
+/// ```
+/// # use cybercrab::io::NodeId;
+/// # use cybercrab::ioalloc::{check, IoConflict, IoModule, IoUse};
+/// let modules = [IoModule {
+///     node: NodeId(0),
+///     name: "rack".into(),
+///     inputs: 8,
+///     outputs: 8,
+///     bus: Default::default(),
+///     filter: Default::default(),
+///     pin_filters: Vec::new(),
+/// }];
+/// let wire = |address: &str, owner: &str| IoUse {
+///     address: address.parse().unwrap(),
+///     owner: owner.into(),
+///     signal: "sensor",
+/// };
+/// let uses = [
+///     wire("%I0.1", "stn 0"),
+///     wire("%I0.1", "stn 1"),
+///     wire("%I1.0", "stn 2"),
+///     wire("N1:%I0.0", "stn 3"),
+/// ];
+/// let conflicts = check(&modules, &uses);
+/// assert_eq!(conflicts.len(), 3);
+/// assert!(matches!(conflicts[0], IoConflict::Overlap { .. }));
+/// assert!(matches!(conflicts[1], IoConflict::OutOfRange { size: 8, .. }));
+/// assert!(matches!(conflicts[2], IoConflict::MissingDevice { .. }));
+/// ```
+pub fn check(modules: &[IoModule], uses: &[IoUse]) -> Vec<IoConflict> {
+    let mut conflicts = Vec::new();
+    for (i, module) in modules.iter().enumerate() {
+        if modules[..i].iter().any(|other| other.node == module.node) {
+            conflicts.push(IoConflict::DuplicateModule { node: module.node });
+        }
+    }
+    let mut sorted: Vec<_> = uses.iter().collect();
+    sorted.sort_by_key(|wire| sort_key(wire.address));
+    let mut owners: HashMap<IoAddress, &str> = HashMap::new();
+    for wire in sorted {
+        let address = wire.address;
+        let owner = wire.owner.clone();
+        let Some(module) = find_module(modules, address.node) else {
+            conflicts.push(IoConflict::MissingDevice { address, owner });
+            continue;
+        };
+        let size = module.size(address.kind);
+        let in_range = address.dio().is_some_and(|dio| dio.pin.as_usize() < size);
+        if !in_range {
+            conflicts.push(IoConflict::OutOfRange {
+                address,
+                owner,
+                size,
+            });
+            continue;
+        }
+        if let Some(first) = owners.insert(address, &wire.owner) {
+            conflicts.push(IoConflict::Overlap {
+                address,
+                first: first.into(),
+                second: owner,
+            });
+        }
+    }
+    conflicts
+}
+
+fn sort_key(address: IoAddress) -> (u32, bool, u16, u8) {
+    let output = address.kind == Io::Output;
+    (address.node.0, output, address.byte, address.bit)
+}
+
+/// The `n` lowest pins of the module on `node` that nothing uses
+pub fn allocate(
+    modules: &[IoModule],
+    uses: &[IoUse],
+    node: NodeId,
+    kind: Io,
+    n: usize,
+) -> Result<Vec<Dio>> {
+    let module =
+        find_module(modules, node).ok_or(format!("no io module declared on N{}", node.0))?;
+    let mut used = vec![false; module.size(kind)];
+    let pins = uses
+        .iter()
+        .filter(|wire| wire.address.kind == kind && wire.address.node == node)
+        .filter_map(|wire| wire.address.dio());
+    for dio in pins {
+        if let Some(used) = used.get_mut(dio.pin.as_usize()) {
+            *used = true;
+        }
+    }
+    let dios: Vec<_> = (0..used.len())
+        .filter(|pin| !used[*pin])
+        .take(n)
+        .map(|pin| Dio {
+            node,
+            pin: DioPin(pin as u16),
+        })
+        .collect();
+    let n_free = dios.len();
+    if n_free < n {
+        return Err(format!(
+            "{} has {n_free} free {kind:?}s, {n} are needed",
+            module.name
+        )
+        .into());
+    }
+    Ok(dios)
+}
+
+/// One row per pin of every module, spare pins included, and the pins in
+/// use that are on no module at the end
+pub fn io_list_csv(modules: &[IoModule], uses: &[IoUse], tags: &TagTable) -> String {
+    let wired: HashMap<IoAddress, &IoUse> = uses.iter().map(|wire| (wire.address, wire)).collect();
+    let tag_names: HashMap<IoAddress, &str> = tags
+        .iter()
+        .filter_map(|(name, tag)| match tag {
+            Tag::Input(dio) => Some((dio.address(Io::Input), name.as_str())),
+            Tag::Output(dio) => Some((dio.address(Io::Output), name.as_str())),
+            Tag::Memory(_) => None,
+        })
+        .collect();
+    let row = |address: IoAddress, module: &str| -> String {
+        let (station, signal) = match wired.get(&address) {
+            Some(wire) => (wire.owner.as_str(), wire.signal),
+            None => ("", "spare"),
+        };
+        let tag = tag_names.get(&address).copied().unwrap_or_default();
+        let pin = address.byte as usize * 8 + address.bit as usize;
+        let fields = [
+            address.to_string(),
+            module.into(),
+            address.node.0.to_string(),
+            pin.to_string(),
+            station.into(),
+            signal.into(),
+            tag.into(),
+        ];
+        fields.map(|field| csv_field(&field)).join(",") + "\n"
+    };
+
+    let mut csv = String::from("address,module,node,pin,station,signal,tag\n");
+    let mut sorted: Vec<_> = modules.iter().collect();
+    sorted.sort_by_key(|module| module.node.0);
+    for module in sorted {
+        for kind in [Io::Input, Io::Output] {
+            for pin in 0..module.size(kind).min(MAX_MODULE_PINS) {
+                let dio = Dio {
+                    node: module.node,
+                    pin: DioPin(pin as u16),
+                };
+                csv += &row(dio.address(kind), &module.name);
+            }
+        }
+    }
+    let mut unmounted: Vec<_> = uses
+        .iter()
+        .filter(|wire| {
+            let size = find_module(modules, wire.address.node)
+                .map_or(0, |module| module.size(wire.address.kind));
+            wire.address
+                .dio()
+                .is_none_or(|dio| dio.pin.as_usize() >= size)
+        })
+        .map(|wire| wire.address)
+        .collect();
+    unmounted.sort_by_key(|address| sort_key(*address));
+    for address in unmounted {
+        csv += &row(address, "");
+    }
+    csv
+}
+
+/// Quote fields holding separators, quotes or line breaks
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
@@ -0,0 +1,1471 @@
+use std::{
+    f32::consts::{FRAC_PI_2, PI},
+    fs,
+    ops::Range,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use avian3d::prelude::*;
+use bevy::{
+    color::palettes::css,
+    platform::collections::HashMap,
+    prelude::*,
+    window::{CursorOptions, PrimaryWindow},
+};
+use bevy_inspector_egui::bevy_egui::EguiContexts;
+
+use crate::{
+    accumulation::{InsertAccumulation, MAX_ZONES, ZONE_MOTOR_SIGNALS, ZONE_SENSOR_NAMES},
+    analog::{LaserDistanceBundle, LoadCellBundle},
+    encoder::{EncoderMount, EncoderOutput},
+    fieldbus::{BusConfig, OfflineInputs},
+    io::{Dio, DioPin, IOStore, InputFilter, Io, IoAddress, IoDevices, NodeId, Width},
+    ioalloc::{self, IoConflict, IoModule, IoUse, MAX_MODULE_PINS},
+    plc::TagTable,
+    proximity::{Contact, LimitSwitchBundle, ProximityBundle, ProximityKind},
+    reload::{modified, reload_due, LoadErrors},
+    segment::ConveyorPath,
+    shiftreg::{Register, RegisterPosition, RegisterZones},
+    sysorder::SimMode,
+    tbana::{
+        Direction, InsertTbana4x2, PullFrom, PushTo, FOTOCELL_NAMES, MOTOR_SIGNALS, STATION_LENGTH,
+        WHEEL_HEIGHT, WHEEL_RADIUS,
+    },
+};
+
+/// Layout loaded at startup and written by the editor
+pub const LAYOUT_FILE: &str = "line.layout";
+
+const N_INPUTS: usize = 4;
+const N_OUTPUTS: usize = 2 * 3;
+/// Height details rest at, on top of the wheels
+const BED_TOP: f32 = WHEEL_HEIGHT + WHEEL_RADIUS;
+/// Size of the box that presses a limit switch
+const LEVER: Vec3 = Vec3::splat(0.05);
+/// Height of a laser above the bed, halfway up a detail
+const LASER_HEIGHT: f32 = 0.15;
+/// Half the platform of a load cell, a pallet fits on it
+const LOAD_CELL: Vec2 = Vec2::new(0.3, 0.8);
+/// Smallest step a laser tells apart, in meters
+const LASER_RESOLUTION: f32 = 0.001;
+/// Smallest step a load cell tells apart, in kilograms
+const LOAD_CELL_RESOLUTION: f32 = 0.1;
+
+pub struct LayoutPlugin;
+
+impl Plugin for LayoutPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Layout>();
+        app.init_resource::<LayoutEditor>();
+        app.init_gizmo_group::<LayoutGizmos>();
+        app.add_systems(OnEnter(SimMode::Editing), pause_physics);
+        app.add_systems(OnExit(SimMode::Editing), resume_physics);
+        app.add_systems(
+            Update,
+            (edit_layout_on_click, render_layout).run_if(in_state(SimMode::Editing)),
+        );
+        // edits in the editor win over the file until they are saved
+        app.add_systems(
+            Update,
+            watch_layout
+                .run_if(reload_due)
+                .run_if(in_state(SimMode::Running)),
+        );
+        app.add_observer(on_apply_layout);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+/// What a station is built as
+pub enum StationKind {
+    /// One detail at a time, four fotocells and two motors
+    #[default]
+    Transfer,
+    /// Accumulation conveyor with this many zones, a fotocell and a motor each
+    Accumulation(u8),
+    /// Transfer station along a curved or inclined [`ConveyorPath`]
+    Segment(ConveyorPath),
+}
+
+impl StationKind {
+    /// Register positions and grid cells the station takes
+    pub fn zones(&self) -> usize {
+        match self {
+            StationKind::Transfer | StationKind::Segment(_) => 1,
+            StationKind::Accumulation(zones) => *zones as usize,
+        }
+    }
+
+    /// Signals on the inputs or outputs of the station, in wiring order
+    pub fn signals(&self, kind: Io) -> &'static [&'static str] {
+        match (self, kind) {
+            (StationKind::Transfer | StationKind::Segment(_), Io::Input) => &FOTOCELL_NAMES,
+            (StationKind::Transfer | StationKind::Segment(_), Io::Output) => &MOTOR_SIGNALS,
+            (StationKind::Accumulation(zones), Io::Input) => &ZONE_SENSOR_NAMES[..*zones as usize],
+            (StationKind::Accumulation(zones), Io::Output) => {
+                &ZONE_MOTOR_SIGNALS[..3 * *zones as usize]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Placement, links and IO of one station
+pub struct StationLayout {
+    pub name: String,
+    pub cell: IVec2,
+    /// rotation around y in steps of 90 degrees
+    pub quarter_turns: u8,
+    /// index of the station details are pushed to
+    pub push_to: Option<usize>,
+    pub kind: StationKind,
+    /// one pin per signal of [`StationKind::signals`]
+    pub inputs: Vec<Dio>,
+    pub outputs: Vec<Dio>,
+}
+
+impl StationLayout {
+    pub fn transform(&self, cell_size: f32) -> Transform {
+        let translation = Vec3::new(
+            self.cell.x as f32 * cell_size,
+            0.0,
+            self.cell.y as f32 * cell_size,
+        );
+        Transform::from_translation(translation)
+            .with_rotation(Quat::from_rotation_y(self.quarter_turns as f32 * FRAC_PI_2))
+    }
+
+    /// Cells the station covers, its own and one more per zone in its direction
+    pub fn cells(&self) -> impl Iterator<Item = IVec2> {
+        let step = match self.quarter_turns % 4 {
+            0 => IVec2::Y,
+            1 => IVec2::X,
+            2 => IVec2::NEG_Y,
+            _ => IVec2::NEG_X,
+        };
+        let cell = self.cell;
+        (0..self.kind.zones() as i32).map(move |i| cell + step * i)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What a device mounted on a station is
+pub enum DeviceKind {
+    /// Proximity sensor under the bed, looking up at what passes over it
+    Proximity(ProximityKind),
+    /// Limit switch with its lever reaching up between the wheels
+    LimitSwitch(Contact),
+    /// Encoder on the wheels of one motor of the station
+    Encoder { motor: u8, pulses_per_rev: u32 },
+    /// Laser above the bed looking back to the infeed, `range` meters
+    LaserDistance { range: f32 },
+    /// Load cell under the bed weighing up to `capacity` kilograms
+    LoadCell { capacity: f32 },
+}
+
+#[derive(Debug, Clone)]
+/// Sensor on a station besides the fotocells it comes with
+pub struct DeviceLayout {
+    pub name: String,
+    /// index of the station it is mounted on
+    pub station: usize,
+    /// meters along the station from its infeed
+    pub at: f32,
+    pub kind: DeviceKind,
+    /// input bits, or the input word of an analog sensor or of an encoder
+    /// on a counter module
+    pub inputs: Vec<IoAddress>,
+}
+
+impl DeviceLayout {
+    /// Signals on the inputs, in wiring order
+    pub fn signals(&self) -> &'static [&'static str] {
+        match (self.kind, self.inputs.as_slice()) {
+            (DeviceKind::Proximity(_), _) => &["proximity"],
+            (DeviceKind::LimitSwitch(_), _) => &["limit switch"],
+            (DeviceKind::Encoder { .. }, [_]) => &["encoder count"],
+            (DeviceKind::Encoder { .. }, _) => &["encoder A", "encoder B"],
+            (DeviceKind::LaserDistance { .. }, _) => &["distance"],
+            (DeviceKind::LoadCell { .. }, _) => &["weight"],
+        }
+    }
+
+    /// Encoder on the motor with this forward output, wired like the device
+    fn encoder_mount(&self, forward: Dio) -> Option<EncoderMount> {
+        let DeviceKind::Encoder { pulses_per_rev, .. } = self.kind else {
+            return None;
+        };
+        let output = match self.inputs[..] {
+            [word] if word.width == Width::Word => EncoderOutput::Counter(word),
+            [a, b] => EncoderOutput::Quadrature {
+                a: a.dio()?,
+                b: b.dio()?,
+            },
+            _ => return None,
+        };
+        Some(EncoderMount {
+            forward,
+            pulses_per_rev,
+            output,
+        })
+    }
+
+    fn keyword(&self) -> &'static str {
+        match self.kind {
+            DeviceKind::Proximity(_) => "proximity",
+            DeviceKind::LimitSwitch(_) => "limit",
+            DeviceKind::Encoder { .. } => "encoder",
+            DeviceKind::LaserDistance { .. } => "laser",
+            DeviceKind::LoadCell { .. } => "loadcell",
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+/// Stations on a grid, the source of truth that the world is rebuilt from
+pub struct Layout {
+    pub cell_size: f32,
+    /// IO modules the stations are wired to
+    pub modules: Vec<IoModule>,
+    pub stations: Vec<StationLayout>,
+    pub devices: Vec<DeviceLayout>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            cell_size: 2.1,
+            modules: Vec::new(),
+            stations: Vec::new(),
+            devices: Vec::new(),
+        }
+    }
+}
+
+/// Modules come in cards of this many pins
+const CARD_PINS: usize = 32;
+
+impl Layout {
+    /// Straight line of stations along z, each pushing to the next, wired
+    /// to one module on `node` with room for all of them
+    pub fn line(n_stations: usize, node: NodeId) -> Result<Self> {
+        let mut layout = Self::default();
+        let cards = |pins: usize| pins.div_ceil(CARD_PINS) * CARD_PINS;
+        layout.modules.push(IoModule {
+            node,
+            name: format!("N{}", node.0),
+            inputs: cards(n_stations * N_INPUTS),
+            outputs: cards(n_stations * N_OUTPUTS),
+            bus: default(),
+            filter: default(),
+            pin_filters: Vec::new(),
+        });
+        for i in 0..n_stations {
+            let idx = layout.add_station(IVec2::new(0, i as i32), node, StationKind::Transfer)?;
+            if idx > 0 {
+                layout.link(idx - 1, idx)?;
+            }
+        }
+        Ok(layout)
+    }
+
+    pub fn station_at(&self, cell: IVec2) -> Option<usize> {
+        self.footprints()
+            .iter()
+            .position(|cells| cells.contains(&cell))
+    }
+
+    /// Cells each station covers, a segment also covers the cells its path
+    /// runs over where it snapped to
+    pub fn footprints(&self) -> Vec<Vec<IVec2>> {
+        let transforms = self.transforms();
+        let stations = self.stations.iter().zip(transforms);
+        stations
+            .map(|(station, transform)| {
+                let mut cells: Vec<_> = station.cells().collect();
+                if let StationKind::Segment(path) = station.kind {
+                    let n = (4.0 * path.length() / self.cell_size).ceil().max(1.0) as usize;
+                    for i in 0..n {
+                        let pose = path.pose((i as f32 + 0.5) * path.length() / n as f32);
+                        let cell = self.cell_of(transform.transform_point(pose.translation));
+                        if !cells.contains(&cell) {
+                            cells.push(cell);
+                        }
+                    }
+                }
+                cells
+            })
+            .collect()
+    }
+
+    /// Refuse stations on cells of another
+    fn check_cells(&self) -> Result<()> {
+        let footprints = self.footprints();
+        for (idx, cells) in footprints.iter().enumerate() {
+            for cell in cells {
+                let Some(other) = footprints[..idx]
+                    .iter()
+                    .position(|other| other.contains(cell))
+                else {
+                    continue;
+                };
+                let (station, other) = (&self.stations[idx].name, &self.stations[other].name);
+                return Err(format!("{station}: cell {cell} is taken by {other}").into());
+            }
+        }
+        Ok(())
+    }
+
+    /// First register position of each station, a station takes one per zone
+    pub fn register_positions(&self) -> Vec<usize> {
+        let zones = self.stations.iter().map(|station| station.kind.zones());
+        zones
+            .scan(0, |next, zones| {
+                let pos = *next;
+                *next += zones;
+                Some(pos)
+            })
+            .collect()
+    }
+
+    /// Where each station is built, a segment snaps its infeed onto the
+    /// outfeed of the station pushing into it and only sits on its own cell
+    /// without one
+    pub fn transforms(&self) -> Vec<Transform> {
+        let mut placed = vec![None; self.stations.len()];
+        for idx in 0..self.stations.len() {
+            self.place(idx, &mut placed, 0);
+        }
+        placed.into_iter().map(Option::unwrap_or_default).collect()
+    }
+
+    fn place(&self, idx: usize, placed: &mut [Option<Transform>], depth: usize) -> Transform {
+        if let Some(transform) = placed[idx] {
+            return transform;
+        }
+        let station = &self.stations[idx];
+        let on_grid = station.transform(self.cell_size);
+        let feeder = self
+            .stations
+            .iter()
+            .position(|other| other.push_to == Some(idx));
+        let transform = match (station.kind, feeder) {
+            // a ring of segments has nothing to start from, one of them stays on its cell
+            (StationKind::Segment(_), Some(from)) if depth < self.stations.len() => {
+                let from = self.place(from, placed, depth + 1) * self.outfeed(from);
+                placed[idx].unwrap_or(from)
+            }
+            (StationKind::Segment(_), _) => {
+                on_grid * Transform::from_xyz(0.0, 0.0, -self.cell_size / 2.0)
+            }
+            _ => on_grid,
+        };
+        placed[idx] = Some(transform);
+        transform
+    }
+
+    /// Where details leave a station, relative to its transform
+    fn outfeed(&self, idx: usize) -> Transform {
+        let zones = self.stations[idx].kind.zones() as f32;
+        match self.stations[idx].kind {
+            StationKind::Segment(path) => path.end(),
+            _ => Transform::from_xyz(0.0, 0.0, (zones - 0.5) * self.cell_size),
+        }
+    }
+
+    /// Input words of the devices, they are read whole
+    pub fn input_words(&self) -> impl Iterator<Item = IoAddress> + '_ {
+        let inputs = self.devices.iter().flat_map(|device| device.inputs.iter());
+        inputs
+            .copied()
+            .filter(|address| address.width == Width::Word)
+    }
+
+    /// Where a device `at` meters along station `idx` from its infeed sits,
+    /// on the center line at the top of the bed, relative to the station
+    fn mount(&self, idx: usize, at: f32) -> Transform {
+        let bed_top = Transform::from_xyz(0.0, BED_TOP, 0.0);
+        match self.stations[idx].kind {
+            StationKind::Segment(path) => path.pose(at) * bed_top,
+            StationKind::Transfer => {
+                bed_top * Transform::from_xyz(0.0, 0.0, at - STATION_LENGTH / 2.0)
+            }
+            StationKind::Accumulation(_) => {
+                bed_top * Transform::from_xyz(0.0, 0.0, at - self.cell_size / 2.0)
+            }
+        }
+    }
+
+    /// Register slots to move from the positions stations had, by name, to
+    /// their positions in this layout
+    ///
+    /// Zones are matched from the outfeed back, a station that lost zones
+    /// drops the details nearest its infeed. Names that are not unique are
+    /// not followed.
+    pub fn register_moves(&self, before: &[(&str, Range<usize>)]) -> Vec<(usize, usize)> {
+        let positions = self.register_positions();
+        let unique = |name: &str| {
+            let now = self.stations.iter().filter(|station| station.name == name);
+            let then = before.iter().filter(|(other, _)| *other == name);
+            now.count() == 1 && then.count() == 1
+        };
+        let mut moves = Vec::new();
+        for (idx, station) in self.stations.iter().enumerate() {
+            let old = before.iter().find(|(name, _)| *name == station.name);
+            let Some((_, old)) = old.filter(|(name, _)| unique(name)) else {
+                continue;
+            };
+            let new = positions[idx]..positions[idx] + station.kind.zones();
+            moves.extend(old.clone().rev().zip(new.rev()));
+        }
+        moves
+    }
+
+    pub fn cell_of(&self, position: Vec3) -> IVec2 {
+        (position.xz() / self.cell_size).round().as_ivec2()
+    }
+
+    /// Place a new station and allocate its IO from the free pins of the
+    /// module on `node`
+    pub fn add_station(&mut self, cell: IVec2, node: NodeId, kind: StationKind) -> Result<usize> {
+        // names are how the register follows a station through edits
+        let name = (self.stations.len()..)
+            .map(|n| format!("stn {n}"))
+            .find(|name| self.stations.iter().all(|station| station.name != *name))
+            .unwrap_or_default();
+        let mut station = StationLayout {
+            name,
+            cell,
+            quarter_turns: 0,
+            push_to: None,
+            kind,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        };
+        let uses = self.io_uses();
+        let allocate =
+            |io: Io| ioalloc::allocate(&self.modules, &uses, node, io, kind.signals(io).len());
+        let allocated = allocate(Io::Input).and_then(|inputs| Ok((inputs, allocate(Io::Output)?)));
+        let (inputs, outputs) = allocated.map_err(|err| format!("{}: {err}", station.name))?;
+        station.inputs = inputs;
+        station.outputs = outputs;
+        self.stations.push(station);
+        if let Err(err) = self.check_cells() {
+            self.stations.pop();
+            return Err(err);
+        }
+        Ok(self.stations.len() - 1)
+    }
+
+    /// Remove a station and the devices mounted on it
+    pub fn remove_station(&mut self, idx: usize) {
+        self.stations.remove(idx);
+        self.devices.retain(|device| device.station != idx);
+        for device in self.devices.iter_mut() {
+            if device.station > idx {
+                device.station -= 1;
+            }
+        }
+        for station in self.stations.iter_mut() {
+            station.push_to = match station.push_to {
+                Some(to) if to == idx => None,
+                Some(to) if to > idx => Some(to - 1),
+                other => other,
+            };
+        }
+    }
+
+    /// Link `from` to push into `to`, unless a segment snapping onto `from`
+    /// would land on another station
+    pub fn link(&mut self, from: usize, to: usize) -> Result<()> {
+        if from == to || to >= self.stations.len() {
+            return Ok(());
+        }
+        let Some(station) = self.stations.get_mut(from) else {
+            return Ok(());
+        };
+        let before = station.push_to.replace(to);
+        if let Err(err) = self.check_cells() {
+            self.stations[from].push_to = before;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Turn a station a quarter, unless it or the segments snapped onto it
+    /// would land on another station
+    pub fn rotate(&mut self, idx: usize) -> Result<()> {
+        let Some(station) = self.stations.get_mut(idx) else {
+            return Ok(());
+        };
+        let before = station.quarter_turns;
+        station.quarter_turns = (before + 1) % 4;
+        if let Err(err) = self.check_cells() {
+            self.stations[idx].quarter_turns = before;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Every pin the stations are wired to, with the signal on it
+    pub fn io_uses(&self) -> Vec<IoUse> {
+        let mut uses = Vec::new();
+        for station in self.stations.iter() {
+            let inputs = station
+                .inputs
+                .iter()
+                .zip(station.kind.signals(Io::Input))
+                .map(|(dio, signal)| (dio.address(Io::Input), *signal));
+            let outputs = station
+                .outputs
+                .iter()
+                .zip(station.kind.signals(Io::Output))
+                .map(|(dio, signal)| (dio.address(Io::Output), *signal));
+            uses.extend(inputs.chain(outputs).map(|(address, signal)| IoUse {
+                address,
+                owner: station.name.clone(),
+                signal,
+            }));
+        }
+        for device in self.devices.iter() {
+            for (address, signal) in device.inputs.iter().zip(device.signals()) {
+                uses.extend(address.pins().map(|dio| IoUse {
+                    address: dio.address(address.kind),
+                    owner: device.name.clone(),
+                    signal,
+                }));
+            }
+        }
+        uses
+    }
+
+    pub fn io_conflicts(&self) -> Vec<IoConflict> {
+        ioalloc::check(&self.modules, &self.io_uses())
+    }
+
+    /// CSV list of all module pins, see [`ioalloc::io_list_csv`]
+    pub fn io_list_csv(&self, tags: &TagTable) -> String {
+        ioalloc::io_list_csv(&self.modules, &self.io_uses(), tags)
+    }
+
+    /// Pins of each declared module
+    pub fn io_size(&self, kind: Io) -> HashMap<NodeId, usize> {
+        self.modules
+            .iter()
+            .map(|module| (module.node, module.size(kind)))
+            .collect()
+    }
+
+    /// Declare modules for layouts written before modules were, one per node
+    /// just big enough for the pins in use
+    fn infer_modules(&mut self) {
+        let mut sizes: HashMap<NodeId, (usize, usize)> = HashMap::new();
+        for wire in self.io_uses() {
+            let Some(dio) = wire.address.dio() else {
+                continue;
+            };
+            let (inputs, outputs) = sizes.entry(dio.node).or_default();
+            let size = match wire.address.kind {
+                Io::Input => inputs,
+                Io::Output => outputs,
+            };
+            *size = (*size).max(dio.pin.as_usize() + 1);
+        }
+        let mut nodes: Vec<_> = sizes.into_iter().collect();
+        nodes.sort_by_key(|(node, _)| node.0);
+        self.modules = nodes
+            .into_iter()
+            .map(|(node, (inputs, outputs))| IoModule {
+                node,
+                name: format!("N{}", node.0),
+                inputs: inputs.next_multiple_of(8),
+                outputs: outputs.next_multiple_of(8),
+                bus: default(),
+                filter: default(),
+                pin_filters: Vec::new(),
+            })
+            .collect();
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# cybercrab layout\n");
+        text += &format!("cell_size {}\n", self.cell_size);
+        text += "# module <node> <inputs> <outputs> <name>\n";
+        for module in self.modules.iter() {
+            text += &format!(
+                "module N{} {} {} {}\n",
+                module.node.0, module.inputs, module.outputs, module.name
+            );
+        }
+        text += "# bus <node> <cycle ms> <delay ms> <freeze|zero offline inputs>\n";
+        for module in self
+            .modules
+            .iter()
+            .filter(|module| module.bus != BusConfig::default())
+        {
+            let bus = module.bus;
+            let offline = match bus.offline_inputs {
+                OfflineInputs::Freeze => "freeze",
+                OfflineInputs::Zero => "zero",
+            };
+            text += &format!(
+                "bus N{} {} {} {offline}\n",
+                module.node.0,
+                bus.cycle.as_secs_f64() * 1000.0,
+                bus.delay.as_secs_f64() * 1000.0,
+            );
+        }
+        text += "# filter <node|input> <on delay ms> <off delay ms>\n";
+        let ms = |delay: Duration| delay.as_secs_f64() * 1000.0;
+        for module in self.modules.iter() {
+            if module.filter != InputFilter::default() {
+                let filter = module.filter;
+                text += &format!(
+                    "filter N{} {} {}\n",
+                    module.node.0,
+                    ms(filter.on_delay),
+                    ms(filter.off_delay)
+                );
+            }
+            for (pin, filter) in module.pin_filters.iter() {
+                let dio = Dio {
+                    node: module.node,
+                    pin: *pin,
+                };
+                text += &format!(
+                    "filter {} {} {}\n",
+                    dio.address(Io::Input),
+                    ms(filter.on_delay),
+                    ms(filter.off_delay)
+                );
+            }
+        }
+        text += "# station <col> <row> <quarter turns> <push to|-> <inputs> <outputs> <name>\n";
+        text += "# accumulation <col> <row> <quarter turns> <push to|-> <zones> <inputs> <outputs> <name>\n";
+        text += "# segment <col> <row> <quarter turns> <push to|-> curve <radius> <degrees>|incline <length> <rise> <inputs> <outputs> <name>\n";
+        let dios = |dios: &[Dio], kind: Io| -> String {
+            dios.iter()
+                .map(|dio| dio.address(kind).to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        for station in self.stations.iter() {
+            let push_to = match station.push_to {
+                Some(to) => to.to_string(),
+                None => "-".into(),
+            };
+            let (keyword, zones) = match station.kind {
+                StationKind::Transfer => ("station", String::new()),
+                StationKind::Accumulation(zones) => ("accumulation", format!(" {zones}")),
+                StationKind::Segment(ConveyorPath::Curve { radius, angle }) => {
+                    let degrees = (angle.to_degrees() * 100.0).round() / 100.0;
+                    ("segment", format!(" curve {radius} {degrees}"))
+                }
+                StationKind::Segment(ConveyorPath::Incline { length, rise }) => {
+                    ("segment", format!(" incline {length} {rise}"))
+                }
+            };
+            text += &format!(
+                "{keyword} {} {} {} {}{zones} {} {} {}\n",
+                station.cell.x,
+                station.cell.y,
+                station.quarter_turns,
+                push_to,
+                dios(&station.inputs, Io::Input),
+                dios(&station.outputs, Io::Output),
+                station.name,
+            );
+        }
+        text += "# proximity <station> <at m> <inductive|capacitive> <input> <name>\n";
+        text += "# limit <station> <at m> <no|nc> <input> <name>\n";
+        text += "# encoder <station> <motor> <pulses per rev> <input a>,<input b>|<input word> <name>\n";
+        text += "# laser <station> <at m> <range m> <input word> <name>\n";
+        text += "# loadcell <station> <at m> <capacity kg> <input word> <name>\n";
+        for device in self.devices.iter() {
+            let (first, second) = match device.kind {
+                DeviceKind::Proximity(kind) => {
+                    let kind = match kind {
+                        ProximityKind::Inductive => "inductive",
+                        ProximityKind::Capacitive => "capacitive",
+                    };
+                    (device.at.to_string(), kind.to_string())
+                }
+                DeviceKind::LimitSwitch(contact) => {
+                    let contact = match contact {
+                        Contact::NormallyOpen => "no",
+                        Contact::NormallyClosed => "nc",
+                    };
+                    (device.at.to_string(), contact.to_string())
+                }
+                DeviceKind::Encoder {
+                    motor,
+                    pulses_per_rev,
+                } => (motor.to_string(), pulses_per_rev.to_string()),
+                DeviceKind::LaserDistance { range } => (device.at.to_string(), range.to_string()),
+                DeviceKind::LoadCell { capacity } => (device.at.to_string(), capacity.to_string()),
+            };
+            let inputs: Vec<_> = device.inputs.iter().map(ToString::to_string).collect();
+            text += &format!(
+                "{} {} {first} {second} {} {}\n",
+                device.keyword(),
+                device.station,
+                inputs.join(","),
+                device.name,
+            );
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut layout = Self::default();
+        let mut buses = Vec::new();
+        let mut filters = Vec::new();
+        for (line_nr, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let result: Result<()> = match fields.as_slice() {
+                ["cell_size", size] => size
+                    .parse()
+                    .map(|size| layout.cell_size = size)
+                    .map_err(|err| format!("bad cell size: {err}").into()),
+                ["module", node, inputs, outputs, name @ ..] => {
+                    parse_module(node, inputs, outputs, &name.join(" "))
+                        .map(|module| layout.modules.push(module))
+                }
+                ["bus", node, cycle, delay, offline] => {
+                    parse_bus(node, cycle, delay, offline).map(|bus| buses.push(bus))
+                }
+                ["filter", target, on_delay, off_delay @ ..] => {
+                    parse_filter(target, on_delay, off_delay).map(|filter| filters.push(filter))
+                }
+                ["station", col, row, turns, push_to, inputs, outputs, name @ ..] => {
+                    let name = name.join(" ");
+                    let kind = StationKind::Transfer;
+                    parse_station(kind, col, row, turns, push_to, inputs, outputs, &name)
+                        .map(|station| layout.stations.push(station))
+                }
+                ["accumulation", col, row, turns, push_to, zones, inputs, outputs, name @ ..] => {
+                    let name = name.join(" ");
+                    parse_zones(zones).and_then(|kind| {
+                        parse_station(kind, col, row, turns, push_to, inputs, outputs, &name)
+                            .map(|station| layout.stations.push(station))
+                    })
+                }
+                ["segment", col, row, turns, push_to, shape, a, b, inputs, outputs, name @ ..] => {
+                    let name = name.join(" ");
+                    parse_path(shape, a, b).and_then(|kind| {
+                        parse_station(kind, col, row, turns, push_to, inputs, outputs, &name)
+                            .map(|station| layout.stations.push(station))
+                    })
+                }
+                [keyword @ ("proximity" | "limit" | "encoder" | "laser" | "loadcell"), station, first, second, inputs, name @ ..] => {
+                    parse_device(keyword, station, first, second, inputs, &name.join(" "))
+                        .map(|device| layout.devices.push(device))
+                }
+                _ => Err("unknown entry".into()),
+            };
+            if let Err(err) = result {
+                return Err(format!("layout line {}: {err}", line_nr + 1).into());
+            }
+        }
+        let n_stations = layout.stations.len();
+        if let Some(station) = layout
+            .stations
+            .iter()
+            .find(|station| station.push_to.is_some_and(|to| to >= n_stations))
+        {
+            return Err(format!("{} pushes to a station that does not exist", station.name).into());
+        }
+        layout.check_cells()?;
+        for device in layout.devices.iter() {
+            let Some(station) = layout.stations.get(device.station) else {
+                let name = &device.name;
+                return Err(format!("{name} is mounted on a station that does not exist").into());
+            };
+            let motors = station.kind.signals(Io::Output).len() / 3;
+            if let DeviceKind::Encoder { motor, .. } = device.kind {
+                if motor as usize >= motors {
+                    let (name, station) = (&device.name, &station.name);
+                    return Err(format!("{name}: {station} has no motor {motor}").into());
+                }
+            }
+        }
+        if layout.modules.is_empty() {
+            layout.infer_modules();
+        }
+        for (node, bus) in buses {
+            let module = layout
+                .modules
+                .iter_mut()
+                .find(|module| module.node == node)
+                .ok_or(format!("bus N{}: no module on that node", node.0))?;
+            module.bus = bus;
+        }
+        for (node, pin, filter) in filters {
+            let module = layout
+                .modules
+                .iter_mut()
+                .find(|module| module.node == node)
+                .ok_or(format!("filter N{}: no module on that node", node.0))?;
+            match pin {
+                Some(pin) if pin.as_usize() >= module.inputs => {
+                    let dio = Dio { node, pin };
+                    return Err(format!(
+                        "filter {}: {} only has {} inputs",
+                        dio.address(Io::Input),
+                        module.name,
+                        module.inputs
+                    )
+                    .into());
+                }
+                Some(pin) => {
+                    module.pin_filters.retain(|(other, _)| *other != pin);
+                    module.pin_filters.push((pin, filter));
+                }
+                None => module.filter = filter,
+            }
+        }
+        layout.check_io()?;
+        Ok(layout)
+    }
+
+    /// All io conflicts as one error
+    pub fn check_io(&self) -> Result<()> {
+        let conflicts = self.io_conflicts();
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        let conflicts: Vec<_> = conflicts.iter().map(ToString::to_string).collect();
+        Err(format!("layout io: {}", conflicts.join("; ")).into())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("cant read layout {}: {err}", path.display()))?;
+        Self::parse(&text)
+    }
+
+    /// Refuses layouts with io conflicts, they would not load again
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        self.check_io()?;
+        fs::write(path, self.to_text())
+            .map_err(|err| format!("cant write layout {}: {err}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// `n` comma separated bit addresses like `%I0.0,N1:%I0.1`
+fn parse_dios(text: &str, kind: Io, n: usize) -> Result<Vec<Dio>> {
+    let dios = text
+        .split(',')
+        .map(|text| -> Result<Dio> {
+            // layouts saved before addresses were written as node:pin
+            if let Some((node, pin)) = text.split_once(':').filter(|_| !text.contains('%')) {
+                if let (Ok(node), Ok(pin)) = (node.parse(), pin.parse()) {
+                    return Ok(Dio {
+                        node: NodeId(node),
+                        pin: DioPin(pin),
+                    });
+                }
+            }
+            let address = IoAddress::parse(text)?;
+            if address.kind != kind {
+                return Err(format!("{address} is not an {kind:?} address").into());
+            }
+            address
+                .dio()
+                .ok_or_else(|| format!("{address} is not a bit address").into())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if dios.len() != n {
+        return Err(format!("expected {n} io addresses, got {}", dios.len()).into());
+    }
+    Ok(dios)
+}
+
+/// `N3` or `3`
+fn parse_node(text: &str) -> Result<NodeId> {
+    let number = text.strip_prefix(['N', 'n']).unwrap_or(text);
+    let node = number.parse().map_err(|_| format!("bad node '{text}'"))?;
+    Ok(NodeId(node))
+}
+
+fn parse_ms(text: &str) -> Result<Duration> {
+    let ms: f64 = text.trim_end_matches("ms").parse()?;
+    Duration::try_from_secs_f64(ms / 1000.0).map_err(|_| format!("bad time '{text}'").into())
+}
+
+fn parse_bus(node: &str, cycle: &str, delay: &str, offline: &str) -> Result<(NodeId, BusConfig)> {
+    let offline_inputs = match offline {
+        "freeze" => OfflineInputs::Freeze,
+        "zero" => OfflineInputs::Zero,
+        other => return Err(format!("offline inputs are freeze or zero, not '{other}'").into()),
+    };
+    let bus = BusConfig {
+        cycle: parse_ms(cycle)?,
+        delay: parse_ms(delay)?,
+        offline_inputs,
+    };
+    Ok((parse_node(node)?, bus))
+}
+
+/// `N0 3` filters every input of a node, `N0:%I1.2 5 20` one input with
+/// its on and off delay
+fn parse_filter(
+    target: &str,
+    on_delay: &str,
+    off_delay: &[&str],
+) -> Result<(NodeId, Option<DioPin>, InputFilter)> {
+    let on_delay = parse_ms(on_delay)?;
+    let off_delay = match off_delay {
+        [] => on_delay,
+        [off_delay] => parse_ms(off_delay)?,
+        _ => return Err("expected filter <node|input> <on delay ms> <off delay ms>".into()),
+    };
+    let filter = InputFilter {
+        on_delay,
+        off_delay,
+    };
+    if !target.contains('%') {
+        return Ok((parse_node(target)?, None, filter));
+    }
+    let address = IoAddress::parse(target)?;
+    let dio = address
+        .dio()
+        .filter(|_| address.kind == Io::Input)
+        .ok_or(format!("{address} is not an input bit"))?;
+    Ok((dio.node, Some(dio.pin), filter))
+}
+
+fn parse_module(node: &str, inputs: &str, outputs: &str, name: &str) -> Result<IoModule> {
+    if name.is_empty() {
+        return Err("module without a name".into());
+    }
+    let module = IoModule {
+        node: parse_node(node)?,
+        name: name.into(),
+        inputs: inputs.parse()?,
+        outputs: outputs.parse()?,
+        bus: default(),
+        filter: default(),
+        pin_filters: Vec::new(),
+    };
+    if module.inputs.max(module.outputs) > MAX_MODULE_PINS {
+        return Err(format!("{name} has more than {MAX_MODULE_PINS} pins").into());
+    }
+    Ok(module)
+}
+
+/// Zones of an accumulation conveyor, `1` to [`MAX_ZONES`]
+fn parse_zones(text: &str) -> Result<StationKind> {
+    let zones: u8 = text
+        .parse()
+        .map_err(|_| format!("bad zone count '{text}'"))?;
+    if zones == 0 || zones as usize > MAX_ZONES {
+        return Err(format!("accumulation conveyors have 1 to {MAX_ZONES} zones").into());
+    }
+    Ok(StationKind::Accumulation(zones))
+}
+
+/// `curve <radius> <degrees>` turning left for positive degrees, half a turn
+/// at most, or
+/// `incline <length> <rise>` declining for a negative rise
+fn parse_path(shape: &str, a: &str, b: &str) -> Result<StationKind> {
+    let (a, b): (f32, f32) = (a.parse()?, b.parse()?);
+    let path = match shape {
+        "curve" if a > 0.0 && b != 0.0 && b.abs() <= 180.0 => ConveyorPath::Curve {
+            radius: a,
+            angle: b.to_radians(),
+        },
+        "incline" if a > 0.0 => ConveyorPath::Incline { length: a, rise: b },
+        "curve" => {
+            return Err("curves need a positive radius and an angle of at most 180 degrees".into())
+        }
+        "incline" => return Err("inclines need a positive length".into()),
+        other => return Err(format!("segments are a curve or an incline, not '{other}'").into()),
+    };
+    Ok(StationKind::Segment(path))
+}
+
+/// `<station> <at> <kind>` of a sensor or `<station> <motor> <pulses per rev>`
+/// of an encoder, then its inputs
+fn parse_device(
+    keyword: &str,
+    station: &str,
+    first: &str,
+    second: &str,
+    inputs: &str,
+    name: &str,
+) -> Result<DeviceLayout> {
+    if name.is_empty() {
+        return Err(format!("{keyword} without a name").into());
+    }
+    let station = station
+        .parse()
+        .map_err(|_| format!("bad station '{station}'"))?;
+    let (at, kind): (f32, DeviceKind) = match keyword {
+        "proximity" => {
+            let kind = match second {
+                "inductive" => ProximityKind::Inductive,
+                "capacitive" => ProximityKind::Capacitive,
+                other => return Err(format!("'{other}' is not inductive or capacitive").into()),
+            };
+            (first.parse()?, DeviceKind::Proximity(kind))
+        }
+        "limit" => {
+            let contact = match second {
+                "no" => Contact::NormallyOpen,
+                "nc" => Contact::NormallyClosed,
+                other => return Err(format!("'{other}' is not a no or nc contact").into()),
+            };
+            (first.parse()?, DeviceKind::LimitSwitch(contact))
+        }
+        "laser" | "loadcell" => {
+            let top: f32 = second.parse()?;
+            if top.is_nan() || top <= 0.0 {
+                return Err(format!("{name}: the measuring range has to be positive").into());
+            }
+            let kind = match keyword {
+                "laser" => DeviceKind::LaserDistance { range: top },
+                _ => DeviceKind::LoadCell { capacity: top },
+            };
+            (first.parse()?, kind)
+        }
+        _ => {
+            let pulses_per_rev = second.parse()?;
+            if pulses_per_rev == 0 {
+                return Err("encoders need at least one pulse per rev".into());
+            }
+            let motor = first.parse()?;
+            let kind = DeviceKind::Encoder {
+                motor,
+                pulses_per_rev,
+            };
+            (0.0, kind)
+        }
+    };
+    if at.is_nan() || at < 0.0 {
+        return Err(format!("{name} is mounted before its station starts").into());
+    }
+    let inputs = inputs
+        .split(',')
+        .map(IoAddress::parse)
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(address) = inputs.iter().find(|address| address.kind != Io::Input) {
+        return Err(format!("{address} is not an input").into());
+    }
+    let widths: Vec<_> = inputs.iter().map(|address| address.width).collect();
+    match (kind, widths.as_slice()) {
+        (DeviceKind::Proximity(_) | DeviceKind::LimitSwitch(_), [Width::Bit]) => (),
+        (DeviceKind::Encoder { .. }, [Width::Bit, Width::Bit] | [Width::Word]) => (),
+        (DeviceKind::LaserDistance { .. } | DeviceKind::LoadCell { .. }, [Width::Word]) => (),
+        (DeviceKind::Encoder { .. }, _) => {
+            return Err(format!("{name}: encoders take two input bits or an input word").into())
+        }
+        (DeviceKind::LaserDistance { .. } | DeviceKind::LoadCell { .. }, _) => {
+            return Err(format!("{name}: {keyword} takes one input word").into())
+        }
+        _ => return Err(format!("{name}: {keyword} takes one input bit").into()),
+    }
+    Ok(DeviceLayout {
+        name: name.into(),
+        station,
+        at,
+        kind,
+        inputs,
+    })
+}
+
+fn parse_station(
+    kind: StationKind,
+    col: &str,
+    row: &str,
+    turns: &str,
+    push_to: &str,
+    inputs: &str,
+    outputs: &str,
+    name: &str,
+) -> Result<StationLayout> {
+    let push_to = match push_to {
+        "-" => None,
+        to => Some(to.parse()?),
+    };
+    Ok(StationLayout {
+        name: name.into(),
+        cell: IVec2::new(col.parse()?, row.parse()?),
+        quarter_turns: turns.parse::<u8>()? % 4,
+        push_to,
+        kind,
+        inputs: parse_dios(inputs, Io::Input, kind.signals(Io::Input).len())?,
+        outputs: parse_dios(outputs, Io::Output, kind.signals(Io::Output).len())?,
+    })
+}
+
+#[derive(Event)]
+/// Despawn all layout stations and spawn them again from [`Layout`]
+///
+/// Details and the [`Register`] are left alone
+pub struct ApplyLayout;
+
+#[derive(Component)]
+/// Station spawned from the [`Layout`]
+pub struct LayoutStation;
+
+pub(crate) fn ensure_io_size(stores: &mut HashMap<NodeId, IOStore>, sizes: HashMap<NodeId, usize>) {
+    for (node, size) in sizes {
+        let store = stores.entry(node).or_insert_with(|| IOStore::new(size));
+        if store.state.len() < size {
+            store.resize(size);
+        }
+    }
+}
+
+fn on_apply_layout(
+    _trigger: On<ApplyLayout>,
+    mut cmd: Commands,
+    layout: Res<Layout>,
+    old: Query<
+        (
+            Entity,
+            Option<&Name>,
+            Option<&RegisterPosition>,
+            Option<&RegisterZones>,
+        ),
+        With<LayoutStation>,
+    >,
+    mut io: ResMut<IoDevices>,
+    mut reg: ResMut<Register>,
+) {
+    let mut before = Vec::new();
+    for (entity, name, pos, zones) in old.iter() {
+        if let (Some(name), Some(pos)) = (name, pos) {
+            before.push((name.as_str(), pos.span(zones)));
+        }
+        cmd.entity(entity).despawn();
+    }
+    ensure_io_size(&mut io.digital_inputs, layout.io_size(Io::Input));
+    ensure_io_size(&mut io.digital_outputs, layout.io_size(Io::Output));
+    for module in layout.modules.iter() {
+        if let Some(store) = io.digital_inputs.get_mut(&module.node) {
+            store.filter = module.filter;
+            store.pin_filters = module
+                .pin_filters
+                .iter()
+                .map(|(pin, filter)| (pin.as_usize(), *filter))
+                .collect();
+        }
+    }
+    // filtering the bits of a word one by one would let programs read it torn
+    for dio in layout.input_words().flat_map(IoAddress::pins) {
+        if let Some(store) = io.digital_inputs.get_mut(&dio.node) {
+            store
+                .pin_filters
+                .insert(dio.pin.as_usize(), InputFilter::default());
+        }
+    }
+    let positions = layout.register_positions();
+    let transforms = layout.transforms();
+    let zones = layout.stations.iter().map(|station| station.kind.zones());
+    if old.is_empty() {
+        reg.resize(zones.sum());
+    } else {
+        let moves = layout.register_moves(&before);
+        reg.remap(zones.sum(), moves);
+    }
+
+    let entities: Vec<_> = layout
+        .stations
+        .iter()
+        .map(|_| cmd.spawn(LayoutStation).id())
+        .collect();
+    for (i, station) in layout.stations.iter().enumerate() {
+        let push = station.push_to.map(|to| PushTo(entities[to]));
+        let from = layout
+            .stations
+            .iter()
+            .position(|other| other.push_to == Some(i))
+            .map(|from| PullFrom(entities[from]));
+        let transform = transforms[i];
+        let register_pos = RegisterPosition(positions[i] as u16);
+        match station.kind {
+            StationKind::Transfer | StationKind::Segment(_) => {
+                let inputs = station.inputs.as_slice().try_into();
+                let outputs = station.outputs.as_slice().try_into();
+                let (Ok(inputs), Ok(outputs)) = (inputs, outputs) else {
+                    warn!(
+                        "{}: a transfer station needs {N_INPUTS} inputs and {N_OUTPUTS} outputs",
+                        station.name
+                    );
+                    continue;
+                };
+                let insert = InsertTbana4x2::new(
+                    entities[i],
+                    None,
+                    station.name.clone(),
+                    inputs,
+                    outputs,
+                    transform,
+                    Direction::Forward,
+                    register_pos,
+                    push,
+                    from,
+                );
+                cmd.trigger(match station.kind {
+                    StationKind::Segment(path) => insert.with_path(path),
+                    _ => insert,
+                });
+            }
+            StationKind::Accumulation(zones) => cmd.trigger(InsertAccumulation {
+                entity: entities[i],
+                name: station.name.clone().into(),
+                zones,
+                pitch: layout.cell_size,
+                inputs: station.inputs.clone(),
+                outputs: station.outputs.clone(),
+                transform,
+                register_pos,
+                push_to: push,
+            }),
+        }
+    }
+
+    for device in layout.devices.iter() {
+        let Some(&station) = entities.get(device.station) else {
+            continue;
+        };
+        let mount = layout.mount(device.station, device.at);
+        let name = device.name.clone();
+        let input = device.inputs.first().and_then(|address| address.dio());
+        match (device.kind, input) {
+            (DeviceKind::Proximity(kind), Some(dio)) => {
+                let face = Transform::from_xyz(0.0, -kind.rated_distance() / 2.0, 0.0)
+                    .with_rotation(Quat::from_rotation_x(-FRAC_PI_2));
+                let bundle = ProximityBundle::new(name, dio, kind);
+                cmd.spawn((bundle, mount * face, ChildOf(station)));
+            }
+            (DeviceKind::LimitSwitch(contact), Some(dio)) => {
+                let bundle = LimitSwitchBundle::new(name, dio, contact, LEVER);
+                cmd.spawn((bundle, mount, ChildOf(station)));
+            }
+            (DeviceKind::Encoder { motor, .. }, _) => {
+                let outputs = &layout.stations[device.station].outputs;
+                let forward = outputs.get(3 * motor as usize);
+                let Some(mount) = forward.and_then(|forward| device.encoder_mount(*forward)) else {
+                    continue;
+                };
+                cmd.spawn((mount, Name::new(name), ChildOf(station)));
+            }
+            (DeviceKind::LaserDistance { range }, _) => {
+                let address = device.inputs[0];
+                let bundle = LaserDistanceBundle::new(name, address, range, LASER_RESOLUTION, 0.0);
+                let looking_back = Transform::from_xyz(0.0, LASER_HEIGHT, 0.0)
+                    .with_rotation(Quat::from_rotation_y(PI));
+                match bundle {
+                    Ok(bundle) => {
+                        cmd.spawn((bundle, mount * looking_back, ChildOf(station)));
+                    }
+                    Err(err) => warn!("{}: {err}", device.name),
+                }
+            }
+            (DeviceKind::LoadCell { capacity }, _) => {
+                let address = device.inputs[0];
+                let resolution = LOAD_CELL_RESOLUTION;
+                match LoadCellBundle::new(name, address, LOAD_CELL, capacity, resolution, 0.0) {
+                    Ok(bundle) => {
+                        cmd.spawn((bundle, mount, ChildOf(station)));
+                    }
+                    Err(err) => warn!("{}: {err}", device.name),
+                }
+            }
+            _ => warn!("{}: not wired like a {}", device.name, device.keyword()),
+        }
+    }
+}
+
+/// Apply the layout file again when it changed on disk, details and the register stay
+fn watch_layout(
+    mut cmd: Commands,
+    mut layout: ResMut<Layout>,
+    mut editor: ResMut<LayoutEditor>,
+    mut errors: ResMut<LoadErrors>,
+) {
+    let modified = modified(&editor.path);
+    if modified.is_none() || modified == editor.modified {
+        return;
+    }
+    editor.modified = modified;
+    let path = editor.path.clone();
+    let loaded = Layout::load(&path);
+    errors.report(Path::new(&path), &loaded);
+    match loaded {
+        Ok(loaded) => {
+            *layout = loaded;
+            editor.status = Some(format!("reloaded {path}"));
+            cmd.trigger(ApplyLayout);
+        }
+        Err(err) => editor.status = Some(err.to_string()),
+    }
+}
+
+fn pause_physics(mut time: ResMut<Time<Physics>>) {
+    time.pause();
+}
+
+fn resume_physics(mut time: ResMut<Time<Physics>>) {
+    time.unpause();
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EditTool {
+    #[default]
+    Place,
+    Rotate,
+    Link,
+    Delete,
+}
+
+#[derive(Resource)]
+pub struct LayoutEditor {
+    pub tool: EditTool,
+    /// node that IO of new stations is allocated from
+    pub io_node: NodeId,
+    /// what the place tool builds
+    pub place: StationKind,
+    /// station picked as start of a link
+    pub link_from: Option<usize>,
+    pub path: String,
+    /// result of the last edit, save or load
+    pub status: Option<String>,
+    /// modification time of `path` when it was last loaded or saved
+    pub modified: Option<SystemTime>,
+}
+
+impl Default for LayoutEditor {
+    fn default() -> Self {
+        Self {
+            tool: default(),
+            io_node: default(),
+            place: default(),
+            link_from: None,
+            path: LAYOUT_FILE.into(),
+            status: None,
+            modified: None,
+        }
+    }
+}
+
+fn edit_layout_on_click(
+    mut cmd: Commands,
+    buttons: Res<ButtonInput<MouseButton>>,
+    window: Single<(&Window, &CursorOptions), With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    mut contexts: EguiContexts,
+    mut layout: ResMut<Layout>,
+    mut editor: ResMut<LayoutEditor>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let (window, cursor_options) = *window;
+    if !cursor_options.visible {
+        return;
+    }
+    if contexts
+        .ctx_mut()
+        .is_ok_and(|ctx| ctx.wants_pointer_input())
+    {
+        return;
+    }
+    let (camera, camera_transform) = *camera;
+    let Some(cursor) = window.cursor_position() else {
+        return;
+    };
+    let Ok(ray) = camera.viewport_to_world(camera_transform, cursor) else {
+        return;
+    };
+    let Some(distance) = ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y)) else {
+        return;
+    };
+    let cell = layout.cell_of(ray.get_point(distance));
+    let clicked = layout.station_at(cell);
+
+    let edit: Result<bool> = match (editor.tool, clicked) {
+        (EditTool::Place, _) => {
+            let node = editor.io_node;
+            layout.add_station(cell, node, editor.place).map(|_| true)
+        }
+        (EditTool::Rotate, Some(idx)) => layout.rotate(idx).map(|_| true),
+        (EditTool::Delete, Some(idx)) => {
+            layout.remove_station(idx);
+            editor.link_from = None;
+            Ok(true)
+        }
+        (EditTool::Link, Some(idx)) => match editor.link_from.take() {
+            Some(from) => layout.link(from, idx).map(|_| true),
+            None => {
+                editor.link_from = Some(idx);
+                Ok(false)
+            }
+        },
+        (_, None) => Ok(false),
+    };
+    match edit {
+        Ok(true) => {
+            editor.status = None;
+            cmd.trigger(ApplyLayout);
+        }
+        Ok(false) => (),
+        Err(err) => editor.status = Some(err.to_string()),
+    }
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct LayoutGizmos;
+
+fn render_layout(mut gizmos: Gizmos<LayoutGizmos>, layout: Res<Layout>, editor: Res<LayoutEditor>) {
+    let size = layout.cell_size;
+    let flat = Quat::from_rotation_x(FRAC_PI_2);
+    gizmos.grid(
+        Isometry3d::new(Vec3::ZERO, flat),
+        UVec2::splat(41),
+        Vec2::splat(size),
+        css::DARK_GRAY,
+    );
+    let center = |cell: IVec2| Vec3::new(cell.x as f32, 0.0, cell.y as f32) * size;
+    let transforms = layout.transforms();
+    for (i, station) in layout.stations.iter().enumerate() {
+        let color = if editor.link_from == Some(i) {
+            css::ORANGE
+        } else {
+            css::LIGHT_GRAY
+        };
+        for cell in station.cells() {
+            gizmos.rect(
+                Isometry3d::new(center(cell) + Vec3::Y * 0.01, flat),
+                Vec2::splat(size * 0.95),
+                color,
+            );
+        }
+        if let StationKind::Segment(path) = station.kind {
+            // where the segment snapped to, it may lie off its cell
+            let (length, transform) = (path.length(), transforms[i]);
+            let points = (0..=16).map(|step| {
+                let pose = path.pose(length * step as f32 / 16.0);
+                transform.transform_point(pose.translation) + Vec3::Y * 0.6
+            });
+            gizmos.linestrip(points, color);
+        }
+        if let Some(to) = station.push_to.and_then(|to| layout.stations.get(to)) {
+            let lift = Vec3::Y * 1.2;
+            // details leave from the last zone
+            let from = station.cells().last().unwrap_or(station.cell);
+            gizmos.arrow(center(from) + lift, center(to.cell) + lift, css::AQUA);
+        }
+    }
+}
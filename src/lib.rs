@@ -1,93 +1,123 @@
 use bevy::prelude::*;
 
+pub mod accumulation;
+pub mod analog;
+pub mod encoder;
+pub mod fieldbus;
 pub mod fotocell;
 pub mod io;
+pub mod ioalloc;
+pub mod layout;
+pub mod pallet;
 pub mod physics;
 pub mod plc;
+pub mod proximity;
+pub mod reload;
+pub mod safety;
+pub mod scenario;
+pub mod segment;
 pub mod sensor;
 pub mod shiftreg;
+pub mod simclock;
 mod sysorder;
 mod tbana;
 pub mod ui;
 use avian3d::prelude::PhysicsPlugins;
-use itertools::Itertools;
-pub use sysorder::InitSet;
+pub use sysorder::{InitSet, SimMode};
 pub use tbana::TbanaPlugin;
 
-use tbana::TbanaBundle;
+use std::path::Path;
 
 use crate::{
-    fotocell::{
-        on_fotocell_blocked, on_fotocell_unblocked, FotocellAssets, FotocellBundle, FotocellPlugin,
-    },
-    io::{on_parrent_switch, Dio, DioPin, IOStore, IoDevices, IoPlugin, NodeId},
-    shiftreg::{RegisterPosition, ShiftRegPlugin},
+    accumulation::AccumulationPlugin,
+    analog::AnalogPlugin,
+    encoder::EncoderPlugin,
+    fieldbus::FieldbusPlugin,
+    fotocell::FotocellPlugin,
+    io::{IoPlugin, NodeId},
+    layout::{ApplyLayout, Layout, LayoutEditor, LayoutPlugin, LAYOUT_FILE},
+    pallet::PalletPlugin,
+    plc::PlcPlugin,
+    proximity::ProximityPlugin,
+    reload::{modified, LoadErrors, ReloadPlugin},
+    safety::{EStopBundle, InsertLightCurtain, SafetyAssets, SafetyPlugin},
+    scenario::ScenarioPlugin,
+    sensor::SensorPlugin,
+    shiftreg::ShiftRegPlugin,
+    simclock::SimClockPlugin,
     sysorder::SysOrderPlugin,
-    tbana::{Direction, InsertTbana4x2, MovimotDQ, PushTo, TBanaAssets, TransportWheelBundle},
     ui::UIPlugin,
 };
 
-use bitvec::prelude::*;
 pub struct DummyPlugin;
 
 impl Plugin for DummyPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(TbanaPlugin);
         app.add_plugins(IoPlugin);
+        app.add_plugins(FieldbusPlugin);
         app.add_plugins(FotocellPlugin);
+        app.add_plugins(SensorPlugin);
+        app.add_plugins(ProximityPlugin);
+        app.add_plugins(EncoderPlugin);
+        app.add_plugins(AnalogPlugin);
+        app.add_plugins(ScenarioPlugin);
         app.add_plugins(UIPlugin);
         app.add_plugins(SysOrderPlugin);
         app.add_plugins(ShiftRegPlugin);
+        app.add_plugins(PalletPlugin);
+        app.add_plugins(AccumulationPlugin);
+        app.add_plugins(SafetyPlugin);
+        app.add_plugins(LayoutPlugin);
+        app.add_plugins(SimClockPlugin::default());
+        app.add_plugins(PlcPlugin);
+        app.add_plugins(ReloadPlugin);
         app.add_plugins(PhysicsPlugins::default());
         app.add_systems(Startup, spawn_some_stuff.in_set(InitSet::Spawn));
     }
 }
 
-fn spawn_some_stuff(mut cmd: Commands, mut io: ResMut<IoDevices>) {
+fn spawn_some_stuff(
+    mut cmd: Commands,
+    mut layout: ResMut<Layout>,
+    mut editor: ResMut<LayoutEditor>,
+    mut errors: ResMut<LoadErrors>,
+    safety_assets: Res<SafetyAssets>,
+) -> Result {
     let n_banor = 30;
-    let io_size = 8 * n_banor;
     let node: NodeId = 0.into();
-    io.digital_inputs.insert(node, IOStore::new(io_size));
-    io.digital_outputs.insert(node, IOStore::new(io_size));
-
-    let mut translation = Vec3::default();
-
     let spaceing = 2.1;
 
-    let new_enitities: Vec<_> = (0..=n_banor).map(|i| cmd.spawn_empty().id()).collect();
-
-    for (i, entity) in new_enitities.iter().enumerate() {
-        let inputs = io.digital_inputs.get_mut(&node).unwrap();
-        let inputs = inputs
-            .take(4)
-            .map(|pin| Dio { node, pin })
-            .collect_array()
-            .unwrap();
-        let outputs = io.digital_outputs.get_mut(&node).unwrap();
-        let outputs = outputs
-            .take(6)
-            .map(|pin| Dio { node, pin })
-            .collect_array()
-            .unwrap();
+    // a broken layout file is reported and fixed while running, start from a line
+    let loaded = Path::new(LAYOUT_FILE)
+        .exists()
+        .then(|| Layout::load(LAYOUT_FILE));
+    if let Some(loaded) = &loaded {
+        errors.report(Path::new(LAYOUT_FILE), loaded);
+    }
+    editor.modified = modified(LAYOUT_FILE);
+    *layout = match loaded {
+        Some(Ok(loaded)) => loaded,
+        _ => Layout::line(n_banor, node)?,
+    };
+    cmd.trigger(ApplyLayout);
 
-        translation.z = spaceing * i as f32;
-        let push = new_enitities.get(i + 1).map(|ent| PushTo(*ent));
-        let from = if i > 0 {
-            new_enitities.get(i - 1).map(|ent| tbana::PullFrom(*ent))
-        } else {
-            None
-        };
-        cmd.trigger(InsertTbana4x2::new(
-            *entity,
-            None,
-            format!("stn {i}"),
-            inputs,
-            outputs,
-            Transform::from_translation(translation),
-            Direction::Forward,
-            RegisterPosition(i as u16),
-            push,
-            from,
+    for (i, z) in [0.0, spaceing * (n_banor - 1) as f32]
+        .into_iter()
+        .enumerate()
+    {
+        cmd.spawn((
+            EStopBundle::new(format!("e-stop {i}"), &safety_assets),
+            Transform::from_xyz(-1.0, 0.9, z),
         ));
     }
+    cmd.trigger(InsertLightCurtain {
+        name: "operator side".into(),
+        transform: Transform::from_xyz(1.5, 0.2, 10.0)
+            .with_rotation(Quat::from_rotation_y(-90_f32.to_radians())),
+        width: 10.0,
+        height: 1.6,
+        n_beams: 8,
+    });
+    Ok(())
 }
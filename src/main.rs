@@ -195,6 +195,7 @@ fn spawn_ball(
 fn main() {
     let mut app = App::new();
     app.add_plugins(DefaultPlugins);
+    app.add_plugins(MeshPickingPlugin);
     app.add_plugins(DummyPlugin);
     app.add_plugins(EguiPlugin::default());
     app.add_plugins(WorldInspectorPlugin::new());
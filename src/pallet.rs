@@ -0,0 +1,203 @@
+//! Pallets, the carriers details travel on
+//!
+//! Conveyors drive pallets like bare details, the details on a pallet are its
+//! children and move with it. The [`Register`] tracks which pallet is at which
+//! station, a pallet keeps its id and data carrier for its whole life.
+//!
+//! A station linked to one that is not next to it, like the last station of a
+//! line linked back to the first, hands its pallets over a return conveyor
+//! that puts them on the infeed of the receiving station.
+
+use avian3d::prelude::*;
+use bevy::{color::palettes::css, prelude::*};
+
+use crate::{
+    physics::PhysLayer,
+    segment::ConveyorPath,
+    shiftreg::{
+        DetailAssets, DetailBundle, DetailState, Register, RegisterPosition, RegisterZones,
+        ShiftOver,
+    },
+    sysorder::InitSet,
+    tbana::{TransportBana, TransportMode, WHEEL_FRICTION},
+};
+
+pub struct PalletPlugin;
+
+impl Plugin for PalletPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Pallet>();
+        app.init_resource::<PalletAssets>();
+        app.add_systems(Startup, load_pallet_assets.in_set(InitSet::LoadAssets));
+        app.add_observer(on_load_pallet);
+        app.add_observer(on_remove_pallet);
+        app.add_observer(on_return_pallet);
+    }
+}
+
+/// Bytes of user memory on a data carrier, like a common RFID tag
+pub const CARRIER_BYTES: usize = 112;
+/// Height of the pallet center over the station, on top of the wheels and
+/// sunk a centimeter into them so a kinematic pallet keeps touching them
+const PALLET_Y: f32 = 0.59;
+const PALLET_SIZE: Vec3 = Vec3::new(0.6, 0.1, 1.6);
+/// Pallets handed to a station farther away than this go over a return conveyor
+const RETURN_DISTANCE: f32 = 3.0;
+/// Density of pallets in kg/m³, like plywood
+const PALLET_DENSITY: f32 = 600.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Reflect, Default)]
+pub struct PalletId(pub u32);
+
+#[derive(Component, Debug, Clone, Reflect)]
+/// Carrier that details ride on, with an id and a data carrier stations can
+/// read and write
+pub struct Pallet {
+    pub id: PalletId,
+    pub carrier: Vec<u8>,
+}
+
+impl Pallet {
+    pub fn new(id: PalletId) -> Self {
+        Self {
+            id,
+            carrier: vec![0; CARRIER_BYTES],
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PalletAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn load_pallet_assets(
+    mut assets: ResMut<PalletAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    assets.mesh = meshes.add(Cuboid::from_size(PALLET_SIZE));
+    assets.material = materials.add(StandardMaterial {
+        base_color: css::SADDLE_BROWN.into(),
+        ..Default::default()
+    });
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+/// Put a new pallet on an empty station, with a detail on it or empty
+pub struct LoadPallet {
+    pub station: Entity,
+    pub with_detail: bool,
+}
+
+fn on_load_pallet(
+    trigger: On<LoadPallet>,
+    mut cmd: Commands,
+    stations: Query<(&GlobalTransform, &RegisterPosition), With<TransportBana>>,
+    pallets: Query<&Pallet>,
+    mut reg: ResMut<Register>,
+    pallet_assets: Res<PalletAssets>,
+    detail_assets: Res<DetailAssets>,
+    mode: Res<TransportMode>,
+) {
+    let Ok((station, pos)) = stations.get(trigger.station) else {
+        warn!("pallets can only be loaded on stations");
+        return;
+    };
+    let pos = pos.as_usize();
+    if reg.occupied(pos) {
+        warn!("no pallet loaded, register position {pos} is taken");
+        return;
+    }
+    let id = pallets
+        .iter()
+        .map(|pallet| pallet.id.0 + 1)
+        .max()
+        .unwrap_or_default();
+    let id = PalletId(id);
+    let transform = Transform::from_translation(station.transform_point(Vec3::Y * PALLET_Y))
+        .with_rotation(station.rotation());
+    let mut pallet = cmd.spawn((
+        Pallet::new(id),
+        Name::new(format!("pallet {}", id.0)),
+        Mesh3d(pallet_assets.mesh.clone()),
+        MeshMaterial3d(pallet_assets.material.clone()),
+        transform,
+        mode.carried_body(),
+        Friction::new(WHEEL_FRICTION),
+        Collider::cuboid(PALLET_SIZE.x, PALLET_SIZE.y, PALLET_SIZE.z),
+        ColliderDensity(PALLET_DENSITY),
+        // conveyors and sensors treat pallets like details
+        CollisionLayers::new(PhysLayer::Detail, LayerMask::ALL),
+        CollidingEntities::default(),
+        LinearVelocity::ZERO,
+    ));
+    if trigger.with_detail {
+        // no body of its own, the collider is part of the pallet
+        pallet.with_child((
+            DetailBundle::new(&detail_assets),
+            Name::new(format!("detail on pallet {}", id.0)),
+            Transform::from_xyz(0.0, (PALLET_SIZE.y + 0.3) / 2.0, 0.0),
+        ));
+        reg.details[pos] = Some(DetailState::default());
+    }
+    reg.pallets[pos] = Some(id);
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+/// Take a pallet off the line with the details on it, the register is left
+/// to the caller
+pub struct RemovePallet(pub PalletId);
+
+fn on_remove_pallet(
+    trigger: On<RemovePallet>,
+    mut cmd: Commands,
+    pallets: Query<(Entity, &Pallet)>,
+) {
+    let id = trigger.0;
+    for (entity, _) in pallets.iter().filter(|(_, pallet)| pallet.id == id) {
+        cmd.entity(entity).despawn();
+    }
+}
+
+/// Move a pallet handed to a station far away onto the infeed of that station
+fn on_return_pallet(
+    trigger: On<ShiftOver>,
+    stations: Query<
+        (
+            &GlobalTransform,
+            &RegisterPosition,
+            Option<&RegisterZones>,
+            Option<&ConveyorPath>,
+        ),
+        With<TransportBana>,
+    >,
+    mut pallets: Query<(&Pallet, &mut Transform)>,
+    reg: Res<Register>,
+) {
+    let Ok([(_, from_pos, zones, _), (to, to_pos, _, path)]) =
+        stations.get_many([trigger.from, trigger.to])
+    else {
+        return;
+    };
+    // the register may have shifted already, observers run in any order
+    let Some(id) = reg
+        .pallet_at(from_pos.discharge(zones))
+        .or(reg.pallet_at(to_pos.as_usize()))
+    else {
+        return;
+    };
+    let Some((_, mut transform)) = pallets.iter_mut().find(|(pallet, _)| pallet.id == id) else {
+        return;
+    };
+    if transform.translation.distance(to.translation()) < RETURN_DISTANCE {
+        return;
+    }
+    // segments start at the station origin, a straight bed is centered on it
+    let start = path.map_or(Transform::from_xyz(0.0, 0.0, -1.0), |path| path.pose(0.0));
+    // behind the back end fotocell, the receiving station runs it in
+    let infeed = Vec3::new(0.0, PALLET_Y, 1.0 - (PALLET_SIZE.z / 2.0 + 0.1));
+    transform.translation = to.transform_point(start.transform_point(infeed));
+    transform.rotation = to.rotation() * start.rotation;
+}
@@ -1 +1,424 @@
-use bevy::prelude::*;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::{
+    io::{Dio, Io, IoAddress, IoDevices},
+    reload::{modified, reload_due, LoadErrors},
+    sysorder::{InitSet, ScanSet},
+};
+
+pub mod fb;
+pub mod ladder;
+pub mod sfc;
+pub mod st;
+pub mod wasm;
+
+use ladder::LadderProgram;
+use sfc::SfcProgram;
+use st::StProgram;
+use wasm::WasmProgram;
+
+/// Structured text (`.st`), ladder (`.ld`), step sequence (`.sfc`) and WebAssembly (`.wasm`)
+/// programs in this directory are loaded at startup and reloaded when they change
+pub const PROGRAM_DIR: &str = "programs";
+
+pub struct PlcPlugin;
+
+impl Plugin for PlcPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TagTable>();
+        app.init_resource::<ProgramFiles>();
+        app.add_systems(Startup, sync_tags.in_set(InitSet::LoadAssets));
+        app.add_systems(Startup, sync_programs.in_set(InitSet::Spawn));
+        app.add_systems(Update, (sync_tags, sync_programs).run_if(reload_due));
+        app.add_systems(
+            FixedUpdate,
+            (
+                run_st_programs,
+                run_ladder_programs,
+                run_sfc_programs,
+                run_wasm_programs,
+            )
+                .chain()
+                .in_set(ScanSet::Control),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Value of a PLC variable
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    Time(Duration),
+}
+
+impl Value {
+    pub fn type_name(self) -> &'static str {
+        match self {
+            Value::Bool(_) => "BOOL",
+            Value::Int(_) => "INT",
+            Value::Real(_) => "REAL",
+            Value::Time(_) => "TIME",
+        }
+    }
+    pub fn as_bool(self) -> Option<bool> {
+        match self {
+            Value::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+    pub fn as_int(self) -> Option<i64> {
+        match self {
+            Value::Int(v) => Some(v),
+            _ => None,
+        }
+    }
+    pub fn as_real(self) -> Option<f64> {
+        match self {
+            Value::Real(v) => Some(v),
+            Value::Int(v) => Some(v as f64),
+            _ => None,
+        }
+    }
+    pub fn as_time(self) -> Option<Duration> {
+        match self {
+            Value::Time(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(v) => write!(f, "{}", if *v { "TRUE" } else { "FALSE" }),
+            Value::Int(v) => write!(f, "{v}"),
+            Value::Real(v) => write!(f, "{v:?}"),
+            Value::Time(v) => write!(f, "T#{}ms", v.as_millis()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// What a symbolic tag refers to
+pub enum Tag {
+    Input(Dio),
+    Output(Dio),
+    /// internal memory, not wired to any IO
+    Memory(Value),
+}
+
+#[derive(Resource, Default, Debug)]
+/// Symbolic names shared by all control programs, case insensitive like IEC identifiers
+pub struct TagTable {
+    tags: HashMap<String, Tag>,
+}
+
+impl TagTable {
+    /// Parse a tag file, one `name address` or `name type` per line
+    ///
+    /// ```text
+    /// start_button  %I0.0
+    /// lamp          N1:%Q3.2
+    /// parts_done    INT
+    /// ```
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut table = Self::default();
+        for (idx, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            let Some((name, what)) = line.split_once(char::is_whitespace) else {
+                if line.is_empty() {
+                    continue;
+                }
+                return Err(format!("tags line {}: expected 'name address'", idx + 1).into());
+            };
+            let what = what.trim();
+            let tag = match what.to_uppercase().as_str() {
+                "BOOL" => Tag::Memory(Value::Bool(false)),
+                "INT" => Tag::Memory(Value::Int(0)),
+                "REAL" => Tag::Memory(Value::Real(0.0)),
+                "TIME" => Tag::Memory(Value::Time(Duration::ZERO)),
+                _ => {
+                    let (kind, dio) = parse_direct_address(what)
+                        .map_err(|err| format!("tags line {}: {err}", idx + 1))?;
+                    match kind {
+                        Io::Input => Tag::Input(dio),
+                        Io::Output => Tag::Output(dio),
+                    }
+                }
+            };
+            table.insert(name, tag);
+        }
+        Ok(table)
+    }
+    pub fn insert(&mut self, name: &str, tag: Tag) {
+        self.tags.insert(name.to_uppercase(), tag);
+    }
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        self.tags.get(&name.to_uppercase())
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Tag)> {
+        self.tags.iter()
+    }
+    pub fn read(&self, name: &str, io: &IoDevices) -> Result<Value> {
+        match self.get(name).ok_or(format!("unknown tag {name}"))? {
+            Tag::Input(dio) => read_bit(io, Io::Input, *dio),
+            Tag::Output(dio) => read_bit(io, Io::Output, *dio),
+            Tag::Memory(value) => Ok(*value),
+        }
+    }
+    pub fn write(&mut self, name: &str, value: Value, io: &mut IoDevices) -> Result<()> {
+        let tag = self
+            .tags
+            .get_mut(&name.to_uppercase())
+            .ok_or(format!("unknown tag {name}"))?;
+        match tag {
+            Tag::Input(_) => Err(format!("tag {name} is an input and can not be written").into()),
+            Tag::Output(dio) => {
+                let bit = value
+                    .as_bool()
+                    .ok_or(format!("tag {name} is BOOL, got {}", value.type_name()))?;
+                io.try_set_bit(Io::Output, *dio, bit)
+            }
+            Tag::Memory(old) => {
+                *old = value;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn read_bit(io: &IoDevices, kind: Io, dio: Dio) -> Result<Value> {
+    let bit = io
+        .get_bit(kind, dio)
+        .ok_or(format!("no {}", dio.address(kind)))?;
+    Ok(Value::Bool(bit))
+}
+
+/// Symbolic tags shared by all programs, reloaded when changed
+pub const TAG_FILE: &str = "programs/tags.txt";
+
+/// Load the tag file, memory tags that still exist keep their value
+fn sync_tags(
+    mut tags: ResMut<TagTable>,
+    mut errors: ResMut<LoadErrors>,
+    mut last: Local<Option<SystemTime>>,
+) {
+    let modified = modified(TAG_FILE);
+    if modified.is_none() || modified == *last {
+        return;
+    }
+    *last = modified;
+    let loaded = fs::read_to_string(TAG_FILE)
+        .map_err(BevyError::from)
+        .and_then(|text| TagTable::parse(&text));
+    errors.report(Path::new(TAG_FILE), &loaded);
+    let Ok(mut loaded) = loaded else {
+        return;
+    };
+    for (name, tag) in loaded.tags.iter_mut() {
+        if let (Tag::Memory(new), Some(Tag::Memory(old))) = (tag, tags.tags.get(name)) {
+            if new.type_name() == old.type_name() {
+                *new = *old;
+            }
+        }
+    }
+    *tags = loaded;
+}
+
+/// `line` without its comment, a `#` only starts one at the start of the
+/// line or after whitespace so literals like `T#2s` stay whole
+/// ```
+/// # use cybercrab::plc::strip_comment;
+/// assert_eq!(strip_comment("XIC start TON t1 T#2s # settle"), "XIC start TON t1 T#2s ");
+/// assert_eq!(strip_comment("# a whole line"), "");
+/// ```
+pub fn strip_comment(line: &str) -> &str {
+    let mut before = None;
+    for (idx, c) in line.char_indices() {
+        if c == '#' && before.is_none_or(char::is_whitespace) {
+            return &line[..idx];
+        }
+        before = Some(c);
+    }
+    line
+}
+
+/// Parse a bit address like `%IX3.4`, `%Q0.1` or `N1:%I2.0`, see [`IoAddress`]
+pub fn parse_direct_address(text: &str) -> Result<(Io, Dio)> {
+    let address = IoAddress::parse(text)?;
+    let dio = address
+        .dio()
+        .ok_or(format!("{address} is not a bit address"))?;
+    Ok((address.kind, dio))
+}
+
+#[derive(Resource, Default, Debug)]
+/// Program files that have been loaded, to spot changes
+pub struct ProgramFiles {
+    files: HashMap<PathBuf, WatchedProgram>,
+}
+
+#[derive(Debug)]
+struct WatchedProgram {
+    modified: Option<SystemTime>,
+    /// the running program, kept when a changed file fails to load
+    entity: Option<Entity>,
+}
+
+fn program_paths() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(PROGRAM_DIR) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| {
+                ["st", "ld", "sfc", "wasm"]
+                    .iter()
+                    .any(|known| ext == *known)
+            })
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn spawn_program(cmd: &mut Commands, path: &Path) -> Result<Entity> {
+    let name = Name::new(program_name(path));
+    let extension = path.extension().unwrap_or_default();
+    let entity = if extension == "ld" {
+        cmd.spawn((name, LadderProgram::from_file(path)?)).id()
+    } else if extension == "sfc" {
+        cmd.spawn((name, SfcProgram::from_file(path)?)).id()
+    } else if extension == "wasm" {
+        cmd.spawn((name, WasmProgram::from_file(path)?)).id()
+    } else {
+        cmd.spawn((name, StProgram::from_file(path)?)).id()
+    };
+    Ok(entity)
+}
+
+/// Load new and changed programs, drop the ones whose file is gone
+fn sync_programs(
+    mut cmd: Commands,
+    mut programs: ResMut<ProgramFiles>,
+    mut errors: ResMut<LoadErrors>,
+) {
+    let paths = program_paths();
+    programs.files.retain(|path, watched| {
+        let keep = paths.contains(path);
+        if !keep {
+            if let Some(entity) = watched.entity {
+                cmd.entity(entity).despawn();
+            }
+            errors.0.remove(path);
+        }
+        keep
+    });
+    for path in paths {
+        let modified = modified(&path);
+        let watched = programs
+            .files
+            .entry(path.clone())
+            .or_insert(WatchedProgram {
+                modified: None,
+                entity: None,
+            });
+        // loaded, or failed to load, and unchanged since
+        let seen = watched.entity.is_some() || watched.modified.is_some();
+        if seen && watched.modified == modified {
+            continue;
+        }
+        watched.modified = modified;
+        let loaded = spawn_program(&mut cmd, &path);
+        errors.report(&path, &loaded);
+        if let Ok(entity) = loaded {
+            if let Some(old) = watched.entity.replace(entity) {
+                cmd.entity(old).despawn();
+                info!("reloaded {}", path.display());
+            }
+        }
+    }
+}
+
+fn program_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn run_st_programs(
+    programs: Query<(&Name, &mut StProgram)>,
+    mut io: ResMut<IoDevices>,
+    mut tags: ResMut<TagTable>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    for (name, mut program) in programs {
+        if program.fault().is_some() {
+            continue;
+        }
+        if let Err(err) = program.scan(now, &mut io, &mut tags) {
+            error!("program {name} faulted: {err}");
+        }
+    }
+}
+
+fn run_ladder_programs(
+    programs: Query<(&Name, &mut LadderProgram)>,
+    mut io: ResMut<IoDevices>,
+    mut tags: ResMut<TagTable>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    for (name, mut program) in programs {
+        if program.fault().is_some() {
+            continue;
+        }
+        if let Err(err) = program.scan(now, &mut io, &mut tags) {
+            error!("ladder {name} faulted: {err}");
+        }
+    }
+}
+
+pub(crate) fn run_sfc_programs(
+    mut programs: Query<(&Name, &mut SfcProgram)>,
+    mut io: ResMut<IoDevices>,
+    mut tags: ResMut<TagTable>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    let snapshot = sfc::snapshot(programs.iter().map(|(_, program)| program));
+    for (name, mut program) in programs.iter_mut() {
+        if !program.enabled || program.fault().is_some() {
+            continue;
+        }
+        if let Err(err) = program.scan(now, &mut io, &mut tags, &snapshot) {
+            error!("sequence {name} faulted: {err}");
+        }
+    }
+}
+
+fn run_wasm_programs(
+    programs: Query<(&Name, &mut WasmProgram)>,
+    mut io: ResMut<IoDevices>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    for (name, mut program) in programs {
+        if program.fault().is_some() {
+            continue;
+        }
+        if let Err(err) = program.scan(now, &mut io) {
+            error!("wasm program {name} faulted: {err}");
+        }
+    }
+}
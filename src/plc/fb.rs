@@ -0,0 +1,386 @@
+//! Standard function blocks of IEC 61131-3
+//!
+//! All blocks are scan driven and run on simulated time: `now` is the
+//! elapsed simulation time at the moment the block is executed. Set the
+//! inputs and call `execute` once per scan, or use `call` which does both
+//! and returns the main output.
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default)]
+/// On-delay timer, `q` turns on once `input` has been on for `preset`
+///
+/// Dropping `input` while timing resets the timer, the next rising edge
+/// starts over from zero.
+/// ```
+/// # use std::time::Duration;
+/// # use cybercrab::plc::fb::Ton;
+/// let ms = Duration::from_millis;
+/// let mut ton = Ton::default();
+/// assert!(!ton.call(true, ms(100), ms(0)));
+/// assert!(!ton.call(true, ms(100), ms(99)));
+/// assert_eq!(ton.elapsed, ms(99));
+/// // reset during timing
+/// assert!(!ton.call(false, ms(100), ms(150)));
+/// assert_eq!(ton.elapsed, Duration::ZERO);
+/// assert!(!ton.call(true, ms(100), ms(200)));
+/// assert!(ton.call(true, ms(100), ms(300)));
+/// // elapsed stops at the preset
+/// assert!(ton.call(true, ms(100), ms(1000)));
+/// assert_eq!(ton.elapsed, ms(100));
+/// // a zero preset follows the input
+/// let mut ton = Ton::default();
+/// assert!(ton.call(true, Duration::ZERO, ms(5)));
+/// ```
+pub struct Ton {
+    pub input: bool,
+    pub preset: Duration,
+    pub q: bool,
+    pub elapsed: Duration,
+    start: Option<Duration>,
+}
+
+impl Ton {
+    pub fn execute(&mut self, now: Duration) {
+        if !self.input {
+            self.start = None;
+            self.q = false;
+            self.elapsed = Duration::ZERO;
+            return;
+        }
+        let start = *self.start.get_or_insert(now);
+        self.elapsed = now.saturating_sub(start).min(self.preset);
+        self.q = self.elapsed >= self.preset;
+    }
+    pub fn call(&mut self, input: bool, preset: Duration, now: Duration) -> bool {
+        self.input = input;
+        self.preset = preset;
+        self.execute(now);
+        self.q
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Off-delay timer, `q` stays on for `preset` after `input` turned off
+///
+/// Turning `input` back on while timing cancels the delay.
+/// ```
+/// # use std::time::Duration;
+/// # use cybercrab::plc::fb::Tof;
+/// let ms = Duration::from_millis;
+/// let mut tof = Tof::default();
+/// // nothing to delay before the first rising edge
+/// assert!(!tof.call(false, ms(100), ms(0)));
+/// assert!(tof.call(true, ms(100), ms(10)));
+/// assert!(tof.call(false, ms(100), ms(20)));
+/// assert!(tof.call(false, ms(100), ms(119)));
+/// // re-trigger during timing
+/// assert!(tof.call(true, ms(100), ms(130)));
+/// assert_eq!(tof.elapsed, Duration::ZERO);
+/// assert!(tof.call(false, ms(100), ms(140)));
+/// assert!(!tof.call(false, ms(100), ms(240)));
+/// // elapsed holds the preset until the input comes back
+/// assert_eq!(tof.elapsed, ms(100));
+/// ```
+pub struct Tof {
+    pub input: bool,
+    pub preset: Duration,
+    pub q: bool,
+    pub elapsed: Duration,
+    start: Option<Duration>,
+}
+
+impl Tof {
+    pub fn execute(&mut self, now: Duration) {
+        if self.input {
+            self.start = None;
+            self.q = true;
+            self.elapsed = Duration::ZERO;
+            return;
+        }
+        if !self.q {
+            return;
+        }
+        let start = *self.start.get_or_insert(now);
+        self.elapsed = now.saturating_sub(start).min(self.preset);
+        self.q = self.elapsed < self.preset;
+    }
+    pub fn call(&mut self, input: bool, preset: Duration, now: Duration) -> bool {
+        self.input = input;
+        self.preset = preset;
+        self.execute(now);
+        self.q
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Pulse timer, a rising edge of `input` turns `q` on for exactly `preset`
+///
+/// The pulse can not be re-triggered or cut short by the input. Once it is
+/// over, `elapsed` holds the preset until `input` goes off.
+/// ```
+/// # use std::time::Duration;
+/// # use cybercrab::plc::fb::Tp;
+/// let ms = Duration::from_millis;
+/// let mut tp = Tp::default();
+/// assert!(tp.call(true, ms(100), ms(0)));
+/// // dropping the input does not end the pulse
+/// assert!(tp.call(false, ms(100), ms(50)));
+/// // a new edge during the pulse does not restart it
+/// assert!(tp.call(true, ms(100), ms(60)));
+/// assert!(!tp.call(true, ms(100), ms(100)));
+/// assert_eq!(tp.elapsed, ms(100));
+/// // input still on, no new pulse
+/// assert!(!tp.call(true, ms(100), ms(300)));
+/// assert!(!tp.call(false, ms(100), ms(310)));
+/// assert_eq!(tp.elapsed, Duration::ZERO);
+/// assert!(tp.call(true, ms(100), ms(320)));
+/// ```
+pub struct Tp {
+    pub input: bool,
+    pub preset: Duration,
+    pub q: bool,
+    pub elapsed: Duration,
+    start: Option<Duration>,
+}
+
+impl Tp {
+    pub fn execute(&mut self, now: Duration) {
+        if let Some(start) = self.start {
+            self.elapsed = now.saturating_sub(start).min(self.preset);
+            if self.elapsed >= self.preset {
+                self.q = false;
+                if !self.input {
+                    self.start = None;
+                    self.elapsed = Duration::ZERO;
+                }
+            }
+            return;
+        }
+        if self.input {
+            self.start = Some(now);
+            self.elapsed = Duration::ZERO;
+            self.q = !self.preset.is_zero();
+        }
+    }
+    pub fn call(&mut self, input: bool, preset: Duration, now: Duration) -> bool {
+        self.input = input;
+        self.preset = preset;
+        self.execute(now);
+        self.q
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Rising edge detection, `q` is on for the one scan where `clk` turns on
+///
+/// Like the standard, a `clk` that is already on at the first scan counts
+/// as an edge.
+/// ```
+/// # use cybercrab::plc::fb::RTrig;
+/// let mut trig = RTrig::default();
+/// assert!(trig.call(true));
+/// assert!(!trig.call(true));
+/// assert!(!trig.call(false));
+/// assert!(trig.call(true));
+/// ```
+pub struct RTrig {
+    pub clk: bool,
+    pub q: bool,
+    mem: bool,
+}
+
+impl RTrig {
+    pub fn execute(&mut self) {
+        self.q = self.clk && !self.mem;
+        self.mem = self.clk;
+    }
+    pub fn call(&mut self, clk: bool) -> bool {
+        self.clk = clk;
+        self.execute();
+        self.q
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Falling edge detection, `q` is on for the one scan where `clk` turns off
+///
+/// Like the standard, a `clk` that is off at the first scan counts as an edge.
+/// ```
+/// # use cybercrab::plc::fb::FTrig;
+/// let mut trig = FTrig::default();
+/// assert!(trig.call(false));
+/// assert!(!trig.call(false));
+/// assert!(!trig.call(true));
+/// assert!(trig.call(false));
+/// ```
+pub struct FTrig {
+    pub clk: bool,
+    pub q: bool,
+    /// `not clk` of the last scan
+    mem: bool,
+}
+
+impl FTrig {
+    pub fn execute(&mut self) {
+        self.q = !self.clk && !self.mem;
+        self.mem = !self.clk;
+    }
+    pub fn call(&mut self, clk: bool) -> bool {
+        self.clk = clk;
+        self.execute();
+        self.q
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Up counter, counts rising edges of `cu`, `q` once `cv` reaches `pv`
+///
+/// `reset` wins over counting, an edge that arrives while resetting is lost.
+/// ```
+/// # use cybercrab::plc::fb::Ctu;
+/// let mut ctu = Ctu::default();
+/// assert!(!ctu.call(true, false, 2));
+/// // holding cu does not count
+/// assert!(!ctu.call(true, false, 2));
+/// ctu.call(false, false, 2);
+/// assert!(ctu.call(true, false, 2));
+/// assert_eq!(ctu.cv, 2);
+/// ctu.call(false, true, 2);
+/// assert!(!ctu.call(true, true, 2));
+/// assert_eq!(ctu.cv, 0);
+/// // the edge was taken during reset
+/// assert!(!ctu.call(true, false, 2));
+/// assert_eq!(ctu.cv, 0);
+/// ```
+pub struct Ctu {
+    pub cu: bool,
+    pub reset: bool,
+    pub pv: i64,
+    pub q: bool,
+    pub cv: i64,
+    edge: RTrig,
+}
+
+impl Ctu {
+    pub fn execute(&mut self) {
+        let rising = self.edge.call(self.cu);
+        if self.reset {
+            self.cv = 0;
+        } else if rising && self.cv < i64::MAX {
+            self.cv += 1;
+        }
+        self.q = self.cv >= self.pv;
+    }
+    pub fn call(&mut self, cu: bool, reset: bool, pv: i64) -> bool {
+        self.cu = cu;
+        self.reset = reset;
+        self.pv = pv;
+        self.execute();
+        self.q
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Down counter, `load` sets `cv` to `pv`, rising edges of `cd` count down
+/// and `q` is on at zero or below
+/// ```
+/// # use cybercrab::plc::fb::Ctd;
+/// let mut ctd = Ctd::default();
+/// // counts from zero, q is on right away
+/// assert!(ctd.call(false, false, 2));
+/// ctd.call(false, true, 2);
+/// assert_eq!(ctd.cv, 2);
+/// assert!(!ctd.call(true, false, 2));
+/// ctd.call(false, false, 2);
+/// assert!(ctd.call(true, false, 2));
+/// // keeps counting below zero
+/// ctd.call(false, false, 2);
+/// ctd.call(true, false, 2);
+/// assert_eq!(ctd.cv, -1);
+/// ```
+pub struct Ctd {
+    pub cd: bool,
+    pub load: bool,
+    pub pv: i64,
+    pub q: bool,
+    pub cv: i64,
+    edge: RTrig,
+}
+
+impl Ctd {
+    pub fn execute(&mut self) {
+        let rising = self.edge.call(self.cd);
+        if self.load {
+            self.cv = self.pv;
+        } else if rising && self.cv > i64::MIN {
+            self.cv -= 1;
+        }
+        self.q = self.cv <= 0;
+    }
+    pub fn call(&mut self, cd: bool, load: bool, pv: i64) -> bool {
+        self.cd = cd;
+        self.load = load;
+        self.pv = pv;
+        self.execute();
+        self.q
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Up/down counter with `qu` at `pv` or above and `qd` at zero or below
+///
+/// `reset` wins over `load`, which wins over counting. Rising edges on both
+/// `cu` and `cd` in the same scan cancel out.
+/// ```
+/// # use cybercrab::plc::fb::Ctud;
+/// let mut ctud = Ctud::default();
+/// ctud.pv = 2;
+/// ctud.cu = true;
+/// ctud.execute();
+/// assert_eq!(ctud.cv, 1);
+/// // both edges at once
+/// ctud.cu = false;
+/// ctud.execute();
+/// ctud.cu = true;
+/// ctud.cd = true;
+/// ctud.execute();
+/// assert_eq!(ctud.cv, 1);
+/// ctud.load = true;
+/// ctud.execute();
+/// assert!(ctud.qu && !ctud.qd);
+/// ctud.reset = true;
+/// ctud.execute();
+/// assert_eq!(ctud.cv, 0);
+/// assert!(!ctud.qu && ctud.qd);
+/// ```
+pub struct Ctud {
+    pub cu: bool,
+    pub cd: bool,
+    pub reset: bool,
+    pub load: bool,
+    pub pv: i64,
+    pub qu: bool,
+    pub qd: bool,
+    pub cv: i64,
+    up: RTrig,
+    down: RTrig,
+}
+
+impl Ctud {
+    pub fn execute(&mut self) {
+        let up = self.up.call(self.cu);
+        let down = self.down.call(self.cd);
+        if self.reset {
+            self.cv = 0;
+        } else if self.load {
+            self.cv = self.pv;
+        } else if up && !down && self.cv < i64::MAX {
+            self.cv += 1;
+        } else if down && !up && self.cv > i64::MIN {
+            self.cv -= 1;
+        }
+        self.qu = self.cv >= self.pv;
+        self.qd = self.cv <= 0;
+    }
+}
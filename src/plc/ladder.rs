@@ -0,0 +1,511 @@
+//! Ladder diagram programs
+//!
+//! One rung per line, elements are read left to right:
+//!
+//! ```text
+//! # start/stop with self holding
+//! motor: XIC %IX0.0 BST XIC %QX0.0 NXB XIC remote_start BND XIO %IX0.1 OTE %QX0.0
+//! XIC %IX0.2 TON t1 T#2s OTE %QX0.1
+//! XIC t1 CTU c1 5
+//! XIC %IX0.3 RES c1
+//! ```
+//!
+//! | mnemonic          | element                                   |
+//! |-------------------|-------------------------------------------|
+//! | `XIC op`          | normally open contact                     |
+//! | `XIO op`          | normally closed contact                   |
+//! | `OTE op`          | coil                                      |
+//! | `OTL op`/`OTU op` | set / reset coil                          |
+//! | `TON t pt`        | on-delay timer box, passes power when done|
+//! | `TOF t pt`        | off-delay timer box                       |
+//! | `TP t pt`         | pulse timer box                           |
+//! | `CTU c pv`        | up counter box, passes power when done    |
+//! | `RES name`        | reset a counter or timer                  |
+//! | `BST NXB BND`     | branch start, next branch, branch end     |
+//!
+//! Operands are direct addresses, tags from the [`TagTable`], timer or counter
+//! names (their done bit) or any other name for an internal relay.
+
+use std::{fs, path::Path, time::Duration};
+
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::{
+    io::{Dio, Io, IoDevices},
+    plc::{
+        fb::{Ctu, Tof, Ton, Tp},
+        parse_direct_address,
+        st::parse_time,
+        strip_comment, TagTable, Value,
+    },
+};
+
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Io(Io, Dio),
+    /// tag, timer, counter or internal relay, resolved when the rung runs
+    Name(String),
+}
+
+impl Operand {
+    /// A bit address like `%I0.3` or `N1:%Q2.0`, otherwise a name
+    pub(crate) fn parse(text: &str) -> Result<Self> {
+        if text.contains('%') {
+            let (kind, dio) = parse_direct_address(text)?;
+            return Ok(Operand::Io(kind, dio));
+        }
+        if text.is_empty() || !text.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("bad operand {text}").into());
+        }
+        Ok(Operand::Name(text.to_uppercase()))
+    }
+    pub fn label(&self) -> String {
+        match self {
+            Operand::Io(kind, dio) => dio.address(*kind).to_string(),
+            Operand::Name(name) => name.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoilKind {
+    Normal,
+    Set,
+    Reset,
+}
+
+#[derive(Debug, Clone)]
+pub enum ElementKind {
+    Contact {
+        operand: Operand,
+        negated: bool,
+    },
+    Coil {
+        operand: Operand,
+        kind: CoilKind,
+    },
+    OnDelay {
+        name: String,
+        preset: Duration,
+    },
+    OffDelay {
+        name: String,
+        preset: Duration,
+    },
+    Pulse {
+        name: String,
+        preset: Duration,
+    },
+    Counter {
+        name: String,
+        preset: i64,
+    },
+    Reset {
+        name: String,
+    },
+    /// parallel paths, power flows out if any path conducts
+    Branch(Vec<Vec<Element>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub kind: ElementKind,
+    /// power flows out of the element, as of the last scan
+    pub energized: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rung {
+    pub label: Option<String>,
+    pub line: usize,
+    pub elements: Vec<Element>,
+}
+
+#[derive(Debug, Clone)]
+enum Timer {
+    On(Ton),
+    Off(Tof),
+    Pulse(Tp),
+}
+
+impl Timer {
+    fn q(&self) -> bool {
+        match self {
+            Timer::On(t) => t.q,
+            Timer::Off(t) => t.q,
+            Timer::Pulse(t) => t.q,
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+/// Ladder program executed once per scan, rung by rung
+pub struct LadderProgram {
+    rungs: Vec<Rung>,
+    timers: HashMap<String, Timer>,
+    counters: HashMap<String, Ctu>,
+    relays: HashMap<String, bool>,
+    fault: Option<String>,
+}
+
+struct Tokens<'a> {
+    words: Vec<(usize, &'a str)>,
+    idx: usize,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(line: usize, text: &'a str) -> Self {
+        let words = text
+            .split_whitespace()
+            .map(|word| (word.as_ptr() as usize - text.as_ptr() as usize + 1, word))
+            .collect();
+        Self {
+            words,
+            idx: 0,
+            line,
+        }
+    }
+    fn next(&mut self) -> Option<&'a str> {
+        let word = self.words.get(self.idx)?.1;
+        self.idx += 1;
+        Some(word)
+    }
+    fn peek(&self) -> Option<&'a str> {
+        self.words.get(self.idx).map(|(_, word)| *word)
+    }
+    fn error(&self, message: impl std::fmt::Display) -> BevyError {
+        let col = self
+            .words
+            .get(self.idx.saturating_sub(1))
+            .map(|(col, _)| *col)
+            .unwrap_or(1);
+        format!("{}:{}: {message}", self.line, col).into()
+    }
+    fn operand(&mut self) -> Result<Operand> {
+        let word = self.next().ok_or_else(|| self.error("missing operand"))?;
+        Operand::parse(word).map_err(|err| self.error(err))
+    }
+    fn name(&mut self) -> Result<String> {
+        match self.operand()? {
+            Operand::Name(name) => Ok(name),
+            Operand::Io(..) => Err(self.error("expected a timer or counter name")),
+        }
+    }
+    fn preset(&mut self) -> Result<Duration> {
+        let word = self.next().ok_or_else(|| self.error("missing preset"))?;
+        parse_time(word).ok_or_else(|| self.error(format!("bad time {word}, expected T#2s")))
+    }
+
+    /// Elements until the end of the line or the end of the current branch path
+    fn elements(&mut self, in_branch: bool) -> Result<Vec<Element>> {
+        let mut elements = Vec::new();
+        while let Some(word) = self.peek() {
+            let mnemonic = word.to_uppercase();
+            if in_branch && (mnemonic == "NXB" || mnemonic == "BND") {
+                return Ok(elements);
+            }
+            self.next();
+            let kind = match mnemonic.as_str() {
+                "XIC" | "XIO" => ElementKind::Contact {
+                    operand: self.operand()?,
+                    negated: mnemonic == "XIO",
+                },
+                "OTE" | "OTL" | "OTU" => {
+                    let operand = self.operand()?;
+                    if let Operand::Io(Io::Input, _) = operand {
+                        return Err(self.error("inputs can not be written"));
+                    }
+                    let kind = match mnemonic.as_str() {
+                        "OTL" => CoilKind::Set,
+                        "OTU" => CoilKind::Reset,
+                        _ => CoilKind::Normal,
+                    };
+                    ElementKind::Coil { operand, kind }
+                }
+                "TON" => ElementKind::OnDelay {
+                    name: self.name()?,
+                    preset: self.preset()?,
+                },
+                "TOF" => ElementKind::OffDelay {
+                    name: self.name()?,
+                    preset: self.preset()?,
+                },
+                "TP" => ElementKind::Pulse {
+                    name: self.name()?,
+                    preset: self.preset()?,
+                },
+                "CTU" => {
+                    let name = self.name()?;
+                    let word = self.next().ok_or_else(|| self.error("missing preset"))?;
+                    let preset = word
+                        .parse()
+                        .map_err(|_| self.error(format!("bad counter preset {word}")))?;
+                    ElementKind::Counter { name, preset }
+                }
+                "RES" => ElementKind::Reset { name: self.name()? },
+                "BST" => {
+                    let mut paths = vec![self.elements(true)?];
+                    loop {
+                        match self.next().map(|word| word.to_uppercase()).as_deref() {
+                            Some("NXB") => paths.push(self.elements(true)?),
+                            Some("BND") => break,
+                            _ => return Err(self.error("branch is missing BND")),
+                        }
+                    }
+                    ElementKind::Branch(paths)
+                }
+                "NXB" | "BND" => return Err(self.error(format!("{mnemonic} outside of a branch"))),
+                other => return Err(self.error(format!("unknown instruction {other}"))),
+            };
+            elements.push(Element {
+                kind,
+                energized: false,
+            });
+        }
+        if in_branch {
+            return Err(self.error("branch is missing BND"));
+        }
+        Ok(elements)
+    }
+}
+
+fn read_operand(
+    operand: &Operand,
+    io: &IoDevices,
+    tags: &TagTable,
+    timers: &HashMap<String, Timer>,
+    counters: &HashMap<String, Ctu>,
+    relays: &HashMap<String, bool>,
+) -> Result<bool> {
+    match operand {
+        Operand::Io(kind, dio) => io
+            .get_bit(*kind, *dio)
+            .ok_or_else(|| format!("no {}", dio.address(*kind)).into()),
+        Operand::Name(name) => {
+            if let Some(timer) = timers.get(name) {
+                return Ok(timer.q());
+            }
+            if let Some(counter) = counters.get(name) {
+                return Ok(counter.q);
+            }
+            if tags.get(name).is_some() {
+                let value = tags.read(name, io)?;
+                return value
+                    .as_bool()
+                    .ok_or_else(|| format!("tag {name} is not BOOL").into());
+            }
+            Ok(relays.get(name).copied().unwrap_or_default())
+        }
+    }
+}
+
+impl LadderProgram {
+    /// ```
+    /// # use cybercrab::plc::ladder::LadderProgram;
+    /// let source = "XIC %IX0.2 TON t1 T#2s OTE %QX0.1 # lamp after two seconds";
+    /// assert!(LadderProgram::parse(source).is_ok());
+    /// assert!(LadderProgram::parse("XIC %IX0.2 TON t1 T#1e300s OTE %QX0.1").is_err());
+    /// ```
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut rungs = Vec::new();
+        for (idx, line) in source.lines().enumerate() {
+            let line_nr = idx + 1;
+            let text = strip_comment(line);
+            if text.trim().is_empty() {
+                continue;
+            }
+            let (label, body) = match text.split_once(':') {
+                // a label is one word, addresses with a node prefix also hold a ':'
+                Some((label, body)) if !label.trim().contains(char::is_whitespace) => {
+                    (Some(label.trim().to_string()), body)
+                }
+                _ => (None, text),
+            };
+            let offset = text.len() - body.len();
+            let mut tokens = Tokens::new(line_nr, body);
+            for word in tokens.words.iter_mut() {
+                word.0 += offset;
+            }
+            let elements = tokens.elements(false)?;
+            rungs.push(Rung {
+                label,
+                line: line_nr,
+                elements,
+            });
+        }
+
+        let mut timers = HashMap::new();
+        let mut counters = HashMap::new();
+        fn collect(
+            elements: &[Element],
+            timers: &mut HashMap<String, Timer>,
+            counters: &mut HashMap<String, Ctu>,
+        ) {
+            for element in elements {
+                match &element.kind {
+                    ElementKind::OnDelay { name, .. } => {
+                        timers.insert(name.clone(), Timer::On(default()));
+                    }
+                    ElementKind::OffDelay { name, .. } => {
+                        timers.insert(name.clone(), Timer::Off(default()));
+                    }
+                    ElementKind::Pulse { name, .. } => {
+                        timers.insert(name.clone(), Timer::Pulse(default()));
+                    }
+                    ElementKind::Counter { name, .. } => {
+                        counters.insert(name.clone(), default());
+                    }
+                    ElementKind::Branch(paths) => {
+                        for path in paths {
+                            collect(path, timers, counters);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        for rung in rungs.iter() {
+            collect(&rung.elements, &mut timers, &mut counters);
+        }
+        Ok(Self {
+            rungs,
+            timers,
+            counters,
+            relays: HashMap::new(),
+            fault: None,
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn rungs(&self) -> &[Rung] {
+        &self.rungs
+    }
+
+    pub fn fault(&self) -> Option<&str> {
+        self.fault.as_deref()
+    }
+
+    pub fn scan(&mut self, now: Duration, io: &mut IoDevices, tags: &mut TagTable) -> Result<()> {
+        let Self {
+            rungs,
+            timers,
+            counters,
+            relays,
+            fault,
+        } = self;
+        let mut ctx = RungScan {
+            now,
+            io,
+            tags,
+            timers,
+            counters,
+            relays,
+        };
+        for rung in rungs.iter_mut() {
+            if let Err(err) = ctx.path(&mut rung.elements, true) {
+                let err = format!("rung at line {}: {err}", rung.line);
+                *fault = Some(err.clone());
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+struct RungScan<'a> {
+    now: Duration,
+    io: &'a mut IoDevices,
+    tags: &'a mut TagTable,
+    timers: &'a mut HashMap<String, Timer>,
+    counters: &'a mut HashMap<String, Ctu>,
+    relays: &'a mut HashMap<String, bool>,
+}
+
+impl RungScan<'_> {
+    fn read(&self, operand: &Operand) -> Result<bool> {
+        read_operand(
+            operand,
+            self.io,
+            self.tags,
+            self.timers,
+            self.counters,
+            self.relays,
+        )
+    }
+
+    fn write(&mut self, operand: &Operand, value: bool) -> Result<()> {
+        match operand {
+            Operand::Io(kind, dio) => self.io.try_set_bit(*kind, *dio, value),
+            Operand::Name(name) if self.tags.get(name).is_some() => {
+                self.tags.write(name, Value::Bool(value), self.io)
+            }
+            Operand::Name(name) => {
+                self.relays.insert(name.clone(), value);
+                Ok(())
+            }
+        }
+    }
+
+    /// Power flow through elements in series, returns the power at the right end
+    fn path(&mut self, elements: &mut [Element], mut power: bool) -> Result<bool> {
+        for element in elements.iter_mut() {
+            power = match &mut element.kind {
+                ElementKind::Contact { operand, negated } => {
+                    power && (self.read(operand)? != *negated)
+                }
+                ElementKind::Coil { operand, kind } => {
+                    match (*kind, power) {
+                        (CoilKind::Normal, _) => self.write(operand, power)?,
+                        (CoilKind::Set, true) => self.write(operand, true)?,
+                        (CoilKind::Reset, true) => self.write(operand, false)?,
+                        _ => (),
+                    }
+                    power
+                }
+                ElementKind::OnDelay { name, preset } => match self.timers.get_mut(name) {
+                    Some(Timer::On(ton)) => ton.call(power, *preset, self.now),
+                    _ => false,
+                },
+                ElementKind::OffDelay { name, preset } => match self.timers.get_mut(name) {
+                    Some(Timer::Off(tof)) => tof.call(power, *preset, self.now),
+                    _ => false,
+                },
+                ElementKind::Pulse { name, preset } => match self.timers.get_mut(name) {
+                    Some(Timer::Pulse(tp)) => tp.call(power, *preset, self.now),
+                    _ => false,
+                },
+                ElementKind::Counter { name, preset } => match self.counters.get_mut(name) {
+                    Some(ctu) => ctu.call(power, false, *preset),
+                    None => false,
+                },
+                ElementKind::Reset { name } => {
+                    if power {
+                        if let Some(ctu) = self.counters.get_mut(name) {
+                            ctu.call(false, true, ctu.pv);
+                        }
+                        match self.timers.get_mut(name) {
+                            Some(Timer::On(ton)) => *ton = default(),
+                            Some(Timer::Off(tof)) => *tof = default(),
+                            Some(Timer::Pulse(tp)) => *tp = default(),
+                            None => (),
+                        }
+                    }
+                    power
+                }
+                ElementKind::Branch(paths) => {
+                    let mut out = false;
+                    for path in paths.iter_mut() {
+                        out |= self.path(path, power)?;
+                    }
+                    out
+                }
+            };
+            element.energized = power;
+        }
+        Ok(power)
+    }
+}
@@ -0,0 +1,608 @@
+//! Sequential function charts (GRAFCET)
+//!
+//! ```text
+//! initial Idle
+//! step Filling
+//!     N %QX0.0
+//!     S done_once
+//! step Full
+//!     R done_once
+//! transition Idle -> Filling : %IX0.0 AND NOT %IX0.1
+//! transition Filling -> Full : %IX0.1 OR Filling.T >= T#5s
+//! transition Full -> Idle : NOT %IX0.1
+//! ```
+//!
+//! Action lines under a step use the qualifiers `N` (while active), `S` (set)
+//! and `R` (reset), followed by one or more operands. Transitions may join or
+//! fork several steps with `A, B -> C`. Conditions combine operands with
+//! `AND`, `OR`, `NOT` and parentheses, `Step.X` is true while a step is active,
+//! `Chart.Step.X` looks into another chart and `Step.T` compares the time a
+//! step has been active. All transitions see the steps as they were at the
+//! start of the scan, so charts can hand shake with each other.
+
+use std::{cmp::Ordering, fs, path::Path, time::Duration};
+
+use bevy::{
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+
+use crate::{
+    io::{Io, IoDevices},
+    plc::{ladder::Operand, st::parse_time, strip_comment, TagTable, Value},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Qualifier {
+    N,
+    S,
+    R,
+}
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub qualifier: Qualifier,
+    pub operands: Vec<Operand>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub name: String,
+    pub initial: bool,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug, Clone)]
+enum Cond {
+    Const(bool),
+    Operand(Operand),
+    Active(usize),
+    /// step of another chart, resolved by name at scan time
+    Remote {
+        chart: String,
+        step: String,
+    },
+    Time {
+        step: usize,
+        order: Ordering,
+        or_equal: bool,
+        preset: Duration,
+    },
+    Not(Box<Cond>),
+    And(Vec<Cond>),
+    Or(Vec<Cond>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub from: Vec<usize>,
+    pub to: Vec<usize>,
+    /// condition as written, for display
+    pub text: String,
+    cond: Cond,
+}
+
+/// Active steps of every chart at the start of the scan, by chart name
+pub type ChartSnapshot = HashMap<String, HashSet<String>>;
+
+#[derive(Component, Debug, Clone)]
+/// Step sequence executed once per scan
+pub struct SfcProgram {
+    name: String,
+    steps: Vec<Step>,
+    transitions: Vec<Transition>,
+    active: Vec<bool>,
+    /// when each active step was entered
+    entered: Vec<Duration>,
+    stored: HashMap<String, bool>,
+    flags: HashMap<String, bool>,
+    /// charts that are not enabled sit in their initial steps and write nothing
+    pub enabled: bool,
+    fault: Option<String>,
+}
+
+fn line_error(line: usize, message: impl std::fmt::Display) -> BevyError {
+    format!("sfc line {line}: {message}").into()
+}
+
+fn split_cond(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() || "()<>=".contains(c) {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            let joins = c == '=' && words.last().is_some_and(|w| w == "<" || w == ">");
+            if joins {
+                words.last_mut().unwrap().push('=');
+            } else if !c.is_whitespace() {
+                words.push(c.to_string());
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+struct CondParser<'a> {
+    words: Vec<String>,
+    idx: usize,
+    steps: &'a [Step],
+    line: usize,
+}
+
+impl CondParser<'_> {
+    fn peek(&self) -> Option<String> {
+        self.words.get(self.idx).map(|word| word.to_uppercase())
+    }
+    fn next(&mut self) -> Option<String> {
+        let word = self.words.get(self.idx).cloned();
+        self.idx += 1;
+        word
+    }
+    fn step(&self, name: &str) -> Result<usize> {
+        self.steps
+            .iter()
+            .position(|step| step.name == name)
+            .ok_or_else(|| line_error(self.line, format!("unknown step {name}")))
+    }
+    fn or(&mut self) -> Result<Cond> {
+        let mut terms = vec![self.and()?];
+        while self.peek().as_deref() == Some("OR") {
+            self.next();
+            terms.push(self.and()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.pop().unwrap()
+        } else {
+            Cond::Or(terms)
+        })
+    }
+    fn and(&mut self) -> Result<Cond> {
+        let mut terms = vec![self.factor()?];
+        while self.peek().as_deref() == Some("AND") {
+            self.next();
+            terms.push(self.factor()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.pop().unwrap()
+        } else {
+            Cond::And(terms)
+        })
+    }
+    fn factor(&mut self) -> Result<Cond> {
+        let word = self
+            .next()
+            .ok_or_else(|| line_error(self.line, "condition ends early"))?;
+        match word.to_uppercase().as_str() {
+            "NOT" => return Ok(Cond::Not(Box::new(self.factor()?))),
+            "TRUE" => return Ok(Cond::Const(true)),
+            "FALSE" => return Ok(Cond::Const(false)),
+            "(" => {
+                let cond = self.or()?;
+                if self.next().as_deref() != Some(")") {
+                    return Err(line_error(self.line, "missing )"));
+                }
+                return Ok(cond);
+            }
+            _ => (),
+        }
+        let upper = word.to_uppercase();
+        let parts: Vec<&str> = upper.split('.').collect();
+        match parts.as_slice() {
+            [step, "X"] => Ok(Cond::Active(self.step(step)?)),
+            [chart, step, "X"] => Ok(Cond::Remote {
+                chart: chart.to_string(),
+                step: step.to_string(),
+            }),
+            [step, "T"] => {
+                let step = self.step(step)?;
+                let (order, or_equal) = match self.next().as_deref() {
+                    Some(">") => (Ordering::Greater, false),
+                    Some(">=") => (Ordering::Greater, true),
+                    Some("<") => (Ordering::Less, false),
+                    Some("<=") => (Ordering::Less, true),
+                    _ => return Err(line_error(self.line, "expected a comparison after .T")),
+                };
+                let preset = self
+                    .next()
+                    .as_deref()
+                    .and_then(parse_time)
+                    .ok_or_else(|| line_error(self.line, "expected a time like T#2s"))?;
+                Ok(Cond::Time {
+                    step,
+                    order,
+                    or_equal,
+                    preset,
+                })
+            }
+            _ if word.contains('%') || !word.contains('.') => Operand::parse(&word)
+                .map(Cond::Operand)
+                .map_err(|err| line_error(self.line, err)),
+            _ => Err(line_error(self.line, format!("bad operand {word}"))),
+        }
+    }
+}
+
+struct ScanCtx<'a> {
+    io: &'a IoDevices,
+    tags: &'a TagTable,
+    flags: &'a HashMap<String, bool>,
+    snapshot: &'a ChartSnapshot,
+    active: &'a [bool],
+    entered: &'a [Duration],
+    now: Duration,
+}
+
+impl ScanCtx<'_> {
+    fn eval(&self, cond: &Cond) -> Result<bool> {
+        Ok(match cond {
+            Cond::Const(value) => *value,
+            Cond::Operand(Operand::Io(kind, dio)) => self
+                .io
+                .get_bit(*kind, *dio)
+                .ok_or_else(|| format!("no {}", dio.address(*kind)))?,
+            Cond::Operand(Operand::Name(name)) => {
+                if self.tags.get(name).is_some() {
+                    self.tags
+                        .read(name, self.io)?
+                        .as_bool()
+                        .ok_or(format!("tag {name} is not BOOL"))?
+                } else {
+                    self.flags.get(name).copied().unwrap_or_default()
+                }
+            }
+            Cond::Active(step) => self.active[*step],
+            Cond::Remote { chart, step } => self
+                .snapshot
+                .get(chart)
+                .is_some_and(|steps| steps.contains(step)),
+            Cond::Time {
+                step,
+                order,
+                or_equal,
+                preset,
+            } => {
+                let time = if self.active[*step] {
+                    self.now.saturating_sub(self.entered[*step])
+                } else {
+                    Duration::ZERO
+                };
+                let cmp = time.cmp(preset);
+                cmp == *order || (*or_equal && cmp == Ordering::Equal)
+            }
+            Cond::Not(cond) => !self.eval(cond)?,
+            Cond::And(conds) => {
+                for cond in conds {
+                    if !self.eval(cond)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Cond::Or(conds) => {
+                for cond in conds {
+                    if self.eval(cond)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+        })
+    }
+}
+
+impl SfcProgram {
+    /// ```
+    /// # use cybercrab::plc::sfc::SfcProgram;
+    /// let source = "
+    /// initial Idle
+    /// step Wait # settle before going on
+    /// transition Idle -> Wait : %IX0.0
+    /// transition Wait -> Idle : Wait.T >= T#5s
+    /// ";
+    /// assert!(SfcProgram::parse("settle", source).is_ok());
+    /// ```
+    pub fn parse(name: &str, source: &str) -> Result<Self> {
+        let mut steps: Vec<Step> = Vec::new();
+        let mut pending = Vec::new();
+        for (idx, line) in source.lines().enumerate() {
+            let line_nr = idx + 1;
+            let text = strip_comment(line).trim();
+            let Some((keyword, rest)) = text
+                .split_once(char::is_whitespace)
+                .or((!text.is_empty()).then_some((text, "")))
+            else {
+                continue;
+            };
+            let rest = rest.trim();
+            match keyword.to_uppercase().as_str() {
+                kw @ ("INITIAL" | "STEP") => {
+                    let name = rest.to_uppercase();
+                    if name.is_empty() || name.contains(|c: char| !c.is_alphanumeric() && c != '_')
+                    {
+                        return Err(line_error(line_nr, format!("bad step name '{rest}'")));
+                    }
+                    if steps.iter().any(|step| step.name == name) {
+                        return Err(line_error(
+                            line_nr,
+                            format!("step {name} is declared twice"),
+                        ));
+                    }
+                    steps.push(Step {
+                        name,
+                        initial: kw == "INITIAL",
+                        actions: Vec::new(),
+                    });
+                }
+                q @ ("N" | "S" | "R") => {
+                    let step = steps
+                        .last_mut()
+                        .ok_or_else(|| line_error(line_nr, "action outside of a step"))?;
+                    let mut operands = Vec::new();
+                    for word in rest.split_whitespace() {
+                        let operand =
+                            Operand::parse(word).map_err(|err| line_error(line_nr, err))?;
+                        if let Operand::Io(Io::Input, _) = operand {
+                            return Err(line_error(line_nr, "inputs can not be written"));
+                        }
+                        operands.push(operand);
+                    }
+                    if operands.is_empty() {
+                        return Err(line_error(line_nr, "action without operands"));
+                    }
+                    let qualifier = match q {
+                        "N" => Qualifier::N,
+                        "S" => Qualifier::S,
+                        _ => Qualifier::R,
+                    };
+                    step.actions.push(Action {
+                        qualifier,
+                        operands,
+                    });
+                }
+                "TRANSITION" => pending.push((line_nr, rest.to_string())),
+                other => return Err(line_error(line_nr, format!("unknown keyword {other}"))),
+            }
+        }
+        if !steps.iter().any(|step| step.initial) {
+            return Err("sfc has no initial step".into());
+        }
+
+        let mut transitions = Vec::new();
+        for (line, text) in pending {
+            let (arrow, cond) = text
+                .split_once(':')
+                .ok_or_else(|| line_error(line, "expected 'A -> B : condition'"))?;
+            let (from, to) = arrow
+                .split_once("->")
+                .ok_or_else(|| line_error(line, "expected 'A -> B : condition'"))?;
+            let lookup = |names: &str| -> Result<Vec<usize>> {
+                names
+                    .split(',')
+                    .map(|name| {
+                        let name = name.trim().to_uppercase();
+                        steps
+                            .iter()
+                            .position(|step| step.name == name)
+                            .ok_or_else(|| line_error(line, format!("unknown step {name}")))
+                    })
+                    .collect()
+            };
+            let (from, to) = (lookup(from)?, lookup(to)?);
+            let mut parser = CondParser {
+                words: split_cond(cond),
+                idx: 0,
+                steps: &steps,
+                line,
+            };
+            let parsed = parser.or()?;
+            if let Some(extra) = parser.next() {
+                return Err(line_error(line, format!("unexpected {extra}")));
+            }
+            transitions.push(Transition {
+                from,
+                to,
+                text: cond.trim().to_string(),
+                cond: parsed,
+            });
+        }
+
+        let active = steps.iter().map(|step| step.initial).collect();
+        Ok(Self {
+            name: name.to_uppercase(),
+            entered: vec![Duration::ZERO; steps.len()],
+            steps,
+            transitions,
+            active,
+            stored: HashMap::new(),
+            flags: HashMap::new(),
+            enabled: true,
+            fault: None,
+        })
+    }
+
+    /// Fill in `$param` placeholders before parsing, longest names first
+    pub fn from_template(name: &str, template: &str, params: &[(&str, String)]) -> Result<Self> {
+        let mut params = params.to_vec();
+        params.sort_by_key(|(key, _)| std::cmp::Reverse(key.len()));
+        let mut source = template.to_string();
+        for (key, value) in params {
+            source = source.replace(&format!("${key}"), &value);
+        }
+        if let Some(idx) = source.find('$') {
+            let param: String = source[idx..]
+                .chars()
+                .take_while(|c| !c.is_whitespace())
+                .collect();
+            return Err(format!("template parameter {param} is not set").into());
+        }
+        Self::parse(name, &source)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::parse(&name, &fs::read_to_string(path)?)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn transitions(&self) -> &[Transition] {
+        &self.transitions
+    }
+
+    pub fn is_active(&self, step: usize) -> bool {
+        self.active[step]
+    }
+
+    pub fn active_steps(&self) -> impl Iterator<Item = &str> {
+        self.steps
+            .iter()
+            .zip(&self.active)
+            .filter(|(_, active)| **active)
+            .map(|(step, _)| step.name.as_str())
+    }
+
+    /// Time the step has been active, zero when it is not
+    pub fn step_time(&self, step: usize, now: Duration) -> Duration {
+        if self.active[step] {
+            now.saturating_sub(self.entered[step])
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    pub fn fault(&self) -> Option<&str> {
+        self.fault.as_deref()
+    }
+
+    /// Back to the initial steps with all stored actions cleared
+    pub fn reset(&mut self, now: Duration) {
+        for (active, step) in self.active.iter_mut().zip(&self.steps) {
+            *active = step.initial;
+        }
+        self.entered.fill(now);
+        self.stored.clear();
+    }
+
+    pub fn scan(
+        &mut self,
+        now: Duration,
+        io: &mut IoDevices,
+        tags: &mut TagTable,
+        snapshot: &ChartSnapshot,
+    ) -> Result<()> {
+        let result = self.try_scan(now, io, tags, snapshot);
+        if let Err(err) = &result {
+            self.fault = Some(err.to_string());
+        }
+        result
+    }
+
+    fn try_scan(
+        &mut self,
+        now: Duration,
+        io: &mut IoDevices,
+        tags: &mut TagTable,
+        snapshot: &ChartSnapshot,
+    ) -> Result<()> {
+        // evaluate every transition on the state at the start of the scan
+        let ctx = ScanCtx {
+            io,
+            tags,
+            flags: &self.flags,
+            snapshot,
+            active: &self.active,
+            entered: &self.entered,
+            now,
+        };
+        let mut fire = Vec::new();
+        for (idx, transition) in self.transitions.iter().enumerate() {
+            let enabled = transition.from.iter().all(|step| self.active[*step]);
+            if enabled && ctx.eval(&transition.cond)? {
+                fire.push(idx);
+            }
+        }
+        let mut left = vec![false; self.steps.len()];
+        for idx in fire {
+            let transition = &self.transitions[idx];
+            // an earlier transition already took one of the steps
+            if transition.from.iter().any(|step| left[*step]) {
+                continue;
+            }
+            for step in transition.from.iter() {
+                left[*step] = true;
+                self.active[*step] = false;
+            }
+            for step in transition.to.iter() {
+                self.active[*step] = true;
+                self.entered[*step] = now;
+            }
+        }
+
+        // actions, a reset wins over a set in the same scan
+        let mut outputs: HashMap<String, (Operand, bool)> = HashMap::new();
+        let mut resets = Vec::new();
+        for (step, active) in self.steps.iter().zip(&self.active) {
+            for action in step.actions.iter() {
+                for operand in action.operands.iter() {
+                    let key = operand.label();
+                    let value = outputs
+                        .entry(key.clone())
+                        .or_insert((operand.clone(), false));
+                    if !active {
+                        continue;
+                    }
+                    match action.qualifier {
+                        Qualifier::N => value.1 = true,
+                        Qualifier::S => {
+                            self.stored.insert(key, true);
+                        }
+                        Qualifier::R => resets.push(key),
+                    }
+                }
+            }
+        }
+        for key in resets {
+            self.stored.remove(&key);
+        }
+        for (key, (operand, n_active)) in outputs {
+            let value = n_active || self.stored.contains_key(&key);
+            match operand {
+                Operand::Io(kind, dio) => io.try_set_bit(kind, dio, value)?,
+                Operand::Name(name) if tags.get(&name).is_some() => {
+                    tags.write(&name, Value::Bool(value), io)?
+                }
+                Operand::Name(name) => {
+                    self.flags.insert(name, value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collect the active steps of all charts before any of them runs
+pub fn snapshot<'a>(charts: impl Iterator<Item = &'a SfcProgram>) -> ChartSnapshot {
+    charts
+        .map(|chart| {
+            let steps = chart.active_steps().map(str::to_string).collect();
+            (chart.name.clone(), steps)
+        })
+        .collect()
+}
@@ -0,0 +1,65 @@
+//! Structured Text interpreter
+//!
+//! Covers the practical subset of IEC 61131-3 ST used for station logic:
+//! `VAR`/`VAR_EXTERNAL` blocks, `BOOL`/`INT`/`REAL`/`TIME` variables,
+//! `IF`/`CASE`/`FOR` statements and the standard function blocks from [`super::fb`].
+//! Variables are bound to IO with `AT %IX0.3` or to symbolic tags by declaring
+//! them in a `VAR_EXTERNAL` block.
+
+use std::{fmt, time::Duration};
+
+mod interp;
+mod lexer;
+mod parser;
+
+pub use interp::StProgram;
+
+/// Time literal like `T#2s`, `T#1m30s` or `TIME#500us`, shared by all the languages
+/// ```
+/// # use std::time::Duration;
+/// # use cybercrab::plc::st::parse_time;
+/// assert_eq!(parse_time("T#1m30s"), Some(Duration::from_secs(90)));
+/// assert_eq!(parse_time("t#2h"), Some(Duration::from_secs(7200)));
+/// assert_eq!(parse_time("TIME#500us"), Some(Duration::from_micros(500)));
+/// assert_eq!(parse_time("T#1.5s"), Some(Duration::from_millis(1500)));
+/// assert_eq!(parse_time("T#2x"), None);
+/// assert_eq!(parse_time("2s"), None);
+/// ```
+pub fn parse_time(word: &str) -> Option<Duration> {
+    let (prefix, value) = word.split_once('#')?;
+    if !prefix.eq_ignore_ascii_case("T") && !prefix.eq_ignore_ascii_case("TIME") {
+        return None;
+    }
+    lexer::time_value(value).ok()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Position in the source, both counted from 1
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Parse or runtime error with the position it refers to
+pub struct StError {
+    pub pos: Pos,
+    pub message: String,
+}
+
+impl StError {
+    pub(crate) fn new(pos: Pos, message: impl Into<String>) -> Self {
+        Self {
+            pos,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for StError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.col, self.message)
+    }
+}
+
+impl std::error::Error for StError {}
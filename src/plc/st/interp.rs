@@ -0,0 +1,777 @@
+use std::{fs, path::Path, time::Duration};
+
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use super::{
+    parser::{parse, BinOp, Binding, Expr, FbKind, Program, Stmt, UnOp, VarRef, VarType},
+    Pos, StError,
+};
+use crate::{
+    io::{Dio, Io, IoDevices},
+    plc::{fb, Tag, TagTable, Value},
+};
+
+/// Statements one scan may execute before it is considered stuck
+const MAX_STEPS_PER_SCAN: usize = 100_000;
+
+#[derive(Debug, Clone)]
+enum FbInstance {
+    Ton(fb::Ton),
+    Tof(fb::Tof),
+    Tp(fb::Tp),
+    RTrig(fb::RTrig),
+    FTrig(fb::FTrig),
+    Ctu(fb::Ctu),
+    Ctd(fb::Ctd),
+    Ctud(fb::Ctud),
+}
+
+impl FbInstance {
+    fn new(kind: FbKind) -> Self {
+        match kind {
+            FbKind::Ton => FbInstance::Ton(default()),
+            FbKind::Tof => FbInstance::Tof(default()),
+            FbKind::Tp => FbInstance::Tp(default()),
+            FbKind::RTrig => FbInstance::RTrig(default()),
+            FbKind::FTrig => FbInstance::FTrig(default()),
+            FbKind::Ctu => FbInstance::Ctu(default()),
+            FbKind::Ctd => FbInstance::Ctd(default()),
+            FbKind::Ctud => FbInstance::Ctud(default()),
+        }
+    }
+    fn get(&self, member: &str) -> Option<Value> {
+        Some(match (self, member) {
+            (FbInstance::Ton(t), "IN") => Value::Bool(t.input),
+            (FbInstance::Ton(t), "PT") => Value::Time(t.preset),
+            (FbInstance::Ton(t), "Q") => Value::Bool(t.q),
+            (FbInstance::Ton(t), "ET") => Value::Time(t.elapsed),
+            (FbInstance::Tof(t), "IN") => Value::Bool(t.input),
+            (FbInstance::Tof(t), "PT") => Value::Time(t.preset),
+            (FbInstance::Tof(t), "Q") => Value::Bool(t.q),
+            (FbInstance::Tof(t), "ET") => Value::Time(t.elapsed),
+            (FbInstance::Tp(t), "IN") => Value::Bool(t.input),
+            (FbInstance::Tp(t), "PT") => Value::Time(t.preset),
+            (FbInstance::Tp(t), "Q") => Value::Bool(t.q),
+            (FbInstance::Tp(t), "ET") => Value::Time(t.elapsed),
+            (FbInstance::RTrig(t), "CLK") => Value::Bool(t.clk),
+            (FbInstance::RTrig(t), "Q") => Value::Bool(t.q),
+            (FbInstance::FTrig(t), "CLK") => Value::Bool(t.clk),
+            (FbInstance::FTrig(t), "Q") => Value::Bool(t.q),
+            (FbInstance::Ctu(c), "CU") => Value::Bool(c.cu),
+            (FbInstance::Ctu(c), "R") => Value::Bool(c.reset),
+            (FbInstance::Ctu(c), "PV") => Value::Int(c.pv),
+            (FbInstance::Ctu(c), "Q") => Value::Bool(c.q),
+            (FbInstance::Ctu(c), "CV") => Value::Int(c.cv),
+            (FbInstance::Ctd(c), "CD") => Value::Bool(c.cd),
+            (FbInstance::Ctd(c), "LD") => Value::Bool(c.load),
+            (FbInstance::Ctd(c), "PV") => Value::Int(c.pv),
+            (FbInstance::Ctd(c), "Q") => Value::Bool(c.q),
+            (FbInstance::Ctd(c), "CV") => Value::Int(c.cv),
+            (FbInstance::Ctud(c), "CU") => Value::Bool(c.cu),
+            (FbInstance::Ctud(c), "CD") => Value::Bool(c.cd),
+            (FbInstance::Ctud(c), "R") => Value::Bool(c.reset),
+            (FbInstance::Ctud(c), "LD") => Value::Bool(c.load),
+            (FbInstance::Ctud(c), "PV") => Value::Int(c.pv),
+            (FbInstance::Ctud(c), "QU") => Value::Bool(c.qu),
+            (FbInstance::Ctud(c), "QD") => Value::Bool(c.qd),
+            (FbInstance::Ctud(c), "CV") => Value::Int(c.cv),
+            _ => return None,
+        })
+    }
+    /// Set an input, the value is already coerced to the member type
+    fn set(&mut self, member: &str, value: Value) {
+        match (self, member, value) {
+            (FbInstance::Ton(t), "IN", Value::Bool(v)) => t.input = v,
+            (FbInstance::Ton(t), "PT", Value::Time(v)) => t.preset = v,
+            (FbInstance::Tof(t), "IN", Value::Bool(v)) => t.input = v,
+            (FbInstance::Tof(t), "PT", Value::Time(v)) => t.preset = v,
+            (FbInstance::Tp(t), "IN", Value::Bool(v)) => t.input = v,
+            (FbInstance::Tp(t), "PT", Value::Time(v)) => t.preset = v,
+            (FbInstance::RTrig(t), "CLK", Value::Bool(v)) => t.clk = v,
+            (FbInstance::FTrig(t), "CLK", Value::Bool(v)) => t.clk = v,
+            (FbInstance::Ctu(c), "CU", Value::Bool(v)) => c.cu = v,
+            (FbInstance::Ctu(c), "R", Value::Bool(v)) => c.reset = v,
+            (FbInstance::Ctu(c), "PV", Value::Int(v)) => c.pv = v,
+            (FbInstance::Ctd(c), "CD", Value::Bool(v)) => c.cd = v,
+            (FbInstance::Ctd(c), "LD", Value::Bool(v)) => c.load = v,
+            (FbInstance::Ctd(c), "PV", Value::Int(v)) => c.pv = v,
+            (FbInstance::Ctud(c), "CU", Value::Bool(v)) => c.cu = v,
+            (FbInstance::Ctud(c), "CD", Value::Bool(v)) => c.cd = v,
+            (FbInstance::Ctud(c), "R", Value::Bool(v)) => c.reset = v,
+            (FbInstance::Ctud(c), "LD", Value::Bool(v)) => c.load = v,
+            (FbInstance::Ctud(c), "PV", Value::Int(v)) => c.pv = v,
+            _ => (),
+        }
+    }
+    fn execute(&mut self, now: Duration) {
+        match self {
+            FbInstance::Ton(t) => t.execute(now),
+            FbInstance::Tof(t) => t.execute(now),
+            FbInstance::Tp(t) => t.execute(now),
+            FbInstance::RTrig(t) => t.execute(),
+            FbInstance::FTrig(t) => t.execute(),
+            FbInstance::Ctu(c) => c.execute(),
+            FbInstance::Ctd(c) => c.execute(),
+            FbInstance::Ctud(c) => c.execute(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Slot {
+    Value(VarType, Value),
+    Fb(FbKind, FbInstance),
+}
+
+fn default_value(ty: VarType) -> Value {
+    match ty {
+        VarType::Bool | VarType::Fb(_) => Value::Bool(false),
+        VarType::Int => Value::Int(0),
+        VarType::Real => Value::Real(0.0),
+        VarType::Time => Value::Time(Duration::ZERO),
+    }
+}
+
+/// Convert a value to the type of the variable it is stored in
+fn coerce(value: Value, ty: VarType, pos: Pos) -> Result<Value, StError> {
+    let coerced = match (ty, value) {
+        (VarType::Bool, Value::Bool(_))
+        | (VarType::Int, Value::Int(_))
+        | (VarType::Real, Value::Real(_))
+        | (VarType::Time, Value::Time(_)) => Some(value),
+        (VarType::Int, Value::Real(v)) => Some(Value::Int(v.round() as i64)),
+        (VarType::Real, Value::Int(v)) => Some(Value::Real(v as f64)),
+        _ => None,
+    };
+    coerced.ok_or_else(|| {
+        StError::new(
+            pos,
+            format!(
+                "type mismatch, expected {} got {}",
+                ty.name(),
+                value.type_name()
+            ),
+        )
+    })
+}
+
+struct Scan {
+    now: Duration,
+    steps: usize,
+}
+
+struct Vars<'a> {
+    slots: &'a mut HashMap<String, Slot>,
+    io: &'a mut IoDevices,
+}
+
+impl Vars<'_> {
+    fn read(&self, var: &VarRef) -> Result<Value, StError> {
+        match (self.slots.get(&var.name), &var.member) {
+            (Some(Slot::Value(_, value)), None) => Ok(*value),
+            (Some(Slot::Fb(_, fb)), Some(member)) => fb
+                .get(member)
+                .ok_or_else(|| StError::new(var.pos, format!("{} has no {member}", var.name))),
+            (Some(Slot::Fb(..)), None) => Err(StError::new(
+                var.pos,
+                format!("{} is a function block, use one of its members", var.name),
+            )),
+            (Some(Slot::Value(..)), Some(member)) => Err(StError::new(
+                var.pos,
+                format!("{} has no member {member}", var.name),
+            )),
+            (None, _) => Err(StError::new(
+                var.pos,
+                format!("unknown variable {}", var.name),
+            )),
+        }
+    }
+    fn write(&mut self, var: &VarRef, value: Value) -> Result<(), StError> {
+        match (self.slots.get_mut(&var.name), &var.member) {
+            (Some(Slot::Value(ty, old)), None) => {
+                *old = coerce(value, *ty, var.pos)?;
+                Ok(())
+            }
+            (Some(Slot::Fb(kind, fb)), Some(member)) => match kind.member(member) {
+                Some((ty, true)) => {
+                    fb.set(member, coerce(value, ty, var.pos)?);
+                    Ok(())
+                }
+                Some((_, false)) => Err(StError::new(
+                    var.pos,
+                    format!("{}.{member} is an output and can not be written", var.name),
+                )),
+                None => Err(StError::new(
+                    var.pos,
+                    format!("{} has no {member}", var.name),
+                )),
+            },
+            _ => Err(StError::new(
+                var.pos,
+                format!("{} can not be written", var.name),
+            )),
+        }
+    }
+}
+
+fn eval(expr: &Expr, vars: &Vars) -> Result<Value, StError> {
+    match expr {
+        Expr::Lit(value) => Ok(*value),
+        Expr::Var(var) => vars.read(var),
+        Expr::Direct(kind, dio, pos) => vars
+            .io
+            .get_bit(*kind, *dio)
+            .map(Value::Bool)
+            .ok_or_else(|| StError::new(*pos, no_io(*kind, *dio))),
+        Expr::Unary(op, operand, pos) => {
+            let value = eval(operand, vars)?;
+            match (op, value) {
+                (UnOp::Not, Value::Bool(v)) => Ok(Value::Bool(!v)),
+                (UnOp::Not, Value::Int(v)) => Ok(Value::Int(!v)),
+                (UnOp::Neg, Value::Int(v)) => v
+                    .checked_neg()
+                    .map(Value::Int)
+                    .ok_or_else(|| StError::new(*pos, "negation overflowed")),
+                (UnOp::Neg, Value::Real(v)) => Ok(Value::Real(-v)),
+                _ => Err(StError::new(
+                    *pos,
+                    format!("{op:?} is not defined for {}", value.type_name()),
+                )),
+            }
+        }
+        Expr::Binary(op, lhs, rhs, pos) => {
+            let lhs = eval(lhs, vars)?;
+            let rhs = eval(rhs, vars)?;
+            binary(*op, lhs, rhs, *pos)
+        }
+    }
+}
+
+fn binary(op: BinOp, lhs: Value, rhs: Value, pos: Pos) -> Result<Value, StError> {
+    use Value::*;
+    let result = match (op, lhs, rhs) {
+        (BinOp::Or, Bool(a), Bool(b)) => Some(Bool(a || b)),
+        (BinOp::Xor, Bool(a), Bool(b)) => Some(Bool(a ^ b)),
+        (BinOp::And, Bool(a), Bool(b)) => Some(Bool(a && b)),
+        (BinOp::Or, Int(a), Int(b)) => Some(Int(a | b)),
+        (BinOp::Xor, Int(a), Int(b)) => Some(Int(a ^ b)),
+        (BinOp::And, Int(a), Int(b)) => Some(Int(a & b)),
+        (BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge, ..) => {
+            let ordering = match (lhs, rhs) {
+                (Bool(a), Bool(b)) => Some(a.cmp(&b)),
+                (Int(a), Int(b)) => Some(a.cmp(&b)),
+                (Time(a), Time(b)) => Some(a.cmp(&b)),
+                (Real(_) | Int(_), Real(_) | Int(_)) => lhs.as_real().partial_cmp(&rhs.as_real()),
+                _ => None,
+            };
+            ordering.map(|ordering| {
+                Bool(match op {
+                    BinOp::Eq => ordering.is_eq(),
+                    BinOp::Ne => ordering.is_ne(),
+                    BinOp::Lt => ordering.is_lt(),
+                    BinOp::Le => ordering.is_le(),
+                    BinOp::Gt => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                })
+            })
+        }
+        (BinOp::Add, Int(a), Int(b)) => a.checked_add(b).map(Int),
+        (BinOp::Sub, Int(a), Int(b)) => a.checked_sub(b).map(Int),
+        (BinOp::Mul, Int(a), Int(b)) => a.checked_mul(b).map(Int),
+        (BinOp::Div | BinOp::Mod, Int(_), Int(0)) => {
+            return Err(StError::new(pos, "division by zero"))
+        }
+        (BinOp::Div, Int(a), Int(b)) => a.checked_div(b).map(Int),
+        (BinOp::Mod, Int(a), Int(b)) => a.checked_rem(b).map(Int),
+        (BinOp::Add, Time(a), Time(b)) => a.checked_add(b).map(Time),
+        (BinOp::Sub, Time(a), Time(b)) => Some(Time(a.saturating_sub(b))),
+        (BinOp::Mul, Time(a), Int(b)) => u32::try_from(b)
+            .ok()
+            .and_then(|b| a.checked_mul(b))
+            .map(Time),
+        (BinOp::Mul, Time(a), Real(b)) => Duration::try_from_secs_f64(a.as_secs_f64() * b)
+            .ok()
+            .map(Time),
+        (BinOp::Div, Time(a), Int(b)) => u32::try_from(b)
+            .ok()
+            .and_then(|b| a.checked_div(b))
+            .map(Time),
+        (BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div, Real(_) | Int(_), Real(_) | Int(_)) => {
+            let (a, b) = (lhs.as_real().unwrap(), rhs.as_real().unwrap());
+            Some(Real(match op {
+                BinOp::Add => a + b,
+                BinOp::Sub => a - b,
+                BinOp::Mul => a * b,
+                _ => a / b,
+            }))
+        }
+        _ => None,
+    };
+    result.ok_or_else(|| {
+        StError::new(
+            pos,
+            format!(
+                "{op:?} is not defined for {} and {} or overflowed",
+                lhs.type_name(),
+                rhs.type_name()
+            ),
+        )
+    })
+}
+
+fn exec(stmts: &[Stmt], vars: &mut Vars, scan: &mut Scan) -> Result<(), StError> {
+    for stmt in stmts {
+        scan.steps += 1;
+        if scan.steps > MAX_STEPS_PER_SCAN {
+            return Err(StError::new(
+                stmt_pos(stmt),
+                format!("scan exceeded {MAX_STEPS_PER_SCAN} statements, endless loop?"),
+            ));
+        }
+        match stmt {
+            Stmt::Assign { target, value } => {
+                let value = eval(value, vars)?;
+                vars.write(target, value)?;
+            }
+            Stmt::AssignDirect { dio, value, pos } => {
+                let value = eval(value, vars)?;
+                let bit = coerce(value, VarType::Bool, *pos)?
+                    .as_bool()
+                    .unwrap_or_default();
+                vars.io
+                    .try_set_bit(Io::Output, *dio, bit)
+                    .map_err(|err| StError::new(*pos, err.to_string()))?;
+            }
+            Stmt::Call {
+                instance,
+                inputs,
+                outputs,
+            } => {
+                for (param, expr) in inputs {
+                    let value = eval(expr, vars)?;
+                    let member = VarRef {
+                        member: Some(param.clone()),
+                        ..instance.clone()
+                    };
+                    vars.write(&member, value)?;
+                }
+                match vars.slots.get_mut(&instance.name) {
+                    Some(Slot::Fb(_, fb)) => fb.execute(scan.now),
+                    _ => {
+                        return Err(StError::new(
+                            instance.pos,
+                            format!("{} is not a function block", instance.name),
+                        ))
+                    }
+                }
+                for (param, target) in outputs {
+                    let member = VarRef {
+                        member: Some(param.clone()),
+                        ..instance.clone()
+                    };
+                    let value = vars.read(&member)?;
+                    vars.write(target, value)?;
+                }
+            }
+            Stmt::If {
+                branches,
+                otherwise,
+            } => {
+                let mut taken = None;
+                for (condition, body) in branches {
+                    if eval_bool(condition, vars)? {
+                        taken = Some(body);
+                        break;
+                    }
+                }
+                exec(taken.unwrap_or(otherwise), vars, scan)?;
+            }
+            Stmt::Case {
+                selector,
+                arms,
+                otherwise,
+            } => {
+                let value = eval(selector, vars)?;
+                let Value::Int(value) = value else {
+                    return Err(StError::new(
+                        expr_pos(selector),
+                        format!("CASE needs an INT, got {}", value.type_name()),
+                    ));
+                };
+                let body = arms
+                    .iter()
+                    .find(|(labels, _)| labels.iter().any(|label| label.matches(value)))
+                    .map(|(_, body)| body)
+                    .unwrap_or(otherwise);
+                exec(body, vars, scan)?;
+            }
+            Stmt::For {
+                var,
+                from,
+                to,
+                by,
+                body,
+            } => {
+                let int = |expr: &Expr, vars: &Vars| -> Result<i64, StError> {
+                    let value = eval(expr, vars)?;
+                    value.as_int().ok_or_else(|| {
+                        StError::new(
+                            expr_pos(expr),
+                            format!("FOR needs INT bounds, got {}", value.type_name()),
+                        )
+                    })
+                };
+                let mut i = int(from, vars)?;
+                let to = int(to, vars)?;
+                let by = match by {
+                    Some(by) => int(by, vars)?,
+                    None => 1,
+                };
+                if by == 0 {
+                    return Err(StError::new(var.pos, "FOR step can not be 0"));
+                }
+                while (by > 0 && i <= to) || (by < 0 && i >= to) {
+                    scan.steps += 1;
+                    if scan.steps > MAX_STEPS_PER_SCAN {
+                        return Err(StError::new(
+                            var.pos,
+                            format!("scan exceeded {MAX_STEPS_PER_SCAN} statements, endless loop?"),
+                        ));
+                    }
+                    vars.write(var, Value::Int(i))?;
+                    exec(body, vars, scan)?;
+                    let Some(next) = i.checked_add(by) else {
+                        break;
+                    };
+                    i = next;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn eval_bool(expr: &Expr, vars: &Vars) -> Result<bool, StError> {
+    let value = eval(expr, vars)?;
+    value.as_bool().ok_or_else(|| {
+        StError::new(
+            expr_pos(expr),
+            format!("condition must be BOOL, got {}", value.type_name()),
+        )
+    })
+}
+
+fn expr_pos(expr: &Expr) -> Pos {
+    match expr {
+        Expr::Lit(_) => Pos::default(),
+        Expr::Var(var) => var.pos,
+        Expr::Direct(.., pos) | Expr::Unary(.., pos) | Expr::Binary(.., pos) => *pos,
+    }
+}
+
+fn stmt_pos(stmt: &Stmt) -> Pos {
+    match stmt {
+        Stmt::Assign { target, .. } => target.pos,
+        Stmt::AssignDirect { pos, .. } => *pos,
+        Stmt::Call { instance, .. } => instance.pos,
+        Stmt::If { branches, .. } => branches
+            .first()
+            .map(|(condition, _)| expr_pos(condition))
+            .unwrap_or_default(),
+        Stmt::Case { selector, .. } => expr_pos(selector),
+        Stmt::For { var, .. } => var.pos,
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+/// A Structured Text program that is executed once per scan
+pub struct StProgram {
+    program: Program,
+    slots: HashMap<String, Slot>,
+    fault: Option<StError>,
+}
+
+impl StProgram {
+    pub fn from_source(source: &str) -> Result<Self, StError> {
+        let program = parse(source)?;
+        let mut slots = HashMap::new();
+        for decl in program.vars.iter() {
+            let slot = match decl.ty {
+                VarType::Fb(kind) => Slot::Fb(kind, FbInstance::new(kind)),
+                ty => Slot::Value(ty, default_value(ty)),
+            };
+            slots.insert(decl.name.clone(), slot);
+        }
+        let mut st = Self {
+            program,
+            slots,
+            fault: None,
+        };
+        st.check()?;
+        st.init_values()?;
+        Ok(st)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let source = fs::read_to_string(path)?;
+        Ok(Self::from_source(&source)?)
+    }
+
+    /// Name after `PROGRAM`
+    pub fn name(&self) -> &str {
+        &self.program.name
+    }
+
+    /// The error that stopped the program, it does not run again until reloaded
+    pub fn fault(&self) -> Option<&StError> {
+        self.fault.as_ref()
+    }
+
+    /// Current values of all plain variables, for monitoring
+    pub fn values(&self) -> impl Iterator<Item = (&str, Value)> {
+        self.program
+            .vars
+            .iter()
+            .filter_map(|decl| match self.slots.get(&decl.name) {
+                Some(Slot::Value(_, value)) => Some((decl.name.as_str(), *value)),
+                _ => None,
+            })
+    }
+
+    fn init_values(&mut self) -> Result<(), StError> {
+        let mut io = IoDevices::default();
+        let mut vars = Vars {
+            slots: &mut self.slots,
+            io: &mut io,
+        };
+        for decl in self.program.vars.iter() {
+            let Some(init) = &decl.init else {
+                continue;
+            };
+            let value = eval(init, &vars)?;
+            let target = VarRef {
+                name: decl.name.clone(),
+                member: None,
+                pos: decl.pos,
+            };
+            vars.write(&target, value)?;
+        }
+        Ok(())
+    }
+
+    /// Report references to unknown variables before the program ever runs
+    fn check(&self) -> Result<(), StError> {
+        let check_var = |var: &VarRef, write: bool| -> Result<(), StError> {
+            let decl = self
+                .program
+                .vars
+                .iter()
+                .find(|decl| decl.name == var.name)
+                .ok_or_else(|| StError::new(var.pos, format!("unknown variable {}", var.name)))?;
+            match (decl.ty, &var.member) {
+                (VarType::Fb(kind), Some(member)) => match kind.member(member) {
+                    Some((_, writable)) if writable || !write => Ok(()),
+                    Some(_) => Err(StError::new(
+                        var.pos,
+                        format!("{}.{member} is an output and can not be written", var.name),
+                    )),
+                    None => Err(StError::new(
+                        var.pos,
+                        format!("{} has no {member}", decl.ty.name()),
+                    )),
+                },
+                (VarType::Fb(_), None) => Err(StError::new(
+                    var.pos,
+                    format!("{} is a function block, use one of its members", var.name),
+                )),
+                (_, Some(member)) => Err(StError::new(
+                    var.pos,
+                    format!("{} is {} and has no {member}", var.name, decl.ty.name()),
+                )),
+                (_, None) if write && matches!(decl.binding, Binding::Io(Io::Input, _)) => {
+                    Err(StError::new(
+                        var.pos,
+                        format!("{} is an input and can not be written", var.name),
+                    ))
+                }
+                _ => Ok(()),
+            }
+        };
+        fn walk_expr(
+            expr: &Expr,
+            check_var: &dyn Fn(&VarRef, bool) -> Result<(), StError>,
+        ) -> Result<(), StError> {
+            match expr {
+                Expr::Lit(_) | Expr::Direct(..) => Ok(()),
+                Expr::Var(var) => check_var(var, false),
+                Expr::Unary(_, operand, _) => walk_expr(operand, check_var),
+                Expr::Binary(_, lhs, rhs, _) => {
+                    walk_expr(lhs, check_var)?;
+                    walk_expr(rhs, check_var)
+                }
+            }
+        }
+        fn walk(
+            stmts: &[Stmt],
+            check_var: &dyn Fn(&VarRef, bool) -> Result<(), StError>,
+        ) -> Result<(), StError> {
+            for stmt in stmts {
+                match stmt {
+                    Stmt::Assign { target, value } => {
+                        check_var(target, true)?;
+                        walk_expr(value, check_var)?;
+                    }
+                    Stmt::AssignDirect { value, .. } => walk_expr(value, check_var)?,
+                    Stmt::Call {
+                        instance,
+                        inputs,
+                        outputs,
+                    } => {
+                        for (param, expr) in inputs {
+                            let member = VarRef {
+                                member: Some(param.clone()),
+                                ..instance.clone()
+                            };
+                            check_var(&member, true)?;
+                            walk_expr(expr, check_var)?;
+                        }
+                        for (param, target) in outputs {
+                            let member = VarRef {
+                                member: Some(param.clone()),
+                                ..instance.clone()
+                            };
+                            check_var(&member, false)?;
+                            check_var(target, true)?;
+                        }
+                    }
+                    Stmt::If {
+                        branches,
+                        otherwise,
+                    } => {
+                        for (condition, body) in branches {
+                            walk_expr(condition, check_var)?;
+                            walk(body, check_var)?;
+                        }
+                        walk(otherwise, check_var)?;
+                    }
+                    Stmt::Case {
+                        selector,
+                        arms,
+                        otherwise,
+                    } => {
+                        walk_expr(selector, check_var)?;
+                        for (_, body) in arms {
+                            walk(body, check_var)?;
+                        }
+                        walk(otherwise, check_var)?;
+                    }
+                    Stmt::For {
+                        var,
+                        from,
+                        to,
+                        by,
+                        body,
+                    } => {
+                        check_var(var, true)?;
+                        walk_expr(from, check_var)?;
+                        walk_expr(to, check_var)?;
+                        if let Some(by) = by {
+                            walk_expr(by, check_var)?;
+                        }
+                        walk(body, check_var)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        for decl in self.program.vars.iter() {
+            if let Some(init) = &decl.init {
+                walk_expr(init, &check_var)?;
+            }
+        }
+        walk(&self.program.body, &check_var)
+    }
+
+    /// Read bound inputs, execute the body and write bound outputs
+    ///
+    /// `now` is the elapsed simulation time, used by the timers
+    ///
+    /// Every statement and loop iteration counts against a step limit, so a
+    /// runaway loop faults the program instead of hanging the scan
+    /// ```
+    /// # use std::time::Duration;
+    /// # use cybercrab::{io::IoDevices, plc::{st::StProgram, TagTable}};
+    /// let source = "PROGRAM Spin VAR i : INT; END_VAR
+    ///     FOR i := 0 TO 9223372036854775807 DO END_FOR;
+    /// END_PROGRAM";
+    /// let mut st = StProgram::from_source(source).unwrap();
+    /// let (mut io, mut tags) = (IoDevices::default(), TagTable::default());
+    /// let err = st.scan(Duration::ZERO, &mut io, &mut tags).unwrap_err();
+    /// assert!(err.message.contains("endless loop"));
+    /// assert!(st.fault().is_some());
+    /// ```
+    pub fn scan(
+        &mut self,
+        now: Duration,
+        io: &mut IoDevices,
+        tags: &mut TagTable,
+    ) -> Result<(), StError> {
+        let result = self.try_scan(now, io, tags);
+        if let Err(err) = &result {
+            self.fault = Some(err.clone());
+        }
+        result
+    }
+
+    fn try_scan(
+        &mut self,
+        now: Duration,
+        io: &mut IoDevices,
+        tags: &mut TagTable,
+    ) -> Result<(), StError> {
+        let Self { program, slots, .. } = self;
+        for decl in program.vars.iter() {
+            let value = match decl.binding {
+                Binding::Local => continue,
+                Binding::Io(kind, dio) => io
+                    .get_bit(kind, dio)
+                    .map(Value::Bool)
+                    .ok_or_else(|| StError::new(decl.pos, no_io(kind, dio)))?,
+                Binding::Tag => tags
+                    .read(&decl.name, io)
+                    .map_err(|err| StError::new(decl.pos, err.to_string()))?,
+            };
+            if let Some(Slot::Value(ty, old)) = slots.get_mut(&decl.name) {
+                *old = coerce(value, *ty, decl.pos)?;
+            }
+        }
+
+        let mut scan = Scan { now, steps: 0 };
+        let mut vars = Vars { slots, io };
+        exec(&program.body, &mut vars, &mut scan)?;
+
+        for decl in program.vars.iter() {
+            let Some(Slot::Value(_, value)) = vars.slots.get(&decl.name) else {
+                continue;
+            };
+            match decl.binding {
+                Binding::Io(Io::Output, dio) => {
+                    let bit = value.as_bool().unwrap_or_default();
+                    vars.io
+                        .try_set_bit(Io::Output, dio, bit)
+                        .map_err(|err| StError::new(decl.pos, err.to_string()))?;
+                }
+                Binding::Tag if !matches!(tags.get(&decl.name), Some(Tag::Input(_))) => {
+                    tags.write(&decl.name, *value, vars.io)
+                        .map_err(|err| StError::new(decl.pos, err.to_string()))?;
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn no_io(kind: Io, dio: Dio) -> String {
+    format!("no {}", dio.address(kind))
+}
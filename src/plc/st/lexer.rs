@@ -0,0 +1,278 @@
+use std::time::Duration;
+
+use super::{Pos, StError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Tok {
+    /// identifiers and keywords, upper cased
+    Ident(String),
+    Int(i64),
+    Real(f64),
+    Time(Duration),
+    /// direct address like `%IX0.3` or `N1:%IX0.3`
+    Address(String),
+    Assign,
+    Arrow,
+    Colon,
+    Semi,
+    Comma,
+    LParen,
+    RParen,
+    Dot,
+    DotDot,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Amp,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Token {
+    pub tok: Tok,
+    pub pos: Pos,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    idx: usize,
+    pos: Pos,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.idx).copied()
+    }
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.idx + offset).copied()
+    }
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.idx += 1;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.col = 1;
+        } else {
+            self.pos.col += 1;
+        }
+        Some(c)
+    }
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.peek().filter(|c| f(*c)) {
+            text.push(c);
+            self.bump();
+        }
+        text
+    }
+
+    fn skip_trivia(&mut self) -> Result<(), StError> {
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => {
+                    self.take_while(|c| c != '\n');
+                }
+                (Some('('), Some('*')) => {
+                    let start = self.pos;
+                    self.bump();
+                    self.bump();
+                    loop {
+                        match (self.peek(), self.peek_at(1)) {
+                            (Some('*'), Some(')')) => {
+                                self.bump();
+                                self.bump();
+                                break;
+                            }
+                            (Some(_), _) => {
+                                self.bump();
+                            }
+                            (None, _) => return Err(StError::new(start, "unterminated comment")),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn number(&mut self, start: Pos) -> Result<Tok, StError> {
+        let digits = self.take_while(|c| c.is_ascii_digit() || c == '_');
+        let digits = digits.replace('_', "");
+        if self.peek() == Some('#') {
+            // based literal like 16#FF
+            self.bump();
+            let radix: u32 = digits
+                .parse()
+                .map_err(|_| StError::new(start, "bad number base"))?;
+            if ![2, 8, 16].contains(&radix) {
+                return Err(StError::new(start, format!("unsupported base {radix}")));
+            }
+            let value = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            return i64::from_str_radix(&value.replace('_', ""), radix)
+                .map(Tok::Int)
+                .map_err(|_| StError::new(start, format!("bad base {radix} literal {value}")));
+        }
+        let is_real =
+            self.peek() == Some('.') && self.peek_at(1).is_some_and(|c| c.is_ascii_digit());
+        if !is_real {
+            return digits
+                .parse()
+                .map(Tok::Int)
+                .map_err(|_| StError::new(start, format!("integer {digits} out of range")));
+        }
+        self.bump();
+        let mut text = format!("{digits}.{}", self.take_while(|c| c.is_ascii_digit()));
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.bump();
+            text.push('e');
+            if let Some(sign @ ('+' | '-')) = self.peek() {
+                self.bump();
+                text.push(sign);
+            }
+            text += &self.take_while(|c| c.is_ascii_digit());
+        }
+        text.parse()
+            .map(Tok::Real)
+            .map_err(|_| StError::new(start, format!("bad real {text}")))
+    }
+
+    /// The part after `T#`, like `1m30s` or `1.5s`
+    fn time(&mut self, start: Pos) -> Result<Tok, StError> {
+        let text = self.take_while(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_');
+        time_value(&text)
+            .map(Tok::Time)
+            .map_err(|message| StError::new(start, message))
+    }
+
+    fn next_token(&mut self) -> Result<Token, StError> {
+        self.skip_trivia()?;
+        let pos = self.pos;
+        let Some(c) = self.peek() else {
+            return Ok(Token { tok: Tok::Eof, pos });
+        };
+        let tok = if c.is_ascii_alphabetic() || c == '_' {
+            let ident = self
+                .take_while(|c| c.is_ascii_alphanumeric() || c == '_')
+                .to_uppercase();
+            let node_prefix = ident.len() > 1
+                && ident.starts_with('N')
+                && ident[1..].chars().all(|c| c.is_ascii_digit());
+            if (ident == "T" || ident == "TIME") && self.peek() == Some('#') {
+                self.bump();
+                self.time(pos)?
+            } else if node_prefix && self.peek() == Some(':') && self.peek_at(1) == Some('%') {
+                // direct address on another node, like N1:%IX0.3
+                self.bump();
+                self.bump();
+                let address = self.take_while(|c| c.is_ascii_alphanumeric() || c == '.');
+                Tok::Address(format!("{ident}:%{address}"))
+            } else {
+                Tok::Ident(ident)
+            }
+        } else if c.is_ascii_digit() {
+            self.number(pos)?
+        } else if c == '%' {
+            self.bump();
+            let address = self.take_while(|c| c.is_ascii_alphanumeric() || c == '.');
+            Tok::Address(format!("%{address}"))
+        } else {
+            self.bump();
+            let next = self.peek();
+            let (tok, double) = match (c, next) {
+                (':', Some('=')) => (Tok::Assign, true),
+                (':', _) => (Tok::Colon, false),
+                ('=', Some('>')) => (Tok::Arrow, true),
+                ('=', _) => (Tok::Eq, false),
+                ('<', Some('>')) => (Tok::Ne, true),
+                ('<', Some('=')) => (Tok::Le, true),
+                ('<', _) => (Tok::Lt, false),
+                ('>', Some('=')) => (Tok::Ge, true),
+                ('>', _) => (Tok::Gt, false),
+                ('.', Some('.')) => (Tok::DotDot, true),
+                ('.', _) => (Tok::Dot, false),
+                (';', _) => (Tok::Semi, false),
+                (',', _) => (Tok::Comma, false),
+                ('(', _) => (Tok::LParen, false),
+                (')', _) => (Tok::RParen, false),
+                ('+', _) => (Tok::Plus, false),
+                ('-', _) => (Tok::Minus, false),
+                ('*', _) => (Tok::Star, false),
+                ('/', _) => (Tok::Slash, false),
+                ('&', _) => (Tok::Amp, false),
+                _ => return Err(StError::new(pos, format!("unexpected character '{c}'"))),
+            };
+            if double {
+                self.bump();
+            }
+            tok
+        };
+        Ok(Token { tok, pos })
+    }
+}
+
+/// Split source into tokens, the last one is always [`Tok::Eof`]
+pub(super) fn tokenize(source: &str) -> Result<Vec<Token>, StError> {
+    let mut lexer = Lexer {
+        chars: source.chars().collect(),
+        idx: 0,
+        pos: Pos { line: 1, col: 1 },
+    };
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token()?;
+        let done = token.tok == Tok::Eof;
+        tokens.push(token);
+        if done {
+            return Ok(tokens);
+        }
+    }
+}
+
+/// Value of a time literal without the `T#` prefix, like `1m30s` or `1.5s`
+pub(super) fn time_value(text: &str) -> Result<Duration, String> {
+    let mut rest = text;
+    let mut total = Duration::ZERO;
+    if rest.is_empty() {
+        return Err("empty time literal".into());
+    }
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let value: f64 = number
+            .replace('_', "")
+            .parse()
+            .map_err(|_| format!("bad time value {number}"))?;
+        let split = tail
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(split);
+        let secs = match unit.to_lowercase().as_str() {
+            "d" => 86_400.0,
+            "h" => 3_600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 1e-3,
+            "us" => 1e-6,
+            "ns" => 1e-9,
+            _ => return Err(format!("bad time unit '{unit}'")),
+        };
+        total = Duration::try_from_secs_f64(value * secs)
+            .ok()
+            .and_then(|part| total.checked_add(part))
+            .ok_or("time literal out of range")?;
+        rest = tail.trim_start_matches('_');
+    }
+    Ok(total)
+}
@@ -0,0 +1,717 @@
+use super::{
+    lexer::{tokenize, Tok, Token},
+    Pos, StError,
+};
+use crate::{
+    io::{Dio, Io},
+    plc::{parse_direct_address, Value},
+};
+
+const KEYWORDS: &[&str] = &[
+    "PROGRAM",
+    "END_PROGRAM",
+    "VAR",
+    "VAR_EXTERNAL",
+    "END_VAR",
+    "AT",
+    "IF",
+    "THEN",
+    "ELSIF",
+    "ELSE",
+    "END_IF",
+    "CASE",
+    "OF",
+    "END_CASE",
+    "FOR",
+    "TO",
+    "BY",
+    "DO",
+    "END_FOR",
+    "AND",
+    "OR",
+    "XOR",
+    "NOT",
+    "MOD",
+    "TRUE",
+    "FALSE",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FbKind {
+    Ton,
+    Tof,
+    Tp,
+    RTrig,
+    FTrig,
+    Ctu,
+    Ctd,
+    Ctud,
+}
+
+impl FbKind {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "TON" => FbKind::Ton,
+            "TOF" => FbKind::Tof,
+            "TP" => FbKind::Tp,
+            "R_TRIG" => FbKind::RTrig,
+            "F_TRIG" => FbKind::FTrig,
+            "CTU" => FbKind::Ctu,
+            "CTD" => FbKind::Ctd,
+            "CTUD" => FbKind::Ctud,
+            _ => return None,
+        })
+    }
+    /// Type of a member and if it is an input that may be written
+    pub fn member(self, name: &str) -> Option<(VarType, bool)> {
+        use VarType::*;
+        Some(match (self, name) {
+            (FbKind::Ton | FbKind::Tof | FbKind::Tp, "IN") => (Bool, true),
+            (FbKind::Ton | FbKind::Tof | FbKind::Tp, "PT") => (Time, true),
+            (FbKind::Ton | FbKind::Tof | FbKind::Tp, "Q") => (Bool, false),
+            (FbKind::Ton | FbKind::Tof | FbKind::Tp, "ET") => (Time, false),
+            (FbKind::RTrig | FbKind::FTrig, "CLK") => (Bool, true),
+            (FbKind::RTrig | FbKind::FTrig, "Q") => (Bool, false),
+            (FbKind::Ctu, "CU" | "R") => (Bool, true),
+            (FbKind::Ctd, "CD" | "LD") => (Bool, true),
+            (FbKind::Ctud, "CU" | "CD" | "R" | "LD") => (Bool, true),
+            (FbKind::Ctu | FbKind::Ctd | FbKind::Ctud, "PV") => (Int, true),
+            (FbKind::Ctu | FbKind::Ctd, "Q") => (Bool, false),
+            (FbKind::Ctud, "QU" | "QD") => (Bool, false),
+            (FbKind::Ctu | FbKind::Ctd | FbKind::Ctud, "CV") => (Int, false),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum VarType {
+    Bool,
+    Int,
+    Real,
+    Time,
+    Fb(FbKind),
+}
+
+impl VarType {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "BOOL" => VarType::Bool,
+            "INT" | "DINT" | "SINT" | "LINT" => VarType::Int,
+            "REAL" | "LREAL" => VarType::Real,
+            "TIME" => VarType::Time,
+            other => VarType::Fb(FbKind::from_name(other)?),
+        })
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            VarType::Bool => "BOOL",
+            VarType::Int => "INT",
+            VarType::Real => "REAL",
+            VarType::Time => "TIME",
+            VarType::Fb(FbKind::Ton) => "TON",
+            VarType::Fb(FbKind::Tof) => "TOF",
+            VarType::Fb(FbKind::Tp) => "TP",
+            VarType::Fb(FbKind::RTrig) => "R_TRIG",
+            VarType::Fb(FbKind::FTrig) => "F_TRIG",
+            VarType::Fb(FbKind::Ctu) => "CTU",
+            VarType::Fb(FbKind::Ctd) => "CTD",
+            VarType::Fb(FbKind::Ctud) => "CTUD",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Binding {
+    Local,
+    Io(Io, Dio),
+    /// `VAR_EXTERNAL`, bound to the tag with the same name
+    Tag,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct VarDecl {
+    pub name: String,
+    pub ty: VarType,
+    pub binding: Binding,
+    pub init: Option<Expr>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum UnOp {
+    Not,
+    Neg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BinOp {
+    Or,
+    Xor,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct VarRef {
+    pub name: String,
+    pub member: Option<String>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum Expr {
+    Lit(Value),
+    Var(VarRef),
+    Direct(Io, Dio, Pos),
+    Unary(UnOp, Box<Expr>, Pos),
+    Binary(BinOp, Box<Expr>, Box<Expr>, Pos),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum CaseLabel {
+    Value(i64),
+    Range(i64, i64),
+}
+
+impl CaseLabel {
+    pub fn matches(self, value: i64) -> bool {
+        match self {
+            CaseLabel::Value(label) => label == value,
+            CaseLabel::Range(from, to) => (from..=to).contains(&value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum Stmt {
+    Assign {
+        target: VarRef,
+        value: Expr,
+    },
+    AssignDirect {
+        dio: Dio,
+        value: Expr,
+        pos: Pos,
+    },
+    Call {
+        instance: VarRef,
+        inputs: Vec<(String, Expr)>,
+        outputs: Vec<(String, VarRef)>,
+    },
+    If {
+        branches: Vec<(Expr, Vec<Stmt>)>,
+        otherwise: Vec<Stmt>,
+    },
+    Case {
+        selector: Expr,
+        arms: Vec<(Vec<CaseLabel>, Vec<Stmt>)>,
+        otherwise: Vec<Stmt>,
+    },
+    For {
+        var: VarRef,
+        from: Expr,
+        to: Expr,
+        by: Option<Expr>,
+        body: Vec<Stmt>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Program {
+    pub name: String,
+    pub vars: Vec<VarDecl>,
+    pub body: Vec<Stmt>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    idx: usize,
+}
+
+type PResult<T> = Result<T, StError>;
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.idx].tok
+    }
+    fn peek_at(&self, offset: usize) -> &Tok {
+        let idx = (self.idx + offset).min(self.tokens.len() - 1);
+        &self.tokens[idx].tok
+    }
+    fn pos(&self) -> Pos {
+        self.tokens[self.idx].pos
+    }
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.idx].clone();
+        if token.tok != Tok::Eof {
+            self.idx += 1;
+        }
+        token
+    }
+    fn error<T>(&self, message: impl Into<String>) -> PResult<T> {
+        Err(StError::new(self.pos(), message))
+    }
+    fn unexpected<T>(&self, expected: &str) -> PResult<T> {
+        let found = match self.peek() {
+            Tok::Ident(name) => name.clone(),
+            Tok::Eof => "end of file".into(),
+            other => format!("{other:?}"),
+        };
+        self.error(format!("expected {expected}, found {found}"))
+    }
+    fn eat(&mut self, tok: &Tok) -> bool {
+        if self.peek() == tok {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, tok: Tok, what: &str) -> PResult<()> {
+        if self.eat(&tok) {
+            Ok(())
+        } else {
+            self.unexpected(what)
+        }
+    }
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Tok::Ident(name) if name == keyword)
+    }
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect_keyword(&mut self, keyword: &str) -> PResult<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            self.unexpected(keyword)
+        }
+    }
+    fn ident(&mut self) -> PResult<(String, Pos)> {
+        match self.peek() {
+            Tok::Ident(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                let pos = self.advance().pos;
+                Ok((name, pos))
+            }
+            _ => self.unexpected("identifier"),
+        }
+    }
+    fn var_ref(&mut self) -> PResult<VarRef> {
+        let (name, pos) = self.ident()?;
+        let member = if self.eat(&Tok::Dot) {
+            Some(self.ident()?.0)
+        } else {
+            None
+        };
+        Ok(VarRef { name, member, pos })
+    }
+    fn direct_address(&mut self) -> PResult<(Io, Dio)> {
+        let Tok::Address(address) = self.peek().clone() else {
+            return self.unexpected("direct address");
+        };
+        match parse_direct_address(&address) {
+            Ok(parsed) => {
+                self.advance();
+                Ok(parsed)
+            }
+            Err(err) => self.error(err.to_string()),
+        }
+    }
+
+    fn program(&mut self) -> PResult<Program> {
+        self.expect_keyword("PROGRAM")?;
+        let (name, _) = self.ident()?;
+        let mut vars = Vec::new();
+        while self.is_keyword("VAR") || self.is_keyword("VAR_EXTERNAL") {
+            self.var_block(&mut vars)?;
+        }
+        let body = self.statements(&["END_PROGRAM"])?;
+        self.expect_keyword("END_PROGRAM")?;
+        self.eat(&Tok::Semi);
+        if *self.peek() != Tok::Eof {
+            return self.unexpected("end of file");
+        }
+        Ok(Program { name, vars, body })
+    }
+
+    fn var_block(&mut self, vars: &mut Vec<VarDecl>) -> PResult<()> {
+        let external = self.is_keyword("VAR_EXTERNAL");
+        self.advance();
+        while !self.eat_keyword("END_VAR") {
+            let mut names = vec![self.ident()?];
+            while self.eat(&Tok::Comma) {
+                names.push(self.ident()?);
+            }
+            let mut binding = if external {
+                Binding::Tag
+            } else {
+                Binding::Local
+            };
+            if self.eat_keyword("AT") {
+                if external {
+                    return self
+                        .error("VAR_EXTERNAL is bound to a tag and can not have an address");
+                }
+                if names.len() > 1 {
+                    return self.error("only one variable can be located at an address");
+                }
+                let (kind, dio) = self.direct_address()?;
+                binding = Binding::Io(kind, dio);
+            }
+            self.expect(Tok::Colon, "':'")?;
+            let type_pos = self.pos();
+            let Tok::Ident(type_name) = self.peek().clone() else {
+                return self.unexpected("type");
+            };
+            self.advance();
+            let Some(ty) = VarType::from_name(&type_name) else {
+                return Err(StError::new(type_pos, format!("unknown type {type_name}")));
+            };
+            if matches!(ty, VarType::Fb(_)) && binding != Binding::Local {
+                return Err(StError::new(
+                    type_pos,
+                    "function block instances can not be bound to IO or tags",
+                ));
+            }
+            if let Binding::Io(..) = binding {
+                if ty != VarType::Bool {
+                    return Err(StError::new(type_pos, "bit addresses must be BOOL"));
+                }
+            }
+            let init = if self.eat(&Tok::Assign) {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            self.expect(Tok::Semi, "';'")?;
+            for (name, pos) in names {
+                if vars.iter().any(|var| var.name == name) {
+                    return Err(StError::new(pos, format!("{name} is declared twice")));
+                }
+                vars.push(VarDecl {
+                    name,
+                    ty,
+                    binding,
+                    init: init.clone(),
+                    pos,
+                });
+            }
+        }
+        self.eat(&Tok::Semi);
+        Ok(())
+    }
+
+    fn statements(&mut self, terminators: &[&str]) -> PResult<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        while !terminators.iter().any(|t| self.is_keyword(t)) {
+            if *self.peek() == Tok::Eof {
+                return self.unexpected(&terminators.join(" or "));
+            }
+            if let Some(stmt) = self.statement()? {
+                stmts.push(stmt);
+            }
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> PResult<Option<Stmt>> {
+        let stmt = match self.peek().clone() {
+            Tok::Semi => {
+                self.advance();
+                return Ok(None);
+            }
+            Tok::Ident(kw) if kw == "IF" => self.if_stmt()?,
+            Tok::Ident(kw) if kw == "CASE" => self.case_stmt()?,
+            Tok::Ident(kw) if kw == "FOR" => self.for_stmt()?,
+            Tok::Address(_) => {
+                let pos = self.pos();
+                let (kind, dio) = self.direct_address()?;
+                if kind == Io::Input {
+                    return Err(StError::new(pos, "inputs can not be written"));
+                }
+                self.expect(Tok::Assign, "':='")?;
+                let value = self.expr()?;
+                self.expect(Tok::Semi, "';'")?;
+                Stmt::AssignDirect { dio, value, pos }
+            }
+            Tok::Ident(_) => {
+                let target = self.var_ref()?;
+                if target.member.is_none() && *self.peek() == Tok::LParen {
+                    self.call(target)?
+                } else {
+                    self.expect(Tok::Assign, "':='")?;
+                    let value = self.expr()?;
+                    self.expect(Tok::Semi, "';'")?;
+                    Stmt::Assign { target, value }
+                }
+            }
+            _ => return self.unexpected("statement"),
+        };
+        Ok(Some(stmt))
+    }
+
+    fn call(&mut self, instance: VarRef) -> PResult<Stmt> {
+        self.expect(Tok::LParen, "'('")?;
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        if !self.eat(&Tok::RParen) {
+            loop {
+                let (param, _) = self.ident()?;
+                if self.eat(&Tok::Assign) {
+                    inputs.push((param, self.expr()?));
+                } else if self.eat(&Tok::Arrow) {
+                    outputs.push((param, self.var_ref()?));
+                } else {
+                    return self.unexpected("':=' or '=>'");
+                }
+                if self.eat(&Tok::RParen) {
+                    break;
+                }
+                self.expect(Tok::Comma, "',' or ')'")?;
+            }
+        }
+        self.expect(Tok::Semi, "';'")?;
+        Ok(Stmt::Call {
+            instance,
+            inputs,
+            outputs,
+        })
+    }
+
+    fn if_stmt(&mut self) -> PResult<Stmt> {
+        self.expect_keyword("IF")?;
+        let mut branches = Vec::new();
+        loop {
+            let condition = self.expr()?;
+            self.expect_keyword("THEN")?;
+            let body = self.statements(&["ELSIF", "ELSE", "END_IF"])?;
+            branches.push((condition, body));
+            if !self.eat_keyword("ELSIF") {
+                break;
+            }
+        }
+        let otherwise = if self.eat_keyword("ELSE") {
+            self.statements(&["END_IF"])?
+        } else {
+            Vec::new()
+        };
+        self.expect_keyword("END_IF")?;
+        self.expect(Tok::Semi, "';'")?;
+        Ok(Stmt::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn at_case_label(&self) -> bool {
+        match self.peek() {
+            Tok::Int(_) => true,
+            Tok::Minus => matches!(self.peek_at(1), Tok::Int(_)),
+            _ => false,
+        }
+    }
+
+    fn case_int(&mut self) -> PResult<i64> {
+        let negative = self.eat(&Tok::Minus);
+        match self.peek() {
+            Tok::Int(value) => {
+                let value = *value;
+                self.advance();
+                Ok(if negative { -value } else { value })
+            }
+            _ => self.unexpected("integer case label"),
+        }
+    }
+
+    fn case_stmt(&mut self) -> PResult<Stmt> {
+        self.expect_keyword("CASE")?;
+        let selector = self.expr()?;
+        self.expect_keyword("OF")?;
+        let mut arms = Vec::new();
+        let mut otherwise = Vec::new();
+        loop {
+            if self.eat_keyword("ELSE") {
+                otherwise = self.statements(&["END_CASE"])?;
+                break;
+            }
+            if self.is_keyword("END_CASE") {
+                break;
+            }
+            let mut labels = Vec::new();
+            loop {
+                let from = self.case_int()?;
+                if self.eat(&Tok::DotDot) {
+                    labels.push(CaseLabel::Range(from, self.case_int()?));
+                } else {
+                    labels.push(CaseLabel::Value(from));
+                }
+                if !self.eat(&Tok::Comma) {
+                    break;
+                }
+            }
+            self.expect(Tok::Colon, "':'")?;
+            let mut body = Vec::new();
+            while !(self.at_case_label() || self.is_keyword("ELSE") || self.is_keyword("END_CASE"))
+            {
+                if *self.peek() == Tok::Eof {
+                    return self.unexpected("END_CASE");
+                }
+                if let Some(stmt) = self.statement()? {
+                    body.push(stmt);
+                }
+            }
+            arms.push((labels, body));
+        }
+        self.expect_keyword("END_CASE")?;
+        self.expect(Tok::Semi, "';'")?;
+        Ok(Stmt::Case {
+            selector,
+            arms,
+            otherwise,
+        })
+    }
+
+    fn for_stmt(&mut self) -> PResult<Stmt> {
+        self.expect_keyword("FOR")?;
+        let var = self.var_ref()?;
+        self.expect(Tok::Assign, "':='")?;
+        let from = self.expr()?;
+        self.expect_keyword("TO")?;
+        let to = self.expr()?;
+        let by = if self.eat_keyword("BY") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        self.expect_keyword("DO")?;
+        let body = self.statements(&["END_FOR"])?;
+        self.expect_keyword("END_FOR")?;
+        self.expect(Tok::Semi, "';'")?;
+        Ok(Stmt::For {
+            var,
+            from,
+            to,
+            by,
+            body,
+        })
+    }
+
+    fn binary_level(
+        &mut self,
+        next: fn(&mut Self) -> PResult<Expr>,
+        op_of: fn(&Tok) -> Option<BinOp>,
+    ) -> PResult<Expr> {
+        let mut lhs = next(self)?;
+        while let Some(op) = op_of(self.peek()) {
+            let pos = self.advance().pos;
+            let rhs = next(self)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs), pos);
+        }
+        Ok(lhs)
+    }
+
+    fn expr(&mut self) -> PResult<Expr> {
+        self.binary_level(Self::xor_expr, |tok| match tok {
+            Tok::Ident(kw) if kw == "OR" => Some(BinOp::Or),
+            _ => None,
+        })
+    }
+    fn xor_expr(&mut self) -> PResult<Expr> {
+        self.binary_level(Self::and_expr, |tok| match tok {
+            Tok::Ident(kw) if kw == "XOR" => Some(BinOp::Xor),
+            _ => None,
+        })
+    }
+    fn and_expr(&mut self) -> PResult<Expr> {
+        self.binary_level(Self::compare_expr, |tok| match tok {
+            Tok::Ident(kw) if kw == "AND" => Some(BinOp::And),
+            Tok::Amp => Some(BinOp::And),
+            _ => None,
+        })
+    }
+    fn compare_expr(&mut self) -> PResult<Expr> {
+        self.binary_level(Self::add_expr, |tok| match tok {
+            Tok::Eq => Some(BinOp::Eq),
+            Tok::Ne => Some(BinOp::Ne),
+            Tok::Lt => Some(BinOp::Lt),
+            Tok::Le => Some(BinOp::Le),
+            Tok::Gt => Some(BinOp::Gt),
+            Tok::Ge => Some(BinOp::Ge),
+            _ => None,
+        })
+    }
+    fn add_expr(&mut self) -> PResult<Expr> {
+        self.binary_level(Self::mul_expr, |tok| match tok {
+            Tok::Plus => Some(BinOp::Add),
+            Tok::Minus => Some(BinOp::Sub),
+            _ => None,
+        })
+    }
+    fn mul_expr(&mut self) -> PResult<Expr> {
+        self.binary_level(Self::unary_expr, |tok| match tok {
+            Tok::Star => Some(BinOp::Mul),
+            Tok::Slash => Some(BinOp::Div),
+            Tok::Ident(kw) if kw == "MOD" => Some(BinOp::Mod),
+            _ => None,
+        })
+    }
+    fn unary_expr(&mut self) -> PResult<Expr> {
+        let pos = self.pos();
+        if self.eat_keyword("NOT") {
+            let operand = self.unary_expr()?;
+            return Ok(Expr::Unary(UnOp::Not, Box::new(operand), pos));
+        }
+        if self.eat(&Tok::Minus) {
+            let operand = self.unary_expr()?;
+            return Ok(Expr::Unary(UnOp::Neg, Box::new(operand), pos));
+        }
+        self.primary()
+    }
+    fn primary(&mut self) -> PResult<Expr> {
+        let pos = self.pos();
+        let expr = match self.peek().clone() {
+            Tok::Int(value) => Expr::Lit(Value::Int(value)),
+            Tok::Real(value) => Expr::Lit(Value::Real(value)),
+            Tok::Time(value) => Expr::Lit(Value::Time(value)),
+            Tok::Ident(kw) if kw == "TRUE" => Expr::Lit(Value::Bool(true)),
+            Tok::Ident(kw) if kw == "FALSE" => Expr::Lit(Value::Bool(false)),
+            Tok::Address(_) => {
+                let (kind, dio) = self.direct_address()?;
+                return Ok(Expr::Direct(kind, dio, pos));
+            }
+            Tok::LParen => {
+                self.advance();
+                let inner = self.expr()?;
+                self.expect(Tok::RParen, "')'")?;
+                return Ok(inner);
+            }
+            Tok::Ident(_) => return Ok(Expr::Var(self.var_ref()?)),
+            _ => return self.unexpected("expression"),
+        };
+        self.advance();
+        Ok(expr)
+    }
+}
+
+pub(super) fn parse(source: &str) -> Result<Program, StError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, idx: 0 };
+    parser.program()
+}
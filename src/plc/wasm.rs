@@ -0,0 +1,160 @@
+//! Control programs compiled to WebAssembly
+//!
+//! A module exports `scan`, called once per scan, and optionally `init`,
+//! called once after loading, before any IO is lent to the module. It may
+//! import these functions from `env`:
+//!
+//! | import                                        | returns                         |
+//! |-----------------------------------------------|---------------------------------|
+//! | `read_input(node: i32, pin: i32) -> i32`      | 0 or 1, -1 if there is no input |
+//! | `read_output(node: i32, pin: i32) -> i32`     | 0 or 1, -1 if there is no output|
+//! | `write_output(node: i32, pin: i32, value: i32) -> i32` | 0, -1 if there is no output |
+//! | `time_ms() -> i64`                            | simulated time                  |
+//!
+//! The module only sees its own linear memory, limited to [`MEMORY_LIMIT`],
+//! and each scan may burn at most [`FUEL_PER_SCAN`]. A trap, an endless loop
+//! or running out of memory faults the program, the simulation keeps going.
+
+use std::{fs, path::Path, time::Duration};
+
+use bevy::prelude::*;
+use wasmi::{
+    Caller, Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+use crate::io::{Dio, DioPin, Io, IoDevices, NodeId};
+
+/// Fuel one scan may use, roughly one unit per executed instruction
+pub const FUEL_PER_SCAN: u64 = 10_000_000;
+/// Largest linear memory a program may grow to
+pub const MEMORY_LIMIT: usize = 16 << 20;
+
+struct Host {
+    /// the process image, lent to the module for the duration of a scan
+    io: IoDevices,
+    now: Duration,
+    limits: StoreLimits,
+}
+
+fn dio(node: i32, pin: i32) -> Option<Dio> {
+    Some(Dio {
+        node: NodeId(u32::try_from(node).ok()?),
+        pin: DioPin(u16::try_from(pin).ok()?),
+    })
+}
+
+fn bit_result(bit: Option<bool>) -> i32 {
+    match bit {
+        Some(bit) => bit as i32,
+        None => -1,
+    }
+}
+
+#[derive(Component)]
+/// Sandboxed program executed once per scan
+pub struct WasmProgram {
+    store: Store<Host>,
+    scan: TypedFunc<(), ()>,
+    scans: u64,
+    fault: Option<String>,
+}
+
+impl WasmProgram {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, bytes)?;
+
+        let host = Host {
+            io: default(),
+            now: Duration::ZERO,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MEMORY_LIMIT)
+                .instances(1)
+                .build(),
+        };
+        let mut store = Store::new(&engine, host);
+        store.limiter(|host| &mut host.limits);
+
+        let mut linker = Linker::<Host>::new(&engine);
+        linker.func_wrap(
+            "env",
+            "read_input",
+            |caller: Caller<'_, Host>, node: i32, pin: i32| {
+                let bit = dio(node, pin).and_then(|dio| caller.data().io.get_bit(Io::Input, dio));
+                bit_result(bit)
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "read_output",
+            |caller: Caller<'_, Host>, node: i32, pin: i32| {
+                let bit = dio(node, pin).and_then(|dio| caller.data().io.get_bit(Io::Output, dio));
+                bit_result(bit)
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "write_output",
+            |mut caller: Caller<'_, Host>, node: i32, pin: i32, value: i32| {
+                let Some(dio) = dio(node, pin) else {
+                    return -1;
+                };
+                let io = &mut caller.data_mut().io;
+                match io.try_set_bit(Io::Output, dio, value != 0) {
+                    Ok(()) => 0,
+                    Err(_) => -1,
+                }
+            },
+        )?;
+        linker.func_wrap("env", "time_ms", |caller: Caller<'_, Host>| {
+            caller.data().now.as_millis() as i64
+        })?;
+
+        store.set_fuel(FUEL_PER_SCAN)?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        let scan = instance
+            .get_typed_func::<(), ()>(&store, "scan")
+            .map_err(|err| format!("module must export `fn scan()`: {err}"))?;
+        if let Ok(init) = instance.get_typed_func::<(), ()>(&store, "init") {
+            init.call(&mut store, ())?;
+        }
+        Ok(Self {
+            store,
+            scan,
+            scans: 0,
+            fault: None,
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn fault(&self) -> Option<&str> {
+        self.fault.as_deref()
+    }
+
+    pub fn scans(&self) -> u64 {
+        self.scans
+    }
+
+    pub fn scan(&mut self, now: Duration, io: &mut IoDevices) -> Result<()> {
+        let result = self.store.set_fuel(FUEL_PER_SCAN).map_err(BevyError::from);
+        let result = result.and_then(|()| {
+            let host = self.store.data_mut();
+            host.now = now;
+            std::mem::swap(&mut host.io, io);
+            let result = self.scan.call(&mut self.store, ());
+            // hand the process image back, also after a trap
+            std::mem::swap(&mut self.store.data_mut().io, io);
+            result.map_err(BevyError::from)
+        });
+        match &result {
+            Ok(()) => self.scans += 1,
+            Err(err) => self.fault = Some(err.to_string()),
+        }
+        result
+    }
+}
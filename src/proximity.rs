@@ -0,0 +1,231 @@
+//! Proximity sensors and limit switches, the position sensors next to
+//! [`crate::fotocell`]. Both report through [`Switch`] like fotocells do, so
+//! a [`crate::sensor::SensorPosition`] on them drives station logic. The
+//! layout mounts them on stations, see [`crate::layout::DeviceKind`]
+//!
+//! ```text
+//! inductive    metal within a few millimeters of its face
+//! capacitive   any material, a bit farther
+//! limit switch a lever pressed by whatever touches it
+//! ```
+
+use std::borrow::Cow;
+
+use avian3d::prelude::*;
+use bevy::{color::palettes::css, prelude::*};
+
+use crate::{
+    io::{Dio, DioPin, NodeId, Switch},
+    physics::PhysLayer,
+    sensor::{cast_distance, report, SensingShape, SensorFault, SensorSignal, SwitchingPoint},
+    sysorder::ScanSet,
+};
+
+pub struct ProximityPlugin;
+
+impl Plugin for ProximityPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<ProximityKind>();
+        app.add_systems(
+            FixedUpdate,
+            (sense_proximity, sense_limit_switches).in_set(ScanSet::Input),
+        );
+        app.add_systems(Update, render_proximity);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum ProximityKind {
+    /// sees metal only
+    Inductive,
+    /// sees any material
+    Capacitive,
+}
+
+impl ProximityKind {
+    /// Rated switching distance in meters of an M18 sensor
+    pub const fn rated_distance(self) -> f32 {
+        match self {
+            Self::Inductive => 0.008,
+            Self::Capacitive => 0.015,
+        }
+    }
+}
+
+#[derive(Component, Debug, Default)]
+/// Plastic, wood or cardboard, inductive sensors do not see it
+pub struct NonMetal;
+
+#[derive(Component, Debug, Clone, Copy)]
+/// Sensing face looking along local +z, it sees what comes within its
+/// switching point anywhere in front of the face
+pub struct ProximitySensor {
+    pub kind: ProximityKind,
+    /// radius of the sensing face
+    pub face: f32,
+    pub switching: SwitchingPoint,
+}
+
+#[derive(Bundle)]
+pub struct ProximityBundle {
+    pub sensor: ProximitySensor,
+    pub switch: Switch,
+    pub name: Name,
+    pub device: NodeId,
+    pub io_pin: DioPin,
+    signal: SensorSignal,
+}
+
+impl ProximityBundle {
+    /// M18 sensor switching at its rated distance
+    pub fn new(name: impl Into<Cow<'static, str>>, dio: Dio, kind: ProximityKind) -> Self {
+        Self {
+            sensor: ProximitySensor {
+                kind,
+                face: 0.009,
+                switching: SwitchingPoint::new(kind.rated_distance()),
+            },
+            switch: default(),
+            name: Name::new(name),
+            device: dio.node,
+            io_pin: dio.pin,
+            signal: default(),
+        }
+    }
+}
+
+fn sense_proximity(
+    mut cmd: Commands,
+    spatial: SpatialQuery,
+    mut sensors: Query<(
+        Entity,
+        &ProximitySensor,
+        &GlobalTransform,
+        &mut SensorSignal,
+        Option<&SensorFault>,
+    )>,
+    non_metal: Query<Entity, With<NonMetal>>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    for (entity, sensor, transform, mut signal, fault) in sensors.iter_mut() {
+        let mask = [
+            PhysLayer::Detail,
+            PhysLayer::Component,
+            PhysLayer::Actuator,
+            PhysLayer::Station,
+        ];
+        let mut filter = SpatialQueryFilter::from_mask(mask).with_excluded_entities([entity]);
+        if sensor.kind == ProximityKind::Inductive {
+            filter.excluded_entities.extend(non_metal.iter());
+        }
+        let hit = cast_distance(
+            &spatial,
+            transform.translation(),
+            -transform.forward(),
+            sensor.switching.reach(),
+            SensingShape::Sphere(sensor.face),
+            &filter,
+        );
+        if signal.distance != hit {
+            signal.distance = hit;
+        }
+        let detected = sensor.switching.switch(signal.detected, hit);
+        report(&mut cmd, entity, &mut signal, detected, now, fault);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+pub enum Contact {
+    /// closes when the lever is pressed
+    #[default]
+    NormallyOpen,
+    /// opens when the lever is pressed, a broken wire reads as pressed
+    NormallyClosed,
+}
+
+#[derive(Component, Debug, Clone, Copy, Default)]
+/// Mechanical switch, pressed while anything touches its lever
+pub struct LimitSwitch {
+    pub contact: Contact,
+}
+
+#[derive(Bundle)]
+pub struct LimitSwitchBundle {
+    pub limit_switch: LimitSwitch,
+    pub switch: Switch,
+    pub name: Name,
+    pub device: NodeId,
+    pub io_pin: DioPin,
+    signal: SensorSignal,
+    simbody: RigidBody,
+    collider: Collider,
+    sensor: Sensor,
+    colliding: CollidingEntities,
+    phys_layers: CollisionLayers,
+}
+
+impl LimitSwitchBundle {
+    /// `lever` is the size of the box that presses the switch
+    pub fn new(
+        name: impl Into<Cow<'static, str>>,
+        dio: Dio,
+        contact: Contact,
+        lever: Vec3,
+    ) -> Self {
+        Self {
+            limit_switch: LimitSwitch { contact },
+            switch: default(),
+            name: Name::new(name),
+            device: dio.node,
+            io_pin: dio.pin,
+            signal: default(),
+            simbody: RigidBody::Kinematic,
+            collider: Collider::cuboid(lever.x, lever.y, lever.z),
+            sensor: Sensor,
+            colliding: default(),
+            phys_layers: CollisionLayers::new(
+                PhysLayer::Sensor,
+                [PhysLayer::Detail, PhysLayer::Component, PhysLayer::Actuator],
+            ),
+        }
+    }
+}
+
+/// Contacts are polled every scan, so a lever held down stays pressed
+fn sense_limit_switches(
+    mut cmd: Commands,
+    mut switches: Query<(
+        Entity,
+        &LimitSwitch,
+        &CollidingEntities,
+        &mut SensorSignal,
+        Option<&SensorFault>,
+    )>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    for (entity, limit_switch, colliding, mut signal, fault) in switches.iter_mut() {
+        let pressed = !colliding.is_empty();
+        let closed = match limit_switch.contact {
+            Contact::NormallyOpen => pressed,
+            Contact::NormallyClosed => !pressed,
+        };
+        report(&mut cmd, entity, &mut signal, closed, now, fault);
+    }
+}
+
+fn render_proximity(
+    mut gizmos: Gizmos,
+    sensors: Query<(&ProximitySensor, &GlobalTransform, &SensorSignal)>,
+) {
+    for (sensor, transform, signal) in sensors {
+        let color = if signal.reported {
+            css::GREEN
+        } else {
+            css::PURPLE
+        };
+        let face = Isometry3d::new(transform.translation(), transform.rotation());
+        gizmos.circle(face, sensor.face, color);
+    }
+}
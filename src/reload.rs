@@ -0,0 +1,57 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bevy::prelude::*;
+
+/// How often watched files are checked for changes
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct ReloadPlugin;
+
+impl Plugin for ReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LoadErrors>();
+        app.insert_resource(ReloadTimer(Timer::new(POLL_INTERVAL, TimerMode::Repeating)));
+        app.add_systems(First, tick_reload_timer);
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+/// Files that failed to load, by path, shown in the ui until they load again
+pub struct LoadErrors(pub BTreeMap<PathBuf, String>);
+
+impl LoadErrors {
+    pub fn report<T>(&mut self, path: &Path, result: &Result<T>) {
+        match result {
+            Ok(_) => {
+                self.0.remove(path);
+            }
+            Err(err) => {
+                error!("{}: {err}", path.display());
+                self.0.insert(path.to_path_buf(), err.to_string());
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct ReloadTimer(Timer);
+
+/// Ticks on real time so files are picked up while the simulation is paused
+fn tick_reload_timer(mut timer: ResMut<ReloadTimer>, time: Res<Time<Real>>) {
+    timer.0.tick(time.delta());
+}
+
+/// Run condition, true once every [`POLL_INTERVAL`]
+pub fn reload_due(timer: Res<ReloadTimer>) -> bool {
+    timer.0.just_finished()
+}
+
+/// Last modification time, `None` when the file is missing
+pub fn modified(path: impl AsRef<Path>) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
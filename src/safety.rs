@@ -0,0 +1,305 @@
+use std::borrow::Cow;
+
+use avian3d::prelude::*;
+use bevy::{color::palettes::css, prelude::*};
+
+use crate::{
+    io::{Dio, FieldIo},
+    physics::PhysLayer,
+    sysorder::{InitSet, ScanSet},
+};
+
+pub struct SafetyPlugin;
+
+impl Plugin for SafetyPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<EStop>();
+        app.register_type::<SafetyZone>();
+        app.register_type::<SafetyRelay>();
+        app.init_resource::<SafetyRelay>();
+        app.init_resource::<SafetyAssets>();
+        app.init_gizmo_group::<SafetyGizmos>();
+        app.add_systems(Startup, load_safety_assets.in_set(InitSet::LoadAssets));
+        app.add_systems(
+            FixedUpdate,
+            (evaluate_safety_circuit, write_relay_feedback)
+                .chain()
+                .in_set(ScanSet::Input),
+        );
+        app.add_systems(Update, render_safety_zones);
+        app.add_observer(on_estop_actuate);
+        app.add_observer(on_safety_ack);
+        app.add_observer(on_safety_reset);
+        app.add_observer(on_insert_light_curtain);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Reflect, Default, PartialEq, Eq)]
+pub enum SafetyState {
+    /// motor power available
+    #[default]
+    Ok,
+    /// a safety demand cut the motor power, needs acknowledge
+    Tripped,
+    /// acknowledged, waiting for reset
+    Acknowledged,
+}
+
+#[derive(Resource, Reflect, Default, Debug)]
+#[reflect(Resource)]
+/// Safety relay that all e-stops and safety zones are wired to
+///
+/// When tripped motor outputs are cut no matter what [`FieldIo`] says.
+/// Getting power back requires all demands to be cleared, then [`SafetyAck`] and then [`SafetyReset`]
+pub struct SafetyRelay {
+    state: SafetyState,
+    demand_active: bool,
+    /// What caused the latest trip
+    pub cause: Option<String>,
+    /// Input that tells the control program if motor power is available
+    pub feedback: Option<Dio>,
+}
+
+impl SafetyRelay {
+    pub fn state(&self) -> SafetyState {
+        self.state
+    }
+    pub fn outputs_enabled(&self) -> bool {
+        self.state == SafetyState::Ok
+    }
+    /// True while any e-stop is pressed or any zone is violated
+    pub fn demand_active(&self) -> bool {
+        self.demand_active
+    }
+}
+
+#[derive(Event)]
+/// Acknowledge a trip, only accepted when no demand is active
+pub struct SafetyAck;
+
+#[derive(Event)]
+/// Restore motor power after acknowledge
+pub struct SafetyReset;
+
+#[derive(Component, Reflect, Default, Debug)]
+/// Latching emergency stop pushbutton
+pub struct EStop {
+    pub pressed: bool,
+}
+
+#[derive(EntityEvent)]
+/// Press or pull out an e-stop
+pub struct EStopActuate {
+    pub entity: Entity,
+    pub pressed: bool,
+}
+
+#[derive(Component, Reflect, Default, Debug)]
+/// Guarded area, any object breaking one of its beams is an intrusion
+pub struct SafetyZone {
+    intrusions: u32,
+}
+
+impl SafetyZone {
+    pub fn violated(&self) -> bool {
+        self.intrusions > 0
+    }
+}
+
+#[derive(Component)]
+/// Single beam of a light curtain, child of a [`SafetyZone`]
+pub struct ZoneBeam {
+    length: f32,
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct SafetyGizmos;
+
+#[derive(Resource, Default)]
+pub struct SafetyAssets {
+    estop_mesh: Handle<Mesh>,
+    released: Handle<StandardMaterial>,
+    pressed: Handle<StandardMaterial>,
+}
+
+fn load_safety_assets(
+    mut mesh_assets: ResMut<Assets<Mesh>>,
+    mut material_assets: ResMut<Assets<StandardMaterial>>,
+    mut assets: ResMut<SafetyAssets>,
+) {
+    assets.estop_mesh = mesh_assets.add(Cylinder::new(0.08, 0.05));
+    assets.released = material_assets.add(StandardMaterial {
+        base_color: css::RED.into(),
+        ..Default::default()
+    });
+    assets.pressed = material_assets.add(StandardMaterial {
+        base_color: css::DARK_RED.into(),
+        ..Default::default()
+    });
+}
+
+#[derive(Bundle)]
+pub struct EStopBundle {
+    pub estop: EStop,
+    pub name: Name,
+    mesh: Mesh3d,
+    material: MeshMaterial3d<StandardMaterial>,
+}
+
+impl EStopBundle {
+    pub fn new(name: impl Into<Cow<'static, str>>, assets: &SafetyAssets) -> Self {
+        Self {
+            estop: default(),
+            name: Name::new(name),
+            mesh: Mesh3d(assets.estop_mesh.clone()),
+            material: MeshMaterial3d(assets.released.clone()),
+        }
+    }
+}
+
+#[derive(Event, Clone)]
+/// Spawn a light curtain of `n_beams` horizontal beams along the local x axis
+pub struct InsertLightCurtain {
+    pub name: Cow<'static, str>,
+    pub transform: Transform,
+    pub width: f32,
+    pub height: f32,
+    pub n_beams: usize,
+}
+
+fn on_insert_light_curtain(spawn: On<InsertLightCurtain>, mut cmd: Commands) {
+    let n_beams = spawn.n_beams.max(2);
+    let phys_layers = CollisionLayers::new(PhysLayer::Sensor, LayerMask::ALL);
+    let beams: Vec<_> = (0..n_beams)
+        .map(|i| {
+            let y = spawn.height * i as f32 / (n_beams - 1) as f32;
+            cmd.spawn((
+                ZoneBeam {
+                    length: spawn.width,
+                },
+                Name::new(format!("beam {i}")),
+                Transform::from_xyz(0.0, y, 0.0),
+                RigidBody::Kinematic,
+                Collider::segment(Vec3::ZERO, Vec3::X * spawn.width),
+                CollisionEventsEnabled,
+                phys_layers,
+            ))
+            .observe(on_beam_broken)
+            .observe(on_beam_cleared)
+            .id()
+        })
+        .collect();
+    cmd.spawn((
+        SafetyZone::default(),
+        Name::new(spawn.name.clone()),
+        spawn.transform,
+    ))
+    .add_children(&beams);
+}
+
+fn on_beam_broken(
+    trigger: On<CollisionStart>,
+    beams: Query<&ChildOf, With<ZoneBeam>>,
+    mut zones: Query<&mut SafetyZone>,
+) {
+    let Ok(child_of) = beams.get(trigger.event_target()) else {
+        return;
+    };
+    if let Ok(mut zone) = zones.get_mut(child_of.parent()) {
+        zone.intrusions += 1;
+    }
+}
+
+fn on_beam_cleared(
+    trigger: On<CollisionEnd>,
+    beams: Query<&ChildOf, With<ZoneBeam>>,
+    mut zones: Query<&mut SafetyZone>,
+) {
+    let Ok(child_of) = beams.get(trigger.event_target()) else {
+        return;
+    };
+    if let Ok(mut zone) = zones.get_mut(child_of.parent()) {
+        zone.intrusions = zone.intrusions.saturating_sub(1);
+    }
+}
+
+fn on_estop_actuate(
+    trigger: On<EStopActuate>,
+    mut estops: Query<(&mut EStop, &mut MeshMaterial3d<StandardMaterial>)>,
+    assets: Res<SafetyAssets>,
+) {
+    let Ok((mut estop, mut material)) = estops.get_mut(trigger.entity) else {
+        return;
+    };
+    estop.pressed = trigger.pressed;
+    material.0 = if trigger.pressed {
+        assets.pressed.clone()
+    } else {
+        assets.released.clone()
+    };
+}
+
+fn on_safety_ack(_trigger: On<SafetyAck>, mut relay: ResMut<SafetyRelay>) {
+    if relay.state == SafetyState::Tripped && !relay.demand_active {
+        relay.state = SafetyState::Acknowledged;
+    }
+}
+
+fn on_safety_reset(_trigger: On<SafetyReset>, mut relay: ResMut<SafetyRelay>) {
+    if relay.state == SafetyState::Acknowledged && !relay.demand_active {
+        relay.state = SafetyState::Ok;
+        relay.cause = None;
+    }
+}
+
+fn evaluate_safety_circuit(
+    estops: Query<(&Name, &EStop)>,
+    zones: Query<(&Name, &SafetyZone)>,
+    mut relay: ResMut<SafetyRelay>,
+) {
+    let demand = estops
+        .iter()
+        .find(|(_, estop)| estop.pressed)
+        .map(|(name, _)| format!("e-stop {name}"))
+        .or_else(|| {
+            zones
+                .iter()
+                .find(|(_, zone)| zone.violated())
+                .map(|(name, _)| format!("safety zone {name}"))
+        });
+    relay.demand_active = demand.is_some();
+    let Some(cause) = demand else {
+        return;
+    };
+    if relay.state != SafetyState::Tripped {
+        warn!("safety circuit tripped by {cause}");
+        relay.state = SafetyState::Tripped;
+        relay.cause = Some(cause);
+    }
+}
+
+fn write_relay_feedback(relay: Res<SafetyRelay>, mut io: ResMut<FieldIo>) {
+    let Some(Dio { node, pin }) = relay.feedback else {
+        return;
+    };
+    if let Some(device) = io.digital_inputs.get_mut(&node) {
+        device.set(pin.as_usize(), relay.outputs_enabled());
+    }
+}
+
+fn render_safety_zones(
+    mut gizmos: Gizmos<SafetyGizmos>,
+    beams: Query<(&ZoneBeam, &GlobalTransform, &ChildOf)>,
+    zones: Query<&SafetyZone>,
+) {
+    for (beam, transform, child_of) in beams {
+        let violated = zones
+            .get(child_of.parent())
+            .map(|zone| zone.violated())
+            .unwrap_or_default();
+        let color = if violated { css::RED } else { css::YELLOW };
+        let start = transform.translation();
+        let end = transform.transform_point(Vec3::X * beam.length);
+        gizmos.line(start, end, color);
+    }
+}
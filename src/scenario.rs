@@ -0,0 +1,163 @@
+//! Scripted sensor faults, replayed on simulated time
+//!
+//! ```text
+//! # at <seconds> <fault|clear> <station>/<sensor>
+//! at 5 stuck_on stn 3/front_end
+//! at 12.5 flicker 0.2 stn 0/back_slow
+//! at 20 delay 150ms stn 1/fron_slow
+//! at 30 clear stn 3/front_end
+//! ```
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    reload::{modified, reload_due, LoadErrors},
+    sensor::{SensorFault, SensorSignal},
+    sysorder::{InitSet, ScanSet},
+};
+
+/// Scenario loaded at startup and reloaded when it changes
+pub const SCENARIO_FILE: &str = "scenario.txt";
+
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Scenario>();
+        app.add_systems(Startup, sync_scenario.in_set(InitSet::LoadAssets));
+        app.add_systems(Update, sync_scenario.run_if(reload_due));
+        app.add_systems(FixedUpdate, play_scenario.in_set(ScanSet::Input));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioStep {
+    pub at: Duration,
+    /// `None` clears the fault
+    pub fault: Option<SensorFault>,
+    pub station: String,
+    pub sensor: String,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct Scenario {
+    pub steps: Vec<ScenarioStep>,
+    /// index of the first step still to come
+    next: usize,
+}
+
+impl Scenario {
+    /// Parse a scenario, steps are sorted by time
+    /// ```
+    /// # use cybercrab::scenario::Scenario;
+    /// let text = "at 2 clear stn 1/back_end\nat 1 wire_break stn 1/back_end";
+    /// let scenario = Scenario::parse(text).unwrap();
+    /// assert_eq!(scenario.steps[0].station, "stn 1");
+    /// assert!(scenario.steps[0].fault.is_some());
+    /// assert!(Scenario::parse("at 1 stuck_on back_end").is_err());
+    /// ```
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut steps = Vec::new();
+        for (line_nr, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let step =
+                parse_step(line).map_err(|err| format!("scenario line {}: {err}", line_nr + 1))?;
+            steps.push(step);
+        }
+        steps.sort_by_key(|step| step.at);
+        Ok(Self { steps, next: 0 })
+    }
+}
+
+fn parse_step(line: &str) -> Result<ScenarioStep> {
+    let words: Vec<_> = line.split_whitespace().collect();
+    let ["at", at, rest @ ..] = words.as_slice() else {
+        return Err("expected at <seconds> <fault> <station>/<sensor>".into());
+    };
+    let at = Duration::try_from_secs_f64(at.parse()?).map_err(|_| format!("bad time '{at}'"))?;
+    // the fault takes one or two words, the target is the rest of the line
+    let (fault, target) = match rest {
+        ["clear", target @ ..] => (None, target),
+        [kind @ ("flicker" | "delay"), param, target @ ..] => {
+            (Some(SensorFault::parse(&[*kind, *param])?), target)
+        }
+        [kind, target @ ..] => (Some(SensorFault::parse(&[*kind])?), target),
+        [] => return Err("missing fault".into()),
+    };
+    let target = target.join(" ");
+    let (station, sensor) = target
+        .rsplit_once('/')
+        .ok_or(format!("target '{target}' is not <station>/<sensor>"))?;
+    Ok(ScenarioStep {
+        at,
+        fault,
+        station: station.into(),
+        sensor: sensor.into(),
+    })
+}
+
+/// Load the scenario file, steps that are already past are not replayed
+fn sync_scenario(
+    mut scenario: ResMut<Scenario>,
+    mut errors: ResMut<LoadErrors>,
+    mut last: Local<Option<SystemTime>>,
+    time: Res<Time<Fixed>>,
+) {
+    let modified = modified(SCENARIO_FILE);
+    if modified.is_none() || modified == *last {
+        return;
+    }
+    *last = modified;
+    let loaded = fs::read_to_string(SCENARIO_FILE)
+        .map_err(BevyError::from)
+        .and_then(|text| Scenario::parse(&text));
+    errors.report(Path::new(SCENARIO_FILE), &loaded);
+    let Ok(mut loaded) = loaded else {
+        return;
+    };
+    let now = time.elapsed();
+    loaded.next = loaded.steps.partition_point(|step| step.at < now);
+    *scenario = loaded;
+}
+
+fn play_scenario(
+    mut cmd: Commands,
+    mut scenario: ResMut<Scenario>,
+    sensors: Query<(Entity, &Name, &ChildOf), With<SensorSignal>>,
+    names: Query<&Name>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    while let Some(step) = scenario.steps.get(scenario.next) {
+        if step.at > now {
+            break;
+        }
+        let target = sensors.iter().find(|(_, name, child_of)| {
+            name.as_str() == step.sensor
+                && names
+                    .get(child_of.parent())
+                    .is_ok_and(|station| station.as_str() == step.station)
+        });
+        match (target, step.fault) {
+            (Some((entity, ..)), Some(fault)) => {
+                info!("scenario: {fault} on {}/{}", step.station, step.sensor);
+                cmd.entity(entity).insert(fault);
+            }
+            (Some((entity, ..)), None) => {
+                info!("scenario: clear {}/{}", step.station, step.sensor);
+                cmd.entity(entity).remove::<SensorFault>();
+            }
+            (None, _) => warn!("scenario: no sensor {}/{}", step.station, step.sensor),
+        }
+        scenario.next += 1;
+    }
+}
@@ -0,0 +1,158 @@
+//! Curved and inclined conveyor segments
+//!
+//! A segment is a transfer station whose conveyor follows a [`ConveyorPath`]
+//! instead of the straight bed. The path starts at the infeed in the origin
+//! of the station and runs along its local z, fotocells, wheels and bed are
+//! placed along it like on a straight station.
+//!
+//! In [`crate::tbana::TransportMode::Kinematic`] details take the velocity of
+//! the transport field of the path, along the path and back to its center
+//! line, and turn with it. With friction the wheels along the path drive
+//! them, and gravity pulls them back down an incline when the wheels slip.
+
+use std::f32::consts::{PI, TAU};
+
+use bevy::prelude::*;
+
+/// How fast details are pulled back to the center line, per second
+const CENTERING: f32 = 2.0;
+/// How fast details turn to the direction of the path, per second
+const ALIGNING: f32 = 4.0;
+/// Wheels along a segment, about this far apart
+pub const WHEEL_PITCH: f32 = 0.4;
+/// Longest straight piece of the bed along a segment
+const BED_PIECE: f32 = 0.25;
+
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+/// Center line of a conveyor, starting in the origin heading along z
+pub enum ConveyorPath {
+    /// Horizontal arc, turning left for a positive angle in radians
+    Curve { radius: f32, angle: f32 },
+    /// Straight slope rising `rise` over the horizontal `length`, a
+    /// negative rise declines
+    Incline { length: f32, rise: f32 },
+}
+
+impl ConveyorPath {
+    /// Length along the center line
+    /// ```
+    /// # use cybercrab::segment::ConveyorPath;
+    /// let incline = ConveyorPath::Incline { length: 4.0, rise: 3.0 };
+    /// assert_eq!(incline.length(), 5.0);
+    /// ```
+    pub fn length(&self) -> f32 {
+        match *self {
+            ConveyorPath::Curve { radius, angle } => radius * angle.abs(),
+            ConveyorPath::Incline { length, rise } => length.hypot(rise),
+        }
+    }
+
+    /// Position and heading `s` along the path, z points along the path and
+    /// y up from the conveyor bed
+    pub fn pose(&self, s: f32) -> Transform {
+        let s = s.clamp(0.0, self.length());
+        match *self {
+            ConveyorPath::Curve { radius, angle } => {
+                let turn = angle.signum();
+                let phi = s / radius;
+                let point = Vec3::new(turn * radius * (1.0 - phi.cos()), 0.0, radius * phi.sin());
+                Transform::from_translation(point).with_rotation(Quat::from_rotation_y(turn * phi))
+            }
+            ConveyorPath::Incline { length, rise } => {
+                let slope = rise.atan2(length);
+                let rotation = Quat::from_rotation_x(-slope);
+                Transform::from_translation(rotation * Vec3::Z * s).with_rotation(rotation)
+            }
+        }
+    }
+
+    /// Pose at the outfeed, where the next segment snaps on
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use cybercrab::segment::ConveyorPath;
+    /// # use std::f32::consts::FRAC_PI_2;
+    /// let curve = ConveyorPath::Curve { radius: 1.0, angle: FRAC_PI_2 };
+    /// let end = curve.end();
+    /// assert!(end.translation.distance([1.0, 0.0, 1.0].into()) < 1e-5);
+    /// assert!((end.rotation * Vec3::Z).distance(Vec3::X) < 1e-5);
+    /// ```
+    pub fn end(&self) -> Transform {
+        self.pose(self.length())
+    }
+
+    /// Distance along the path of the point on it closest to `point`, points
+    /// past either end of a curve go to the nearer end
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use cybercrab::segment::ConveyorPath;
+    /// # use std::f32::consts::PI;
+    /// let u_turn = ConveyorPath::Curve { radius: 1.0, angle: PI };
+    /// let length = u_turn.length();
+    /// let near_end = u_turn.pose(length - 0.01).translation;
+    /// assert!((u_turn.closest(near_end) - (length - 0.01)).abs() < 1e-4);
+    /// assert_eq!(u_turn.closest(Vec3::new(2.0, 0.0, -0.5)), length);
+    /// assert_eq!(u_turn.closest(Vec3::new(0.0, 0.0, -0.5)), 0.0);
+    /// ```
+    pub fn closest(&self, point: Vec3) -> f32 {
+        let s = match *self {
+            ConveyorPath::Curve { radius, angle } => {
+                let turn = angle.signum();
+                let from_center = point - Vec3::X * turn * radius;
+                let phi = from_center.z.atan2(-turn * from_center.x);
+                // measured from the middle of the arc, so it does not wrap on it
+                let half = angle.abs() / 2.0;
+                ((phi - half + PI).rem_euclid(TAU) - PI + half) * radius
+            }
+            ConveyorPath::Incline { .. } => point.dot(self.pose(0.0).rotation * Vec3::Z),
+        };
+        s.clamp(0.0, self.length())
+    }
+
+    fn bed_pieces(&self) -> usize {
+        (self.length() / BED_PIECE).ceil().max(1.0) as usize
+    }
+
+    /// Length of the straight pieces of [`Self::bed`], overlapping a little
+    pub fn bed_piece(&self) -> f32 {
+        self.length() / self.bed_pieces() as f32 + 0.02
+    }
+
+    /// Centers of the straight pieces the bed is built of
+    pub fn bed(&self) -> impl Iterator<Item = Transform> + '_ {
+        let n = self.bed_pieces();
+        (0..n).map(move |i| self.pose((i as f32 + 0.5) * self.length() / n as f32))
+    }
+
+    /// Velocity and angular velocity of a detail at `detail` on the segment
+    /// at `station`, driven at `speed` along the path
+    pub fn field(
+        &self,
+        station: &GlobalTransform,
+        detail: &GlobalTransform,
+        speed: f32,
+    ) -> (Vec3, Vec3) {
+        if speed == 0.0 {
+            return (Vec3::ZERO, Vec3::ZERO);
+        }
+        let local = station
+            .affine()
+            .inverse()
+            .transform_point3(detail.translation());
+        let pose = self.pose(self.closest(local));
+        let (along, left) = (pose.rotation * Vec3::Z, pose.rotation * Vec3::X);
+        let off_center = (local - pose.translation).dot(left);
+        let velocity = station.rotation() * (along * speed - left * off_center * CENTERING);
+
+        // details lie either way round on the conveyor
+        let rotation = detail.rotation();
+        let mut heading = station.rotation() * pose.rotation;
+        if (rotation * Vec3::Z).dot(heading * Vec3::Z) < 0.0 {
+            heading *= Quat::from_rotation_y(PI);
+        }
+        let mut turn = heading * rotation.inverse();
+        if turn.w < 0.0 {
+            turn = -turn;
+        }
+        (velocity, turn.to_scaled_axis() * ALIGNING)
+    }
+}
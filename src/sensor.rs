@@ -1,6 +1,22 @@
+use std::{fmt, time::Duration};
+
+use avian3d::prelude::*;
 use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    io::{Io, SwitchSet},
+    simclock::SimRng,
+    sysorder::ScanSet,
+};
 
-use crate::io::SwitchSet;
+pub struct SensorPlugin;
+
+impl Plugin for SensorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate, apply_sensor_faults.in_set(ScanSet::Input));
+    }
+}
 
 #[derive(EntityEvent)]
 #[entity_event(propagate)]
@@ -38,3 +54,219 @@ pub enum SensorPosition {
     ProximityUp,
     ProximityDown,
 }
+
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+/// Injected fault, the sensor reports something other than what it detects
+pub enum SensorFault {
+    StuckOn,
+    StuckOff,
+    /// every scan the reported value flips with this probability
+    Flicker(f32),
+    /// changes are reported once they have been stable this long
+    Delay(Duration),
+    /// reads 0 and sets [`SensorSignal::wire_break`]
+    WireBreak,
+}
+
+impl SensorFault {
+    /// Parse `stuck_on`, `stuck_off`, `flicker 0.2`, `delay 150ms` or `wire_break`
+    /// ```
+    /// # use std::time::Duration;
+    /// # use cybercrab::sensor::SensorFault;
+    /// assert_eq!(SensorFault::parse(&["flicker", "0.2"]).unwrap(), SensorFault::Flicker(0.2));
+    /// let delay = SensorFault::parse(&["delay", "150ms"]).unwrap();
+    /// assert_eq!(delay, SensorFault::Delay(Duration::from_millis(150)));
+    /// assert!(SensorFault::parse(&["flicker", "2"]).is_err());
+    /// ```
+    pub fn parse(words: &[&str]) -> Result<Self> {
+        let fault = match words {
+            ["stuck_on"] => Self::StuckOn,
+            ["stuck_off"] => Self::StuckOff,
+            ["wire_break"] => Self::WireBreak,
+            ["flicker", probability] => {
+                let probability: f32 = probability.parse()?;
+                if !(0.0..=1.0).contains(&probability) {
+                    return Err(format!("flicker probability {probability} is not 0 to 1").into());
+                }
+                Self::Flicker(probability)
+            }
+            ["delay", ms] => {
+                let ms: u64 = ms.trim_end_matches("ms").parse()?;
+                Self::Delay(Duration::from_millis(ms))
+            }
+            _ => return Err(format!("unknown fault '{}'", words.join(" ")).into()),
+        };
+        Ok(fault)
+    }
+}
+
+impl fmt::Display for SensorFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StuckOn => write!(f, "stuck_on"),
+            Self::StuckOff => write!(f, "stuck_off"),
+            Self::Flicker(probability) => write!(f, "flicker {probability}"),
+            Self::Delay(delay) => write!(f, "delay {}ms", delay.as_millis()),
+            Self::WireBreak => write!(f, "wire_break"),
+        }
+    }
+}
+
+#[derive(Component, Debug, Default)]
+/// What a sensor detects and what it reports, they only differ while a
+/// [`SensorFault`] is active
+pub struct SensorSignal {
+    pub detected: bool,
+    pub reported: bool,
+    /// diagnostic of a broken wire, like the channel fault of an input card
+    pub wire_break: bool,
+    /// distance to what the sensor sees, for sensors that measure it
+    pub distance: Option<f32>,
+    detected_since: Duration,
+}
+
+impl SensorSignal {
+    /// Record a new detection, true when it should be reported right away
+    pub fn detect(&mut self, detected: bool, now: Duration, fault: Option<&SensorFault>) -> bool {
+        if detected != self.detected {
+            self.detected = detected;
+            self.detected_since = now;
+        }
+        if fault.is_some() {
+            return false;
+        }
+        self.reported = detected;
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Reflect)]
+/// What a sensor casts into the scene to find objects
+pub enum SensingShape {
+    /// infinitely thin beam
+    #[default]
+    Ray,
+    /// sphere of this radius swept along the beam, for wide beams and
+    /// sensing faces
+    Sphere(f32),
+}
+
+/// Distance to the nearest object along `direction`, objects the cast starts
+/// inside of are at distance 0
+pub fn cast_distance(
+    spatial: &SpatialQuery,
+    origin: Vec3,
+    direction: Dir3,
+    max_distance: f32,
+    shape: SensingShape,
+    filter: &SpatialQueryFilter,
+) -> Option<f32> {
+    match shape {
+        SensingShape::Ray => spatial
+            .cast_ray(origin, direction, max_distance, true, filter)
+            .map(|hit| hit.distance),
+        SensingShape::Sphere(radius) => {
+            let config = ShapeCastConfig::from_max_distance(max_distance);
+            let sphere = Collider::sphere(radius);
+            spatial
+                .cast_shape(&sphere, origin, Quat::IDENTITY, direction, &config, filter)
+                .map(|hit| hit.distance)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+/// Distance where a sensor switches on, it only switches off again once the
+/// object is `hysteresis` farther away so it does not chatter at the edge
+pub struct SwitchingPoint {
+    pub distance: f32,
+    pub hysteresis: f32,
+}
+
+impl SwitchingPoint {
+    /// Real sensors have a hysteresis of about a tenth of the switching distance
+    pub fn new(distance: f32) -> Self {
+        Self {
+            distance,
+            hysteresis: distance * 0.1,
+        }
+    }
+    /// Farthest distance anything can switch the sensor
+    pub fn reach(&self) -> f32 {
+        self.distance + self.hysteresis
+    }
+    /// Whether a sensor that is `on` stays or turns on with an object at `hit`
+    /// ```
+    /// # use cybercrab::sensor::SwitchingPoint;
+    /// let point = SwitchingPoint { distance: 0.4, hysteresis: 0.05 };
+    /// assert!(point.switch(false, Some(0.4)));
+    /// assert!(!point.switch(false, Some(0.42)));
+    /// assert!(point.switch(true, Some(0.42)));
+    /// assert!(!point.switch(true, Some(0.46)));
+    /// assert!(!point.switch(true, None));
+    /// ```
+    pub fn switch(&self, on: bool, hit: Option<f32>) -> bool {
+        let limit = if on { self.reach() } else { self.distance };
+        hit.is_some_and(|distance| distance <= limit)
+    }
+}
+
+/// Pass a change of what a sensor detects on to its input, healthy sensors
+/// switch right away, faulty ones once per scan in [`SensorPlugin`]
+pub fn report(
+    cmd: &mut Commands,
+    entity: Entity,
+    signal: &mut SensorSignal,
+    detected: bool,
+    now: Duration,
+    fault: Option<&SensorFault>,
+) {
+    if detected != signal.detected && signal.detect(detected, now, fault) {
+        cmd.trigger(SwitchSet {
+            entity,
+            closed: detected,
+            kind: Io::Input,
+        });
+    }
+}
+
+/// Report what faulty sensors make of their detection, and what healthy
+/// sensors detect once their fault is cleared
+fn apply_sensor_faults(
+    mut cmd: Commands,
+    mut sensors: Query<(Entity, &mut SensorSignal, Option<&SensorFault>)>,
+    mut rng: ResMut<SimRng>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    for (entity, mut signal, fault) in sensors.iter_mut() {
+        let reported = match fault {
+            None => signal.detected,
+            Some(SensorFault::StuckOn) => true,
+            Some(SensorFault::StuckOff | SensorFault::WireBreak) => false,
+            Some(SensorFault::Flicker(probability)) => {
+                signal.detected ^ rng.random_bool(probability.clamp(0.0, 1.0) as f64)
+            }
+            Some(SensorFault::Delay(delay)) => {
+                if now.saturating_sub(signal.detected_since) >= *delay {
+                    signal.detected
+                } else {
+                    signal.reported
+                }
+            }
+        };
+        let wire_break = fault == Some(&SensorFault::WireBreak);
+        if signal.wire_break != wire_break {
+            signal.wire_break = wire_break;
+        }
+        if signal.reported == reported {
+            continue;
+        }
+        signal.reported = reported;
+        cmd.trigger(SwitchSet {
+            entity,
+            closed: reported,
+            kind: Io::Input,
+        });
+    }
+}
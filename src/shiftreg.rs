@@ -1,20 +1,25 @@
+use std::ops::Range;
+
 use avian3d::prelude::*;
 use bevy::{color::palettes::css, prelude::*};
 use bitvec::BitArr;
 
-use crate::{physics::PhysLayer, tbana::TransportBana, InitSet};
+use crate::{
+    pallet::PalletId, physics::PhysLayer, sysorder::ScanSet, tbana::TransportBana, InitSet,
+};
 
 pub struct ShiftRegPlugin;
 
 impl Plugin for ShiftRegPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<RegisterPosition>();
+        app.register_type::<RegisterZones>();
         app.register_type::<Register>();
         app.init_resource::<DetailAssets>();
         app.insert_resource(Register::new(50));
         app.add_systems(Startup, load_assets.in_set(InitSet::LoadAssets));
         app.add_systems(Startup, spawn_test_detail.in_set(InitSet::Spawn));
-        app.add_systems(Update, animate_test_detail);
+        app.add_systems(FixedUpdate, animate_test_detail.in_set(ScanSet::Plant));
         app.add_observer(on_shift_over);
     }
 }
@@ -24,7 +29,7 @@ fn spawn_test_detail(mut cmd: Commands, assets: Res<DetailAssets>) {
         DetailBundle::new(&assets),
         Name::new("Detail_1"),
         RigidBody::Kinematic,
-        Transform::from_xyz(0.0, 0.6, 0.0),
+        Transform::from_xyz(0.0, 0.69, 0.0),
         CollidingEntities::default(),
         LinearVelocity(Vec3 {
             z: 0.1,
@@ -64,6 +69,9 @@ fn load_assets(
     detail_resource.collider = Collider::cuboid(0.3, 0.3, 1.5);
 }
 
+/// Density of details in kg/m³, their mass matters when wheels drive them by friction
+pub const DETAIL_DENSITY: f32 = 400.0;
+
 #[derive(Component)]
 pub struct Detail;
 
@@ -73,6 +81,7 @@ pub struct DetailBundle {
     mesh: Mesh3d,
     material: MeshMaterial3d<StandardMaterial>,
     collider: Collider,
+    density: ColliderDensity,
     phys_layers: CollisionLayers,
 }
 
@@ -83,6 +92,7 @@ impl DetailBundle {
             mesh: Mesh3d(assets.base_shape.clone()),
             material: MeshMaterial3d(assets.normal_material.clone()),
             collider: assets.collider.clone(),
+            density: ColliderDensity(DETAIL_DENSITY),
             phys_layers: CollisionLayers::new(PhysLayer::Detail, LayerMask::ALL),
         }
     }
@@ -104,8 +114,22 @@ impl RegisterPosition {
     pub fn as_usize(&self) -> usize {
         self.0 as usize
     }
+    /// Positions of a station in transport order, only one without [`RegisterZones`]
+    pub fn span(&self, zones: Option<&RegisterZones>) -> Range<usize> {
+        let n = zones.map_or(1, |zones| zones.0 as usize);
+        self.as_usize()..self.as_usize() + n
+    }
+    /// Position details leave the station from
+    pub fn discharge(&self, zones: Option<&RegisterZones>) -> usize {
+        self.span(zones).end - 1
+    }
 }
 
+#[derive(Component, Reflect, Clone, Copy, Debug)]
+/// Number of positions a station with several zones holds, from its
+/// [`RegisterPosition`] at the infeed on, in the order details queue up
+pub struct RegisterZones(pub RegisterIndex);
+
 #[derive(Default, Clone, Reflect, Debug)]
 pub struct DetailState {
     #[reflect(ignore)]
@@ -126,6 +150,8 @@ impl DetailState {
 #[reflect(Resource)]
 pub struct Register {
     pub details: Vec<Option<DetailState>>,
+    /// pallet at each position, details on a pallet share its position
+    pub pallets: Vec<Option<PalletId>>,
 }
 
 #[derive(Event)]
@@ -134,19 +160,24 @@ pub struct ShiftOver {
     pub to: Entity,
 }
 
-fn on_shift_over(trigger: On<ShiftOver>, q: Query<&RegisterPosition>, mut reg: ResMut<Register>) {
-    let Ok([from, to]) = q.get_many([trigger.from, trigger.to]) else {
+fn on_shift_over(
+    trigger: On<ShiftOver>,
+    q: Query<(&RegisterPosition, Option<&RegisterZones>)>,
+    mut reg: ResMut<Register>,
+) {
+    let Ok([(from, zones), (to, _)]) = q.get_many([trigger.from, trigger.to]) else {
         return;
     };
-    let Some(detail) = reg.details[from.as_usize()].take() else {
+    // details leave from the last zone and arrive in the first
+    let (from, to) = (from.discharge(zones), to.as_usize());
+    if !reg.occupied(from) {
         return;
-    };
-    let to_state = &mut reg.details[to.as_usize()];
-    if to_state.is_some() {
-        panic!("cant shift part to occuipeid pos");
-        // return;
     }
-    *to_state = Some(detail);
+    if reg.occupied(to) {
+        warn!("cant shift part from position {from} to occuipeid position {to}");
+        return;
+    }
+    reg.move_slot(from, to);
 }
 
 impl Register {
@@ -155,7 +186,56 @@ impl Register {
         if n_details > 0 {
             details[0] = Some(DetailState::default());
         }
-        Self { details }
+        Self {
+            details,
+            pallets: vec![None; n_details],
+        }
+    }
+    /// Grow the register to `len` positions, it never shrinks
+    pub fn resize(&mut self, len: usize) {
+        if self.details.len() < len {
+            self.details.resize(len, None);
+        }
+        if self.pallets.len() < len {
+            self.pallets.resize(len, None);
+        }
+    }
+    /// Rebuild the register with `len` positions, moving the slot at `from`
+    /// to `to` for each move and dropping everything else
+    /// ```
+    /// # use cybercrab::shiftreg::Register;
+    /// let mut reg = Register::new(3);
+    /// reg.remap(2, [(0, 1)]);
+    /// assert!(!reg.occupied(0));
+    /// assert!(reg.occupied(1));
+    /// assert_eq!(reg.details.len(), 2);
+    /// ```
+    pub fn remap(&mut self, len: usize, moves: impl IntoIterator<Item = (usize, usize)>) {
+        let mut details = vec![None; len];
+        let mut pallets = vec![None; len];
+        for (from, to) in moves.into_iter().filter(|(_, to)| *to < len) {
+            details[to] = self.details.get_mut(from).and_then(Option::take);
+            pallets[to] = self.pallets.get_mut(from).and_then(Option::take);
+        }
+        self.details = details;
+        self.pallets = pallets;
+    }
+    /// A detail or an empty pallet is at `idx`
+    pub fn occupied(&self, idx: usize) -> bool {
+        let detail = self.details.get(idx).is_some_and(Option::is_some);
+        detail || self.pallet_at(idx).is_some()
+    }
+    /// Move the detail and pallet at `from` to `to`, whatever is at `to` is lost
+    pub fn move_slot(&mut self, from: usize, to: usize) {
+        self.details[to] = self.details[from].take();
+        self.pallets[to] = self.pallets[from].take();
+    }
+    pub fn pallet_at(&self, idx: usize) -> Option<PalletId> {
+        self.pallets.get(idx).copied().flatten()
+    }
+    /// Position of a pallet, if the register tracks it
+    pub fn find_pallet(&self, id: PalletId) -> Option<usize> {
+        self.pallets.iter().position(|pallet| *pallet == Some(id))
     }
     pub fn pop_detail(&mut self) -> Option<DetailState> {
         if self.details.len() == 0 {
@@ -0,0 +1,140 @@
+use bevy::{
+    app::{FixedMain, RunFixedMainLoopSystems},
+    prelude::*,
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// Scan rate of plant logic and physics
+pub const SCAN_HZ: f64 = 100.0;
+/// Selectable simulation speeds, relative to real time
+pub const SPEEDS: [f32; 7] = [0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0];
+
+pub struct SimClockPlugin {
+    pub seed: u64,
+}
+
+impl Default for SimClockPlugin {
+    fn default() -> Self {
+        Self { seed: 0x5eed }
+    }
+}
+
+impl Plugin for SimClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SimClock>();
+        app.insert_resource(Time::<Fixed>::from_hz(SCAN_HZ));
+        app.insert_resource(SimClock {
+            seed: self.seed,
+            ticks: 0,
+            step: false,
+        });
+        app.insert_resource(SimRng(StdRng::seed_from_u64(self.seed)));
+        app.add_systems(FixedFirst, count_ticks);
+        app.add_systems(
+            RunFixedMainLoop,
+            run_step.in_set(RunFixedMainLoopSystems::AfterFixedMainLoop),
+        );
+        app.add_systems(Update, time_control_keys);
+        app.add_observer(on_time_control);
+    }
+}
+
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+/// Bookkeeping of the fixed step simulation
+pub struct SimClock {
+    pub seed: u64,
+    ticks: u64,
+    /// a single step was asked for while paused
+    step: bool,
+}
+
+impl SimClock {
+    /// Number of scans simulated so far
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+/// The only source of randomness in the simulation, seeded from [`SimClock::seed`]
+/// so that runs with the same inputs are reproducible
+pub struct SimRng(pub StdRng);
+
+#[derive(Event, Debug, Clone, Copy)]
+pub enum TimeControl {
+    Pause,
+    Resume,
+    /// Run exactly one scan, only while paused
+    Step,
+    /// Relative speed, clamped to 0.1x - 10x
+    SetSpeed(f32),
+}
+
+fn count_ticks(mut clock: ResMut<SimClock>) {
+    clock.ticks += 1;
+}
+
+fn on_time_control(
+    trigger: On<TimeControl>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    mut clock: ResMut<SimClock>,
+) {
+    match *trigger {
+        TimeControl::Pause => virtual_time.pause(),
+        TimeControl::Resume => virtual_time.unpause(),
+        TimeControl::Step => {
+            if virtual_time.is_paused() {
+                clock.step = true;
+            }
+        }
+        TimeControl::SetSpeed(speed) => {
+            let (min, max) = (SPEEDS[0], SPEEDS[SPEEDS.len() - 1]);
+            virtual_time.set_relative_speed(speed.clamp(min, max));
+        }
+    }
+}
+
+/// Run one scan while paused, like the fixed loop runs one once a whole
+/// timestep is owed
+fn run_step(world: &mut World) {
+    if !std::mem::take(&mut world.resource_mut::<SimClock>().step) {
+        return;
+    }
+    let mut fixed_time = world.resource_mut::<Time<Fixed>>();
+    let step = fixed_time.timestep();
+    fixed_time.advance_by(step);
+    *world.resource_mut::<Time>() = world.resource::<Time<Fixed>>().as_generic();
+    world.run_schedule(FixedMain);
+    *world.resource_mut::<Time>() = world.resource::<Time<Virtual>>().as_generic();
+}
+
+/// P pause, . single step, [ slower, ] faster
+fn time_control_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    virtual_time: Res<Time<Virtual>>,
+    mut cmd: Commands,
+) {
+    if keys.just_pressed(KeyCode::KeyP) {
+        cmd.trigger(if virtual_time.is_paused() {
+            TimeControl::Resume
+        } else {
+            TimeControl::Pause
+        });
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        cmd.trigger(TimeControl::Step);
+    }
+    let speed = virtual_time.relative_speed();
+    let current = SPEEDS
+        .iter()
+        .position(|s| *s >= speed)
+        .unwrap_or(SPEEDS.len() - 1);
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        cmd.trigger(TimeControl::SetSpeed(SPEEDS[current.saturating_sub(1)]));
+    }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        let faster = (current + 1).min(SPEEDS.len() - 1);
+        cmd.trigger(TimeControl::SetSpeed(SPEEDS[faster]));
+    }
+}
@@ -5,6 +5,13 @@ pub struct SysOrderPlugin;
 impl Plugin for SysOrderPlugin {
     fn build(&self, app: &mut App) {
         app.configure_sets(Startup, (InitSet::LoadAssets, InitSet::Spawn).chain());
+        app.init_state::<SimMode>();
+        app.configure_sets(
+            FixedUpdate,
+            (ScanSet::Input, ScanSet::Control, ScanSet::Plant)
+                .chain()
+                .run_if(in_state(SimMode::Running)),
+        );
     }
 }
 
@@ -13,3 +20,22 @@ pub enum InitSet {
     LoadAssets,
     Spawn,
 }
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+/// Order of one scan, run in [`FixedUpdate`] while the simulation is running
+pub enum ScanSet {
+    /// field devices to the process image
+    Input,
+    /// control logic
+    Control,
+    /// actuators act on the plant
+    Plant,
+}
+
+#[derive(States, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+/// Running simulates the plant, editing pauses it so the layout can be changed
+pub enum SimMode {
+    #[default]
+    Running,
+    Editing,
+}
@@ -1,43 +1,87 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, path::Path, time::SystemTime};
 
-use avian3d::prelude::{Collider, CollidingEntities, CollisionLayers, LinearVelocity};
+use avian3d::prelude::{
+    AngularVelocity, Collider, CollidingEntities, CollisionLayers, Friction, LinearVelocity,
+    RigidBody,
+};
 use bevy::color::palettes::css;
 use bevy::prelude::{Mesh3d, *};
 
-use crate::fotocell::{
-    on_fotocell_blocked, on_fotocell_unblocked, Fotocell, FotocellAssets, FotocellBundle,
-};
-use crate::io::{Dio, DioPin, IoDevices, NodeId, Switch};
+use crate::accumulation::AccumulationConveyor;
+use crate::fotocell::{Fotocell, FotocellAssets, FotocellBundle, FotocellKind};
+use crate::io::{Dio, DioPin, FieldIo, Io, IoDevices, NodeId, Switch};
+use crate::layout::ApplyLayout;
+use crate::pallet::Pallet;
 use crate::physics::PhysLayer;
-use crate::sensor::{PositionReached, SensorPosition};
-use crate::shiftreg::{Register, RegisterPosition, ShiftOver};
-use crate::sysorder::InitSet;
+use crate::plc::{run_sfc_programs, sfc::SfcProgram};
+use crate::reload::{modified, reload_due, LoadErrors};
+use crate::safety::SafetyRelay;
+use crate::segment::{ConveyorPath, WHEEL_PITCH};
+use crate::sensor::SensorPosition;
+use crate::shiftreg::{Detail, Register, RegisterPosition, ShiftOver};
+use crate::sysorder::{InitSet, ScanSet};
 
 pub struct TbanaPlugin;
 
 impl Plugin for TbanaPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<AutoMode>();
+        app.register_type::<RunState>();
+        app.register_type::<ManualJog>();
+        app.register_type::<LineControl>();
         app.register_type::<PushTo>();
         app.register_type::<Reciver>();
         app.register_type::<PullFrom>();
         app.register_type::<Giver>();
         app.register_type::<Movimot>();
+        app.register_type::<TransportMode>();
+        app.register_type::<ConveyorPath>();
         app.add_message::<PushRequest>();
         app.init_resource::<TBanaAssets>();
-        app.add_systems(Startup, load_assets.in_set(InitSet::LoadAssets));
+        app.init_resource::<LineControl>();
+        app.init_resource::<TransportMode>();
+        app.init_resource::<StationChart>();
+        app.add_systems(
+            Startup,
+            (load_assets, load_station_chart).in_set(InitSet::LoadAssets),
+        );
+        app.add_systems(FixedUpdate, gate_station_charts.in_set(ScanSet::Input));
+        app.add_systems(
+            FixedUpdate,
+            shift_on_chart_handover
+                .after(run_sfc_programs)
+                .in_set(ScanSet::Control),
+        );
+        app.add_systems(Update, watch_station_chart.run_if(reload_due));
         app.add_systems(
-            Update,
+            FixedUpdate,
             (
-                motor_effect,
                 // tbana_motor_logic,
-                push_request_handler,
+                set_tbana_ready,
                 request_push,
+                push_request_handler,
                 stop_pushing,
-                set_tbana_ready,
-            ),
+                stop_at_limit,
+                apply_manual_jog,
+            )
+                .chain()
+                .in_set(ScanSet::Control),
+        );
+        app.add_systems(
+            FixedUpdate,
+            (
+                sync_transport_mode,
+                motor_effect.run_if(resource_equals(TransportMode::Kinematic)),
+                drive_wheels.run_if(resource_equals(TransportMode::Friction)),
+            )
+                .chain()
+                .in_set(ScanSet::Plant),
         );
         app.add_observer(on_insert_tbana);
+        app.add_observer(clear_station_chart_errors);
+        app.add_observer(on_switch_mode);
+        app.add_observer(on_line_start);
+        app.add_observer(on_line_stop);
     }
 }
 
@@ -76,7 +120,7 @@ pub enum SensorRole {
     BackProximity,
 }
 
-#[derive(Component, Reflect, Copy, Clone, Debug, Default)]
+#[derive(Component, Reflect, Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub enum Direction {
     #[default]
     Forward,
@@ -95,6 +139,7 @@ pub struct InsertTbana4x2 {
     register_pos: RegisterPosition,
     push_to: Option<PushTo>,
     pull_from: Option<PullFrom>,
+    path: Option<ConveyorPath>,
 }
 
 impl InsertTbana4x2 {
@@ -121,13 +166,23 @@ impl InsertTbana4x2 {
             register_pos,
             push_to,
             pull_from,
+            path: None,
         }
     }
+
+    /// Follow `path` from the infeed at the transform instead of the straight bed
+    pub fn with_path(mut self, path: ConveyorPath) -> Self {
+        self.path = Some(path);
+        self
+    }
 }
 
 fn stop_pushing(
     mut cmd: Commands,
-    pushers: Query<(Entity, &PushTo, &TransportState, &Children), Without<SensorPosition>>,
+    pushers: Query<
+        (Entity, &PushTo, &TransportState, &Children),
+        (Without<SensorPosition>, Without<AccumulationConveyor>),
+    >,
     sensors: Query<(&NodeId, &DioPin), (With<SensorPosition>, With<Switch>)>,
     io: Res<IoDevices>,
 ) {
@@ -156,40 +211,53 @@ fn stop_pushing(
     }
 }
 
-fn on_sensor_pos(
-    mut trigger: On<PositionReached>,
+/// Stop a receiving station once the limit sensor at its outgoing end sees
+/// the detail in the process image, so the stop waits for the fieldbus
+fn stop_at_limit(
     mut cmd: Commands,
-    directions: Query<(&Direction, &TransportState)>,
+    stations: Query<(Entity, &Direction, &TransportState, &Children)>,
+    sensors: Query<(&SensorPosition, &NodeId, &DioPin), With<Switch>>,
+    io: Res<IoDevices>,
 ) {
-    let Ok((bana_dir, &state)) = directions.get(trigger.entity) else {
-        return;
-    };
-    if !(state == TransportState::Reciving) {
-        return;
+    for (entity, direction, state, children) in stations.iter() {
+        if *state != TransportState::Reciving {
+            continue;
+        }
+        let reached = children
+            .iter()
+            .filter_map(|child| sensors.get(child).ok())
+            .filter(|(position, ..)| {
+                matches!(
+                    (direction, position),
+                    (Direction::Forward, SensorPosition::LimitFront)
+                        | (Direction::Reverse, SensorPosition::LimitBack)
+                )
+            })
+            .any(|(_, node, pin)| io.get_input_bit(*node, *pin) == Some(true));
+        if reached {
+            cmd.trigger(StopRunning(entity));
+        }
     }
+}
 
-    trigger.propagate(false);
-    let entity = trigger.entity;
-    match (bana_dir, trigger.position) {
-        (Direction::Forward, SensorPosition::LimitFront) => cmd.trigger((StopRunning(entity))),
-        (Direction::Reverse, SensorPosition::LimitBack) => cmd.trigger(StopRunning(entity)),
-        // (Direction::Reverse, SensorPosition::ProximityBack) => todo!(),
-        // (Direction::Forward, SensorPosition::ProximityFront) => todo!(),
-        _ => (),
-    }
+/// True when a station is allowed to run the transfer handshake on its own
+pub(crate) fn in_auto(auto: &AutoMode, run: &RunState) -> bool {
+    auto.enabled && *run == RunState::Running
 }
 
 fn request_push(
-    pushers: Query<(Entity, &PushTo, &TransportState)>,
+    pushers: Query<(Entity, &PushTo, &TransportState, &AutoMode, &RunState)>,
     mut writer: MessageWriter<PushRequest>,
 ) {
-    let filter_map = pushers.iter().filter_map(|(from, pushto, state)| {
-        if state == &TransportState::ReadySend {
-            Some((from, pushto.0))
-        } else {
-            None
-        }
-    });
+    let filter_map = pushers
+        .iter()
+        .filter_map(|(from, pushto, state, auto, run)| {
+            if state == &TransportState::ReadySend && in_auto(auto, run) {
+                Some((from, pushto.0))
+            } else {
+                None
+            }
+        });
     for (from, to) in filter_map {
         writer.write(PushRequest { from, to });
     }
@@ -197,21 +265,33 @@ fn request_push(
 
 fn push_request_handler(
     mut push_requests: MessageReader<PushRequest>,
-    q: Query<&TransportState>,
+    q: Query<(
+        &TransportState,
+        &AutoMode,
+        &RunState,
+        Option<&AccumulationConveyor>,
+    )>,
     mut cmd: Commands,
 ) {
     for push in push_requests.read() {
         let Ok(_) = q.get(push.from) else {
             continue;
         };
-        let Ok(reciver_state) = q.get(push.to) else {
+        let Ok((reciver_state, auto, run, accumulation)) = q.get(push.to) else {
             continue;
         };
-        if !(reciver_state == &TransportState::ReadyRecive) {
+        // accumulation conveyors take details whenever their first zone is free
+        let ready = match accumulation {
+            Some(conveyor) => conveyor.accepts(),
+            None => reciver_state == &TransportState::ReadyRecive,
+        };
+        if !ready || !in_auto(auto, run) {
             continue;
         }
         cmd.trigger(StartSending { entity: push.from });
-        cmd.trigger(StartRecive(push.to));
+        if accumulation.is_none() {
+            cmd.trigger(StartRecive(push.to));
+        }
     }
 }
 
@@ -281,14 +361,18 @@ fn on_stop_running_motor(
 }
 
 fn set_tbana_ready(
-    mut tbana: Query<(&mut TransportState, &RegisterPosition), With<NoProcess>>,
+    mut tbana: Query<
+        (&mut TransportState, &RegisterPosition, &AutoMode, &RunState),
+        (With<NoProcess>, Without<SfcProgram>),
+    >,
     reg: Res<Register>,
 ) {
-    for (mut state, index) in tbana
+    for (mut state, index, ..) in tbana
         .iter_mut()
-        .filter(|(state, _)| (state.as_ref()) == &TransportState::NotReady)
+        .filter(|(state, ..)| (state.as_ref()) == &TransportState::NotReady)
+        .filter(|(_, _, auto, run)| in_auto(auto, run))
     {
-        if reg.details[index.0 as usize].is_some() {
+        if reg.occupied(index.as_usize()) {
             *state = TransportState::ReadySend;
         } else {
             *state = TransportState::ReadyRecive;
@@ -296,65 +380,215 @@ fn set_tbana_ready(
     }
 }
 
+/// Drive the motors of stations in manual mode from their [`ManualJog`] command
+fn apply_manual_jog(
+    stations: Query<(&ManualJog, &AutoMode, &RunState, &Children), Changed<ManualJog>>,
+    motors: Query<&Movimot>,
+    mut io: ResMut<IoDevices>,
+) {
+    for (jog, auto, run, children) in stations {
+        if auto.enabled || *run == RunState::Disabled {
+            continue;
+        }
+        for motor in children.iter().filter_map(|e| motors.get(e).ok()) {
+            let forward = **jog == Some(Direction::Forward);
+            let reverse = **jog == Some(Direction::Reverse);
+            io.set_output_bit(motor.dq.forward.node, motor.dq.forward.pin, forward);
+            io.set_output_bit(motor.dq.reverse.node, motor.dq.reverse.pin, reverse);
+        }
+    }
+}
+
+fn on_switch_mode(
+    trigger: On<SwitchMode>,
+    mut stations: Query<(&mut AutoMode, &mut ManualJog), With<TransportBana>>,
+    mut cmd: Commands,
+) {
+    let Ok((mut auto, mut jog)) = stations.get_mut(trigger.entity) else {
+        return;
+    };
+    if auto.enabled == trigger.auto {
+        return;
+    }
+    // whatever the station was doing, it has to come to a halt before the new mode takes over
+    auto.enabled = trigger.auto;
+    jog.set_if_neq(ManualJog(None));
+    cmd.trigger(StopRunning(trigger.entity));
+}
+
+fn on_line_start(
+    _trigger: On<LineStart>,
+    mut line: ResMut<LineControl>,
+    stations: Query<&mut RunState, With<TransportBana>>,
+) {
+    line.running = true;
+    for mut run in stations {
+        if *run == RunState::Ready {
+            *run = RunState::Running;
+        }
+    }
+}
+
+fn on_line_stop(
+    _trigger: On<LineStop>,
+    mut line: ResMut<LineControl>,
+    stations: Query<(Entity, &mut RunState), With<TransportBana>>,
+    mut cmd: Commands,
+) {
+    line.running = false;
+    for (entity, mut run) in stations {
+        if *run == RunState::Running {
+            *run = RunState::Ready;
+            cmd.trigger(StopRunning(entity));
+        }
+    }
+}
+
 fn motor_effect(
-    target: Query<(&CollidingEntities, &mut LinearVelocity), Without<Movimot>>,
-    motors: Query<(&Movimot, &Transform)>,
-    io: Res<IoDevices>,
+    target: Query<
+        (
+            &CollidingEntities,
+            &GlobalTransform,
+            &mut LinearVelocity,
+            Option<&mut AngularVelocity>,
+        ),
+        Without<Movimot>,
+    >,
+    motors: Query<(&Movimot, &GlobalTransform, &ChildOf)>,
+    paths: Query<(&ConveyorPath, &GlobalTransform)>,
+    io: Res<FieldIo>,
+    safety: Res<SafetyRelay>,
 ) {
-    for (colliding, mut velocity) in target {
+    for (colliding, at, mut velocity, mut spin) in target {
+        if !safety.outputs_enabled() {
+            // the safety relay cuts motor power, whatever the outputs say
+            velocity.0 = Vec3::ZERO;
+            if let Some(spin) = spin.as_mut() {
+                spin.0 = Vec3::ZERO;
+            }
+            continue;
+        }
         let motors = colliding.iter().filter_map(|id| motors.get(*id).ok());
         let speeds: Vec<_> = motors
-            .map(|(motor, transform)| {
-                let fw_node = motor.dq.forward.node;
-                let fw_pin = motor.dq.forward.pin;
-                let rev_node = motor.dq.reverse.node;
-                let rev_pin = motor.dq.reverse.pin;
-                let fw = io.get_output_bit(fw_node, fw_pin);
-                let rev = io.get_output_bit(motor.dq.reverse.node, motor.dq.reverse.pin);
-                let rapid =
-                    io.get_output_bit(motor.dq.rapid.node, motor.dq.rapid.pin) == Some(true);
-                let speed = if rapid {
-                    motor.fast_speed
-                } else {
-                    motor.slow_speed
-                };
-                match (fw, rev) {
-                    (Some(true), Some(true)) => {
-                        eprintln!("motor cant run in both directions");
-                        eprintln!("motor FW signal from address{:?}, pin{:?}", fw_node, fw_pin);
-                        eprintln!(
-                            "motor rev signal from address{:?}, pin{:?}",
-                            rev_node, rev_pin
-                        );
-                        dbg!(Vec3::ZERO)
-                    }
-                    (Some(true), _) => speed * transform.left(),
-                    (_, Some(true)) => -speed * transform.left(),
-                    // (None, None) => 0_f32,
-                    _ => Vec3::ZERO,
+            .map(|(motor, transform, parrent)| {
+                // both directions on stops the motor
+                let speed = motor.surface_speed(&io);
+                match paths.get(parrent.parent()) {
+                    Ok((path, station)) => path.field(station, at, speed),
+                    Err(_) => (speed * transform.left(), Vec3::ZERO),
                 }
             })
             .collect();
         let n = speeds.len();
         if n == 0 {
             velocity.0 = Vec3::ZERO;
+            if let Some(spin) = spin.as_mut() {
+                spin.0 = Vec3::ZERO;
+            }
+            continue;
+        }
+        let (linear, angular) = speeds
+            .into_iter()
+            .fold((Vec3::ZERO, Vec3::ZERO), |(l, a), (v, w)| (l + v, a + w));
+        velocity.0 = linear / (n as f32);
+        if let Some(spin) = spin.as_mut() {
+            spin.0 = angular / (n as f32);
+        }
+    }
+}
+
+/// Spin the wheels at the surface speed of their motor, the contacts drag the details along
+fn drive_wheels(
+    mut wheels: Query<(&Movimot, &GlobalTransform, &mut AngularVelocity)>,
+    io: Res<FieldIo>,
+    safety: Res<SafetyRelay>,
+) {
+    for (motor, transform, mut spin) in wheels.iter_mut() {
+        let speed = if safety.outputs_enabled() {
+            motor.surface_speed(&io)
+        } else {
+            0.0
+        };
+        // spinning about its extruded z axis moves the top of the rim along its left at rest
+        spin.0 = *transform.back() * speed / WHEEL_RADIUS;
+    }
+}
+
+/// Give wheels, details and pallets the bodies the [`TransportMode`] asks for,
+/// also for those spawned after the mode was picked
+fn sync_transport_mode(
+    mode: Res<TransportMode>,
+    mut wheels: Query<(Entity, Has<RigidBody>, &mut Transform, &WheelRest)>,
+    carried: Query<(Entity, &RigidBody), Or<(With<Detail>, With<Pallet>)>>,
+    mut cmd: Commands,
+) {
+    let friction = *mode == TransportMode::Friction;
+    for (entity, has_body, mut transform, rest) in wheels.iter_mut() {
+        match (friction, has_body) {
+            (true, false) => {
+                cmd.entity(entity).insert((
+                    RigidBody::Kinematic,
+                    AngularVelocity::ZERO,
+                    Friction::new(WHEEL_FRICTION),
+                ));
+            }
+            (false, true) => {
+                cmd.entity(entity)
+                    .remove::<(RigidBody, AngularVelocity, Friction)>();
+                // undo the spin, kinematic transport pushes along the wheels left
+                transform.rotation = rest.0;
+            }
+            _ => {}
+        }
+    }
+    let body = mode.carried_body();
+    for (entity, current) in carried.iter() {
+        if *current == body {
             continue;
         }
-        let avg_speed = speeds.into_iter().sum::<Vec3>() / (n as f32);
-        velocity.0 = avg_speed;
+        let mut entity = cmd.entity(entity);
+        entity.insert((body, AngularVelocity::ZERO));
+        if friction {
+            entity.insert(Friction::new(WHEEL_FRICTION));
+        }
     }
 }
 
 // fn tbana_motor_effects(motors: Query<(&CollidingEntities, &MovimotDQ)>, io: Res<IoDevices>) {}
 
+/// Fotocells of a station, in the order of its inputs
+pub const FOTOCELL_NAMES: [&str; 4] = ["back_end", "back_slow", "fron_slow", "front_end"];
+/// Motor outputs of a station, in the order of its outputs
+pub const MOTOR_SIGNALS: [&str; 2 * 3] = [
+    "motor 0 fw",
+    "motor 0 rev",
+    "motor 0 rapid",
+    "motor 1 fw",
+    "motor 1 rev",
+    "motor 1 rapid",
+];
+
 fn on_insert_tbana(
     spawn: On<InsertTbana4x2>,
     mut cmd: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
     fotocell_assets: Res<FotocellAssets>,
     tbana_assets: Res<TBanaAssets>,
+    station_chart: Res<StationChart>,
+    mut errors: ResMut<LoadErrors>,
 ) {
-    let z_values = [-0.9, -0.7, 0.7, 0.9];
-    let fc_names = ["back_end", "back_slow", "fron_slow", "front_end"];
+    let length = spawn.path.map_or(STATION_LENGTH, |path| path.length());
+    // `s` along the conveyor from the infeed, the straight bed is centered on the station
+    let place = |s: f32, offset: Vec3| match spawn.path {
+        None => Transform::from_translation(offset.with_z(s - STATION_LENGTH / 2.0)),
+        Some(path) => {
+            let pose = path.pose(s);
+            Transform::from_translation(pose.transform_point(offset)).with_rotation(pose.rotation)
+        }
+    };
+
+    let s_values = [0.1, 0.3, length - 0.3, length - 0.1];
+    let fc_names = FOTOCELL_NAMES;
     let fc_roles = [
         SensorPosition::LimitBack,
         SensorPosition::ProximityBack,
@@ -362,51 +596,54 @@ fn on_insert_tbana(
         SensorPosition::LimitFront,
     ];
     let io_inputs = spawn.io_inputs.iter();
-    let phys_layers = CollisionLayers::new(PhysLayer::Sensor, PhysLayer::Detail);
     let fotocells: Vec<_> = io_inputs
-        .zip(z_values)
+        .zip(s_values)
         .zip(fc_names)
         .zip(fc_roles)
-        .map(|(((dio, z), name), role)| {
-            let coord = Vec3 {
-                x: 0.45,
-                y: 0.53,
-                z,
-            };
-            let mut transform = Transform::from_translation(coord);
+        .map(|(((dio, s), name), role)| {
+            let mut transform = place(s, Vec3::new(0.45, 0.6, 0.0));
             transform.rotate_local_y(-90_f32.to_radians());
-            let fotocell = FotocellBundle::new(name, dio.pin, &fotocell_assets, dio.node, 0.8);
-            cmd.spawn((fotocell, transform, phys_layers, role))
-                .observe(on_fotocell_blocked)
-                .observe(on_fotocell_unblocked)
-                .id()
+            let kind = FotocellKind::Reflex;
+            let fotocell =
+                FotocellBundle::new(name, dio.pin, &fotocell_assets, dio.node, kind, 0.8);
+            cmd.spawn((fotocell, transform, role)).id()
         })
         .collect();
 
-    let z_values = [-0.8, 0.8];
+    // a wheel at each end of a straight bed, segments spread them over their halves
+    let s_values: [Vec<f32>; 2] = match spawn.path {
+        None => [vec![0.2], vec![length - 0.2]],
+        Some(_) => {
+            let half = length / 2.0;
+            let n = (half / WHEEL_PITCH).round().max(1.0) as usize;
+            let spread =
+                |first: f32| (0..n).map(move |k| first + (k as f32 + 0.5) * half / n as f32);
+            [spread(0.0).collect(), spread(half).collect()]
+        }
+    };
     let mut io_outputs = spawn.io_outputs.iter();
     let phys_layers = CollisionLayers::new(PhysLayer::Actuator, PhysLayer::Detail);
-    let motors_wheels: Vec<_> = z_values
-        .into_iter()
-        .map(|z| {
-            let forward = *io_outputs.next().unwrap();
-            let reverse = *io_outputs.next().unwrap();
-            let rapid = *io_outputs.next().unwrap();
-            let bundle = TransportWheelBundle::new(
-                &tbana_assets,
-                MovimotDQ {
-                    forward,
-                    reverse,
-                    rapid,
-                },
-            );
-            let mut transform = Transform::from_xyz(0.0, 0.45, z);
+    let mut motors_wheels = Vec::new();
+    for wheels in s_values {
+        let forward = *io_outputs.next().unwrap();
+        let reverse = *io_outputs.next().unwrap();
+        let rapid = *io_outputs.next().unwrap();
+        let dq = MovimotDQ {
+            forward,
+            reverse,
+            rapid,
+        };
+        for s in wheels {
+            let mut transform = place(s, Vec3::Y * WHEEL_HEIGHT);
             transform.rotate_local_y(90_f32.to_radians());
-            cmd.spawn((bundle, transform, phys_layers))
+            let bundle = TransportWheelBundle::new(&tbana_assets, dq, transform);
+            let wheel = cmd
+                .spawn((bundle, phys_layers))
                 .observe(on_stop_running_motor)
-                .id()
-        })
-        .collect();
+                .id();
+            motors_wheels.push(wheel);
+        }
+    }
 
     let bana_bundle = (
         TbanaBundle::new(&tbana_assets),
@@ -416,16 +653,29 @@ fn on_insert_tbana(
         spawn.register_pos,
     );
 
+    let mut bed = Vec::new();
+    if let Some(path) = spawn.path {
+        let piece = meshes.add(Cuboid::new(1.0, 1.0, path.bed_piece()));
+        for transform in path.bed() {
+            let material = MeshMaterial3d(tbana_assets.station_material());
+            bed.push(cmd.spawn((Mesh3d(piece.clone()), material, transform)).id());
+        }
+    }
+
     let mut tbana = cmd.entity(spawn.entity);
     tbana
         .insert(bana_bundle)
         .add_children(&fotocells[0..4])
-        .add_children(&motors_wheels[0..2])
+        .add_children(&motors_wheels)
         .observe(on_stop_running_tbana)
         .observe(on_start_reciving)
-        .observe(on_sensor_pos)
         .observe(on_start_sending);
 
+    if let Some(path) = spawn.path {
+        // the bed follows the path instead
+        tbana.remove::<Mesh3d>().insert(path).add_children(&bed);
+    }
+
     if let Some(parrent) = spawn.parrent {
         tbana.insert(ChildOf(parrent));
     }
@@ -433,6 +683,122 @@ fn on_insert_tbana(
     if let Some(pushto) = spawn.push_to {
         tbana.insert(pushto);
     }
+
+    if let Some(template) = &station_chart.source {
+        let chart = station_chart_for(&spawn, template);
+        errors.report(&Path::new(STATION_CHART).join(spawn.name.as_ref()), &chart);
+        if let Ok(chart) = chart {
+            tbana.insert((chart, ChartHandover::default()));
+        }
+    }
+}
+
+/// Station sequence template, replaces the built in transfer handshake when present
+pub const STATION_CHART: &str = "programs/station.template";
+
+#[derive(Resource, Default)]
+/// Source of [`STATION_CHART`], instantiated for every station
+pub struct StationChart {
+    pub source: Option<String>,
+    modified: Option<SystemTime>,
+}
+
+fn load_station_chart(mut chart: ResMut<StationChart>) {
+    chart.source = std::fs::read_to_string(STATION_CHART).ok();
+    chart.modified = modified(STATION_CHART);
+}
+
+/// Respawn the stations with the new chart when the template changed
+fn watch_station_chart(mut cmd: Commands, mut chart: ResMut<StationChart>) {
+    let modified = modified(STATION_CHART);
+    if modified == chart.modified {
+        return;
+    }
+    chart.modified = modified;
+    chart.source = std::fs::read_to_string(STATION_CHART).ok();
+    cmd.trigger(ApplyLayout);
+}
+
+/// Stations report their chart errors again when they are spawned, under
+/// [`STATION_CHART`] joined with their name
+fn clear_station_chart_errors(_trigger: On<ApplyLayout>, mut errors: ResMut<LoadErrors>) {
+    errors.0.retain(|path, _| !path.starts_with(STATION_CHART));
+}
+
+/// Name other station charts use to refer to this one
+pub fn station_chart_name(station: Entity) -> String {
+    format!("STN{}", station.index())
+}
+
+fn station_chart_for(spawn: &InsertTbana4x2, template: &str) -> Result<SfcProgram> {
+    let address = |kind: Io, dio: &Dio| dio.address(kind).to_string();
+    let (forward, end) = match spawn.direction {
+        Direction::Forward => ([0, 3], 3),
+        Direction::Reverse => ([1, 4], 0),
+    };
+    let fw = forward
+        .map(|i| address(Io::Output, &spawn.io_outputs[i]))
+        .join(" ");
+    let occupied = spawn
+        .io_inputs
+        .iter()
+        .map(|dio| address(Io::Input, dio))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let neighbour = |entity: Option<Entity>| {
+        entity
+            .map(station_chart_name)
+            .unwrap_or_else(|| "NONE".into())
+    };
+    let params = [
+        ("fw", fw),
+        ("end", address(Io::Input, &spawn.io_inputs[end])),
+        ("occupied", format!("({occupied})")),
+        ("next", neighbour(spawn.push_to.map(|to| to.0))),
+        ("prev", neighbour(spawn.pull_from.map(|from| from.0))),
+    ];
+    SfcProgram::from_template(&station_chart_name(spawn.entity), template, &params)
+}
+
+/// Step of a station chart that hands its detail over, leaving it shifts the
+/// register to the next station like the built in handshake does
+pub const HANDOVER_STEP: &str = "SENDING";
+
+#[derive(Component, Default)]
+/// The station chart was in its [`HANDOVER_STEP`] at the last scan
+struct ChartHandover(bool);
+
+fn shift_on_chart_handover(
+    mut cmd: Commands,
+    mut stations: Query<(Entity, &SfcProgram, &mut ChartHandover, Option<&PushTo>)>,
+) {
+    for (entity, chart, mut sending, push_to) in stations.iter_mut() {
+        // a chart put back in its initial step by leaving auto hands nothing over
+        let now = chart.active_steps().any(|step| step == HANDOVER_STEP);
+        if sending.0 && !now && chart.enabled {
+            if let Some(to) = push_to {
+                cmd.trigger(ShiftOver {
+                    from: entity,
+                    to: to.0,
+                });
+            }
+        }
+        sending.0 = now;
+    }
+}
+
+/// Station charts only run in auto, leaving it puts them back in their initial step
+fn gate_station_charts(
+    mut stations: Query<(&AutoMode, &RunState, &mut SfcProgram), With<TransportBana>>,
+    time: Res<Time>,
+) {
+    for (auto, run, mut chart) in stations.iter_mut() {
+        let enabled = in_auto(auto, run);
+        if chart.enabled && !enabled {
+            chart.reset(time.elapsed());
+        }
+        chart.enabled = enabled;
+    }
 }
 
 pub fn load_assets(
@@ -473,7 +839,13 @@ pub fn load_assets(
     tbana_res.wheel_materials.alarm = alarm_mode;
 
     tbana_res.bana_mesh = mesh_assets.add(Extrusion::new(Rectangle::default(), 2.0));
-    tbana_res.wheel_mesh = mesh_assets.add(Extrusion::new(Circle::new(0.1), 0.6));
+    tbana_res.wheel_mesh = mesh_assets.add(Extrusion::new(Circle::new(WHEEL_RADIUS), WHEEL_WIDTH));
+    // cylinders stand along y, the extruded mesh lies along z
+    tbana_res.wheel_collider = Collider::compound(vec![(
+        Vec3::ZERO,
+        Quat::from_rotation_x(90_f32.to_radians()),
+        Collider::cylinder(WHEEL_RADIUS, WHEEL_WIDTH),
+    )]);
 }
 
 #[derive(Default)]
@@ -492,10 +864,19 @@ pub struct TBanaAssets {
     wheel_collider: Collider,
 }
 
+impl TBanaAssets {
+    /// Material of the conveyor bed, for stations that bring their own mesh
+    pub fn station_material(&self) -> Handle<StandardMaterial> {
+        self.bana_materials.ready.clone()
+    }
+}
+
 #[derive(Bundle)]
 pub struct TbanaBundle {
     pub tbana: TransportBana,
     pub auto: AutoMode,
+    pub run: RunState,
+    pub jog: ManualJog,
     pub mesh: Mesh3d,
     pub material: MeshMaterial3d<StandardMaterial>,
     pub mode: Mode,
@@ -507,7 +888,9 @@ impl TbanaBundle {
     pub fn new(tbana_assets: &TBanaAssets) -> Self {
         Self {
             tbana: TransportBana,
-            auto: AutoMode::default(),
+            auto: AutoMode::new(true),
+            run: RunState::Ready,
+            jog: default(),
             mesh: Mesh3d(tbana_assets.bana_mesh.clone()),
             material: MeshMaterial3d(tbana_assets.bana_materials.ready.clone()),
             mode: default(),
@@ -521,13 +904,72 @@ impl TbanaBundle {
 pub struct TransportBana;
 
 #[derive(Reflect, Component, Default, Deref)]
+/// Auto runs the transfer handshake, manual leaves the motors to the operator
 pub struct AutoMode {
     enabled: bool,
 }
 
+impl AutoMode {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+}
+
+#[derive(Component, Reflect, Default, Clone, Copy, PartialEq, Eq, Debug, Deref, DerefMut)]
+/// Jog command given by the operator, only obeyed in manual mode
+pub struct ManualJog(pub Option<Direction>);
+
+#[derive(Resource, Reflect, Default, Debug)]
+#[reflect(Resource)]
+/// Global line start/stop
+pub struct LineControl {
+    pub running: bool,
+}
+
+#[derive(Resource, Reflect, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[reflect(Resource)]
+/// How the conveyors move what lies on them
+pub enum TransportMode {
+    /// Details follow the average speed of the wheels they touch, fast and
+    /// predictable but without mass, slip or accumulation
+    #[default]
+    Kinematic,
+    /// Details are dynamic bodies dragged along by spinning wheels
+    Friction,
+}
+
+impl TransportMode {
+    /// Body type of details and pallets in this mode
+    pub fn carried_body(&self) -> RigidBody {
+        match self {
+            TransportMode::Kinematic => RigidBody::Kinematic,
+            TransportMode::Friction => RigidBody::Dynamic,
+        }
+    }
+}
+
+#[derive(Event)]
+/// Start every station that is [`RunState::Ready`]
+pub struct LineStart;
+
+#[derive(Event)]
+/// Stop every running station
+pub struct LineStop;
+
+#[derive(EntityEvent)]
+/// Switch a station between auto and manual, stopping any motion
+pub struct SwitchMode {
+    pub entity: Entity,
+    pub auto: bool,
+}
+
 #[derive(Component)]
 pub struct Wheel;
 
+#[derive(Component, Debug, Clone, Copy)]
+/// Rotation a wheel was spawned with, it goes back to it when it stops spinning
+pub struct WheelRest(pub Quat);
+
 // enum MoviMotion {
 //     FastForward,
 //     Forward,
@@ -536,11 +978,22 @@ pub struct Wheel;
 //     Stop,
 // }
 
+/// Length of the straight bed of a transfer station
+pub const STATION_LENGTH: f32 = 2.0;
+/// Height of the wheel axles above the station origin
+pub const WHEEL_HEIGHT: f32 = 0.45;
+/// Radius of the wheels driving the details
+pub const WHEEL_RADIUS: f32 = 0.1;
+/// Width of the wheels, across the conveyor
+pub const WHEEL_WIDTH: f32 = 0.6;
+/// Friction coefficient of wheels and what they carry in [`TransportMode::Friction`]
+pub const WHEEL_FRICTION: f32 = 0.8;
+
 #[derive(Component, Reflect, Copy, Clone, Debug)]
 pub struct MovimotDQ {
-    forward: Dio,
-    reverse: Dio,
-    rapid: Dio,
+    pub forward: Dio,
+    pub reverse: Dio,
+    pub rapid: Dio,
 }
 
 impl MovimotDQ {
@@ -577,11 +1030,29 @@ pub struct Movimot {
     pub dq: MovimotDQ,
 }
 
-impl Movimot {}
+impl Movimot {
+    /// Signed speed of the wheel rim along its local left, with the motor
+    /// outputs as the field sees them. Zero when both directions are on
+    pub fn surface_speed(&self, io: &IoDevices) -> f32 {
+        let bit = |dio: Dio| io.get_output_bit(dio.node, dio.pin) == Some(true);
+        let speed = if bit(self.dq.rapid) {
+            self.fast_speed
+        } else {
+            self.slow_speed
+        };
+        match (bit(self.dq.forward), bit(self.dq.reverse)) {
+            (true, false) => speed,
+            (false, true) => -speed,
+            _ => 0.0,
+        }
+    }
+}
 
 #[derive(Bundle)]
 pub struct TransportWheelBundle {
     marker: Wheel,
+    transform: Transform,
+    rest: WheelRest,
     mesh: Mesh3d,
     material: MeshMaterial3d<StandardMaterial>,
     collider: Collider,
@@ -589,9 +1060,11 @@ pub struct TransportWheelBundle {
 }
 
 impl TransportWheelBundle {
-    pub fn new(assets: &TBanaAssets, dq: MovimotDQ) -> Self {
+    pub fn new(assets: &TBanaAssets, dq: MovimotDQ, transform: Transform) -> Self {
         Self {
             marker: Wheel,
+            transform,
+            rest: WheelRest(transform.rotation),
             mesh: Mesh3d(assets.wheel_mesh.clone()),
             material: MeshMaterial3d(assets.wheel_materials.ready.clone()),
             collider: assets.wheel_collider.clone(),
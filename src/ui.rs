@@ -1,96 +1,1376 @@
-use std::io::Read;
+use std::{f32::consts::FRAC_PI_2, mem, time::Duration};
 
-use bevy::{platform::collections::HashMap, prelude::*};
-use bevy_inspector_egui::{
-    bevy_egui::{egui, EguiContexts, EguiPrimaryContextPass},
-    egui::WidgetText,
+use bevy::{
+    ecs::system::SystemParam,
+    platform::collections::HashMap,
+    prelude::*,
+    window::{CursorOptions, PrimaryWindow},
 };
-use bitvec::prelude::BitVec;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts, EguiPrimaryContextPass};
 
 use crate::{
-    io::{DioPin, IOStore, Io, IoDevices, NodeId, UIOveride},
-    shiftreg::Register,
+    accumulation::MAX_ZONES,
+    fieldbus::{Fieldbus, OfflineInputs, SetNodeOnline},
+    io::{Dio, DioPin, Io, IoAddress, IoDevices, NodeId, Switch, UIOveride, Width},
+    ioalloc::{find_module, IoModule, IO_LIST_FILE, MAX_MODULE_PINS},
+    layout::{ApplyLayout, EditTool, Layout, LayoutEditor, StationKind},
+    pallet::{LoadPallet, RemovePallet},
+    plc::{
+        ladder::{CoilKind, Element, ElementKind, LadderProgram},
+        sfc::SfcProgram,
+        st::StProgram,
+        wasm::WasmProgram,
+        Tag, TagTable,
+    },
+    reload::{modified, LoadErrors},
+    safety::{EStop, EStopActuate, SafetyAck, SafetyRelay, SafetyReset, SafetyState},
+    segment::ConveyorPath,
+    sensor::{SensorFault, SensorSignal},
+    shiftreg::{Register, RegisterPosition, RegisterZones},
+    simclock::{SimClock, TimeControl, SPEEDS},
+    sysorder::SimMode,
+    tbana::{
+        AutoMode, Direction, LineControl, LineStart, LineStop, ManualJog, Movimot, RunState,
+        StopRunning, SwitchMode, TransportBana, TransportMode, TransportState,
+    },
 };
 pub struct UIPlugin;
 
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(EguiPrimaryContextPass, monitor_state);
+        app.init_resource::<Faceplate>();
+        app.init_resource::<IoTable>();
+        app.add_systems(
+            EguiPrimaryContextPass,
+            (
+                monitor_state,
+                line_control,
+                safety_panel,
+                station_faceplate,
+                layout_editor_panel,
+                time_panel,
+                program_monitor,
+                ladder_view,
+                sequence_view,
+                load_errors,
+                fieldbus_panel,
+            ),
+        );
+        app.add_observer(select_station);
     }
 }
 
+#[derive(Resource)]
+/// Filter and edit state of the io table
+struct IoTable {
+    filter: String,
+    kind: Option<Io>,
+    width: Width,
+    /// byte or word being edited and the hex typed so far
+    editing: Option<(IoAddress, String)>,
+    grab_focus: bool,
+    error: Option<String>,
+}
+
+impl Default for IoTable {
+    fn default() -> Self {
+        Self {
+            filter: String::new(),
+            kind: None,
+            width: Width::Bit,
+            editing: None,
+            grab_focus: false,
+            error: None,
+        }
+    }
+}
+
+const ADDRESS_WIDTH: f32 = 90.0;
+const TAG_WIDTH: f32 = 110.0;
+const VALUE_WIDTH: f32 = 60.0;
+
+#[derive(SystemParam)]
+/// Entities reading or driving io pins
+struct IoOwners<'w, 's> {
+    switches: Query<
+        'w,
+        's,
+        (
+            Entity,
+            Option<&'static Name>,
+            &'static NodeId,
+            &'static DioPin,
+        ),
+        With<Switch>,
+    >,
+    motors: Query<'w, 's, (Entity, Option<&'static Name>, &'static Movimot)>,
+    relay: Option<Res<'w, SafetyRelay>>,
+}
+
+impl IoOwners<'_, '_> {
+    fn by_address(&self) -> HashMap<IoAddress, String> {
+        let label = |entity: Entity, name: Option<&Name>| match name {
+            Some(name) => format!("{name} ({entity})"),
+            None => entity.to_string(),
+        };
+        let mut owners = HashMap::new();
+        for (entity, name, node, pin) in &self.switches {
+            let dio = Dio {
+                node: *node,
+                pin: *pin,
+            };
+            owners.insert(dio.address(Io::Input), label(entity, name));
+        }
+        for (entity, name, motor) in &self.motors {
+            for dio in [motor.dq.forward, motor.dq.reverse, motor.dq.rapid] {
+                owners.insert(dio.address(Io::Output), label(entity, name));
+            }
+        }
+        if let Some(dio) = self.relay.as_ref().and_then(|relay| relay.feedback) {
+            owners.insert(dio.address(Io::Input), "safety relay".into());
+        }
+        owners
+    }
+}
+
+/// Every address of the table, in node and address order
+fn io_rows(io: &IoDevices, kind: Option<Io>, width: Width) -> Vec<IoAddress> {
+    let mut rows = Vec::new();
+    for io_kind in [Io::Input, Io::Output] {
+        if kind.is_some_and(|kind| kind != io_kind) {
+            continue;
+        }
+        let stores = match io_kind {
+            Io::Input => &io.digital_inputs,
+            Io::Output => &io.digital_outputs,
+        };
+        let mut nodes: Vec<_> = stores
+            .iter()
+            .map(|(node, store)| (*node, store.state.len()))
+            .collect();
+        nodes.sort_by_key(|(node, _)| node.0);
+        for (node, len) in nodes {
+            let n_bytes = (len / 8) as u16;
+            let at = |byte| IoAddress {
+                kind: io_kind,
+                node,
+                width,
+                byte,
+                bit: 0,
+            };
+            match width {
+                Width::Bit => rows.extend((0..len).map(|pin| {
+                    let dio = Dio {
+                        node,
+                        pin: DioPin(pin as u16),
+                    };
+                    dio.address(io_kind)
+                })),
+                Width::Byte => rows.extend((0..n_bytes).map(at)),
+                Width::Word => rows.extend((0..n_bytes.saturating_sub(1)).step_by(2).map(at)),
+            }
+        }
+    }
+    rows
+}
+
 fn monitor_state(
     mut cmd: Commands,
     mut contexts: EguiContexts,
     mut io: ResMut<IoDevices>,
+    mut table: ResMut<IoTable>,
+    tags: Res<TagTable>,
+    owners: IoOwners,
+    reg: Res<Register>,
+) -> Result {
+    let table = &mut *table;
+    let owners = owners.by_address();
+    let tag_names: HashMap<IoAddress, &str> = tags
+        .iter()
+        .filter_map(|(name, tag)| match tag {
+            Tag::Input(dio) => Some((dio.address(Io::Input), name.as_str())),
+            Tag::Output(dio) => Some((dio.address(Io::Output), name.as_str())),
+            Tag::Memory(_) => None,
+        })
+        .collect();
+    let mut rows = io_rows(&io, table.kind, table.width);
+    let filter = table.filter.trim().to_lowercase();
+    if !filter.is_empty() {
+        let matches = |text: &str| text.to_lowercase().contains(&filter);
+        rows.retain(|address| {
+            matches(&address.to_string())
+                || tag_names.get(address).is_some_and(|tag| matches(tag))
+                || owners.get(address).is_some_and(|owner| matches(owner))
+        });
+    }
+
+    egui::Window::new("IO Devices").show(contexts.ctx_mut()?, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("filter");
+            ui.text_edit_singleline(&mut table.filter)
+                .on_hover_text("address, tag or owner");
+        });
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut table.kind, None, "all");
+            ui.selectable_value(&mut table.kind, Some(Io::Input), "inputs");
+            ui.selectable_value(&mut table.kind, Some(Io::Output), "outputs");
+            ui.separator();
+            for (width, text) in [
+                (Width::Bit, "bits"),
+                (Width::Byte, "bytes"),
+                (Width::Word, "words"),
+            ] {
+                if ui.selectable_value(&mut table.width, width, text).changed() {
+                    table.editing = None;
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label(format!("{} rows", rows.len()));
+            let n_forced = io.n_forced();
+            if n_forced > 0 {
+                ui.colored_label(egui::Color32::YELLOW, format!("{n_forced} forced"));
+                if ui.button("Release all").clicked() {
+                    io.release_forces();
+                }
+            }
+        });
+        if let Some(error) = &table.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        ui.separator();
+
+        let row_height = ui.spacing().interact_size.y;
+        let head = |ui: &mut egui::Ui, width: f32, text: &str| {
+            ui.add_sized(
+                [width, row_height],
+                egui::Label::new(egui::RichText::new(text).strong()),
+            );
+        };
+        ui.horizontal(|ui| {
+            head(ui, ADDRESS_WIDTH, "address");
+            match table.width {
+                Width::Bit => {
+                    head(ui, TAG_WIDTH, "tag");
+                    head(ui, VALUE_WIDTH, "value");
+                    head(ui, VALUE_WIDTH, "raw");
+                    head(ui, VALUE_WIDTH * 1.5, "force");
+                    ui.strong("owner");
+                }
+                Width::Byte | Width::Word => {
+                    head(ui, VALUE_WIDTH, "hex");
+                    ui.strong("bits");
+                }
+            }
+        });
+        // only the visible rows are laid out, so thousands of pins stay cheap
+        egui::ScrollArea::vertical().max_height(400.0).show_rows(
+            ui,
+            row_height,
+            rows.len(),
+            |ui, range| {
+                for address in &rows[range] {
+                    ui.horizontal(|ui| {
+                        ui.add_sized(
+                            [ADDRESS_WIDTH, row_height],
+                            egui::Label::new(address.to_string()),
+                        );
+                        match address.dio() {
+                            Some(dio) => {
+                                let tag = tag_names.get(address).copied().unwrap_or_default();
+                                ui.add_sized([TAG_WIDTH, row_height], egui::Label::new(tag));
+                                bit_cells(ui, &mut cmd, &mut io, table, address.kind, dio);
+                                if let Some(owner) = owners.get(address) {
+                                    ui.label(owner);
+                                }
+                            }
+                            None => hex_cells(ui, &mut io, table, *address),
+                        }
+                    });
+                }
+            },
+        );
+
+        ui.collapsing("DetailRegister", |ui| {
+            egui::Grid::new("Shift reg grid").show(ui, |ui| {
+                for head in ["pos", "pallet", "Op1", "Op2", "Op3", "Op4"] {
+                    ui.label(head);
+                }
+                ui.end_row();
+                for (i, detail) in reg.details.iter().enumerate() {
+                    ui.label(format!("{i}"));
+                    match reg.pallet_at(i) {
+                        Some(pallet) => ui.label(format!("{}", pallet.0)),
+                        None => ui.label("-"),
+                    };
+                    if let Some(detail) = detail {
+                        for bit in (0..4).map(|i| detail.get_bit(i)) {
+                            let msg = match bit {
+                                Some(true) => "Ok",
+                                Some(false) => "Failed",
+                                None => "Not Done",
+                            };
+                            ui.label(msg);
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+    });
+    Ok(())
+}
+
+/// Value checkbox and force buttons of one pin
+fn bit_cells(
+    ui: &mut egui::Ui,
+    cmd: &mut Commands,
+    io: &mut IoDevices,
+    table: &mut IoTable,
+    kind: Io,
+    dio: Dio,
+) {
+    let forced = io.forced(kind, dio);
+    let mut value = io.get_bit(kind, dio).unwrap_or_default();
+    ui.add_sized(
+        [VALUE_WIDTH, ui.spacing().interact_size.y],
+        |ui: &mut egui::Ui| {
+            let checkbox = egui::Checkbox::without_text(&mut value);
+            let response = ui.add_enabled(forced.is_none(), checkbox);
+            if response.changed() {
+                table.error = io
+                    .try_set_bit(kind, dio, value)
+                    .err()
+                    .map(|err| err.to_string());
+                cmd.trigger(UIOveride {
+                    address: dio.node,
+                    pin: dio.pin,
+                    value,
+                    kind,
+                });
+            }
+            response
+        },
+    );
+    raw_cell(ui, io, kind, dio, value);
+    let mut wanted = forced;
+    ui.horizontal(|ui| {
+        ui.set_width(VALUE_WIDTH * 1.5);
+        ui.selectable_value(&mut wanted, None, "-");
+        ui.selectable_value(&mut wanted, Some(false), "F0");
+        ui.selectable_value(&mut wanted, Some(true), "F1");
+    });
+    if wanted != forced {
+        table.error = io.force(kind, dio, wanted).err().map(|err| err.to_string());
+    }
+}
+
+/// Input as the field shows it, highlighted while the input filter holds
+/// it back from the process image
+fn raw_cell(ui: &mut egui::Ui, io: &IoDevices, kind: Io, dio: Dio, value: bool) {
+    let size = [VALUE_WIDTH, ui.spacing().interact_size.y];
+    let store = io
+        .digital_inputs
+        .get(&dio.node)
+        .filter(|_| kind == Io::Input);
+    let Some(store) = store else {
+        ui.add_sized(size, egui::Label::new(""));
+        return;
+    };
+    let idx = dio.pin.as_usize();
+    let raw = store.raw(idx);
+    let text = egui::RichText::new(bit_text(raw));
+    let text = match raw {
+        Some(raw) if raw != value => text.color(egui::Color32::YELLOW),
+        _ => text,
+    };
+    let filter = store.filter_of(idx);
+    ui.add_sized(size, egui::Label::new(text))
+        .on_hover_text(format!(
+            "filter on {} ms off {} ms",
+            filter.on_delay.as_millis(),
+            filter.off_delay.as_millis()
+        ));
+}
+
+/// Hex value of a byte or word, click it to type a new value
+fn hex_cells(ui: &mut egui::Ui, io: &mut IoDevices, table: &mut IoTable, address: IoAddress) {
+    let size = [VALUE_WIDTH, ui.spacing().interact_size.y];
+    let digits = match address.width {
+        Width::Word => 4,
+        _ => 2,
+    };
+    let value = io.read(address);
+    let editing = table.editing.as_mut().filter(|(at, _)| *at == address);
+    if let Some((_, text)) = editing {
+        let response = ui.add_sized(size, egui::TextEdit::singleline(text));
+        if table.grab_focus {
+            response.request_focus();
+            table.grab_focus = false;
+        }
+        if response.lost_focus() {
+            if ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+                let text = text
+                    .trim()
+                    .trim_start_matches("0x")
+                    .trim_start_matches("16#");
+                let written = match u16::from_str_radix(text, 16) {
+                    Ok(value) if digits == 2 && value > 0xff => {
+                        Err(format!("{value:#X} does not fit in {address}"))
+                    }
+                    Ok(value) => io.write(address, value).map_err(|err| err.to_string()),
+                    Err(_) => Err(format!("'{text}' is not a hex number")),
+                };
+                table.error = written.err();
+            }
+            table.editing = None;
+        }
+    } else {
+        let shown = match value {
+            Some(value) => format!("{value:0digits$X}"),
+            None => "-".into(),
+        };
+        let response = ui.add_sized(size, egui::Button::new(shown).frame(false));
+        if response.on_hover_text("click to edit").clicked() {
+            table.editing = Some((address, format!("{:0digits$X}", value.unwrap_or_default())));
+            table.grab_focus = true;
+        }
+    }
+    if let Some(value) = value {
+        ui.monospace(format!("{value:0width$b}", width = digits * 4));
+    }
+}
+
+fn line_control(
+    mut cmd: Commands,
+    mut contexts: EguiContexts,
+    line: Res<LineControl>,
+    mut mode: ResMut<TransportMode>,
+    stations: Query<
+        (
+            Entity,
+            &Name,
+            &RegisterPosition,
+            &AutoMode,
+            &RunState,
+            &TransportState,
+            &ManualJog,
+        ),
+        With<TransportBana>,
+    >,
+) -> Result {
+    egui::Window::new("Line Control").show(contexts.ctx_mut()?, |ui| {
+        ui.horizontal(|ui| {
+            let start = egui::Button::new("Start line");
+            if ui.add_enabled(!line.running, start).clicked() {
+                cmd.trigger(LineStart);
+            }
+            let stop = egui::Button::new("Stop line");
+            if ui.add_enabled(line.running, stop).clicked() {
+                cmd.trigger(LineStop);
+            }
+            ui.separator();
+            let mut wanted = *mode;
+            ui.label("transport");
+            ui.selectable_value(&mut wanted, TransportMode::Kinematic, "kinematic")
+                .on_hover_text("details take the wheel speed, fast");
+            ui.selectable_value(&mut wanted, TransportMode::Friction, "friction")
+                .on_hover_text("wheels drive dynamic details, they can slip and accumulate");
+            if wanted != *mode {
+                *mode = wanted;
+            }
+        });
+        let mut rows: Vec<_> = stations.iter().collect();
+        rows.sort_by_key(|(_, _, pos, ..)| pos.0);
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("station modes")
+                .striped(true)
+                .show(ui, |ui| {
+                    for head in ["station", "auto", "run", "state", "jog"] {
+                        ui.label(head);
+                    }
+                    ui.end_row();
+                    for (entity, name, _, auto, run, state, jog) in rows {
+                        ui.label(name.as_str());
+                        auto_checkbox(ui, &mut cmd, entity, auto);
+                        ui.label(format!("{run:?}"));
+                        ui.label(format!("{state:?}"));
+                        // the faceplate owns the jog buttons
+                        ui.label(match jog.0 {
+                            Some(Direction::Forward) => ">>",
+                            Some(Direction::Reverse) => "<<",
+                            None => "-",
+                        });
+                        ui.end_row();
+                    }
+                });
+        });
+    });
+    Ok(())
+}
+
+#[derive(Resource, Default)]
+/// Station whose faceplate is open
+pub struct Faceplate(pub Option<Entity>);
+
+fn select_station(
+    click: On<Pointer<Click>>,
+    stations: Query<(), With<TransportBana>>,
+    cursor: Single<&CursorOptions, With<PrimaryWindow>>,
+    mut faceplate: ResMut<Faceplate>,
+) {
+    // while the cursor is grabbed, clicks belong to the fps controls
+    if !cursor.visible || click.button != PointerButton::Primary {
+        return;
+    }
+    // clicks bubble up from wheels and fotocells to the station
+    let entity = click.event_target();
+    if stations.contains(entity) {
+        faceplate.0 = Some(entity);
+    }
+}
+
+/// Pick the fault injected into a sensor, none makes it healthy again
+fn fault_widget(
+    ui: &mut egui::Ui,
+    cmd: &mut Commands,
+    sensor: Entity,
+    fault: Option<&SensorFault>,
+) {
+    let mut selected = fault.copied();
+    egui::ComboBox::from_id_salt(("sensor fault", sensor))
+        .selected_text(selected.map_or("none".into(), |fault| fault.to_string()))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut selected, None, "none");
+            for fault in [
+                SensorFault::StuckOn,
+                SensorFault::StuckOff,
+                SensorFault::Flicker(0.1),
+                SensorFault::Delay(Duration::from_millis(100)),
+                SensorFault::WireBreak,
+            ] {
+                let same_kind = selected.is_some_and(|current| {
+                    mem::discriminant(&current) == mem::discriminant(&fault)
+                });
+                let label = fault.to_string();
+                let label = label.split(' ').next().unwrap_or_default();
+                if ui.selectable_label(same_kind, label).clicked() && !same_kind {
+                    selected = Some(fault);
+                }
+            }
+        });
+    match &mut selected {
+        Some(SensorFault::Flicker(probability)) => {
+            ui.add(
+                egui::DragValue::new(probability)
+                    .range(0.0..=1.0)
+                    .speed(0.01),
+            );
+        }
+        Some(SensorFault::Delay(delay)) => {
+            let mut ms = delay.as_millis() as u64;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut ms)
+                        .range(0..=10_000)
+                        .suffix(" ms"),
+                )
+                .changed()
+            {
+                *delay = Duration::from_millis(ms);
+            }
+        }
+        _ => (),
+    }
+    if selected.as_ref() == fault {
+        return;
+    }
+    match selected {
+        Some(fault) => cmd.entity(sensor).insert(fault),
+        None => cmd.entity(sensor).remove::<SensorFault>(),
+    };
+}
+
+fn bit_text(bit: Option<bool>) -> &'static str {
+    match bit {
+        Some(true) => "1",
+        Some(false) => "0",
+        None => "-",
+    }
+}
+
+fn station_faceplate(
+    mut cmd: Commands,
+    mut contexts: EguiContexts,
+    mut faceplate: ResMut<Faceplate>,
+    mut stations: Query<
+        (
+            &Name,
+            &TransportState,
+            &Direction,
+            &RegisterPosition,
+            Option<&RegisterZones>,
+            &AutoMode,
+            &mut ManualJog,
+            &Children,
+            Option<&SfcProgram>,
+        ),
+        With<TransportBana>,
+    >,
+    sensors: Query<
+        (
+            Entity,
+            &Name,
+            &NodeId,
+            &DioPin,
+            &SensorSignal,
+            Option<&SensorFault>,
+        ),
+        With<Switch>,
+    >,
+    motors: Query<&Movimot>,
+    io: Res<IoDevices>,
     mut reg: ResMut<Register>,
 ) -> Result {
-    egui::Window::new("IO Devices")
-        .scroll([true, true])
+    let Some(entity) = faceplate.0 else {
+        return Ok(());
+    };
+    let Ok((name, state, direction, pos, zones, auto, mut jog, children, chart)) =
+        stations.get_mut(entity)
+    else {
+        faceplate.0 = None;
+        return Ok(());
+    };
+    let mut open = true;
+    egui::Window::new(format!("Faceplate {name}"))
+        .id(egui::Id::new("station faceplate"))
+        .open(&mut open)
         .show(contexts.ctx_mut()?, |ui| {
-            // egui::ScrollArea::vertical().show(ui, |ui| {});
-
-            ui.label("Digital Inputs");
-            io_widget(&mut cmd, ui, &mut io.digital_inputs, "input", Io::Input);
-            ui.label("Digital Outputs");
-            io_widget(&mut cmd, ui, &mut io.digital_outputs, "output", Io::Output);
-            // ui.label("Shift Register");
-            ui.collapsing("DetailRegister", |ui| {
-                egui::Grid::new("Shift reg grid").show(ui, |ui| {
-                    for head in ["pos", "Op1", "Op2", "Op3", "Op4"] {
-                        ui.label(head);
+            egui::Grid::new("faceplate state").show(ui, |ui| {
+                ui.label("state");
+                match chart {
+                    Some(chart) => ui.label(chart.active_steps().collect::<Vec<_>>().join(", ")),
+                    None => ui.label(format!("{state:?}")),
+                };
+                ui.end_row();
+                ui.label("direction");
+                ui.label(format!("{direction:?}"));
+                ui.end_row();
+                // one position per zone, from the infeed to the discharge
+                for idx in pos.span(zones) {
+                    ui.label(format!("register {idx}"));
+                    let slot = match reg.details.get(idx) {
+                        Some(Some(detail)) => (0..4)
+                            .map(|i| match detail.get_bit(i) {
+                                Some(true) => "Ok",
+                                Some(false) => "Failed",
+                                None => "Not Done",
+                            })
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        Some(None) => "empty".into(),
+                        None => "out of range".into(),
+                    };
+                    ui.label(slot);
+                    ui.end_row();
+                    ui.label("pallet");
+                    match reg.pallet_at(idx) {
+                        Some(pallet) => ui.label(format!("{}", pallet.0)),
+                        None => ui.label("none"),
+                    };
+                    ui.end_row();
+                }
+            });
+            ui.separator();
+            egui::Grid::new("faceplate io").show(ui, |ui| {
+                let fcs = children.iter().filter_map(|e| sensors.get(e).ok());
+                for (fc, fc_name, node, pin, signal, fault) in fcs {
+                    let dio = Dio {
+                        node: *node,
+                        pin: *pin,
+                    };
+                    ui.label(fc_name.as_str());
+                    ui.label(bit_text(io.get_input_bit(*node, *pin)));
+                    ui.label(dio.address(Io::Input).to_string());
+                    ui.horizontal(|ui| fault_widget(ui, &mut cmd, fc, fault));
+                    if signal.wire_break {
+                        ui.colored_label(egui::Color32::RED, "wire break");
+                    } else if fault.is_some() {
+                        ui.label(format!(
+                            "detects {} reports {}",
+                            bit_text(Some(signal.detected)),
+                            bit_text(Some(signal.reported))
+                        ));
+                    } else if let Some(distance) = signal.distance {
+                        ui.label(format!("hit at {distance:.2} m"));
                     }
-                    for (i, detail) in reg.details.iter().enumerate() {
-                        ui.label(format!("{i}"));
-                        if let Some(detail) = detail {
-                            for bit in (0..4).map(|i| detail.get_bit(i)) {
-                                let msg = match bit {
-                                    Some(true) => "Ok",
-                                    Some(false) => "Failed",
-                                    None => "Not Done",
-                                };
-                                ui.label(msg);
-                            }
+                    ui.end_row();
+                }
+                for (i, motor) in children
+                    .iter()
+                    .filter_map(|e| motors.get(e).ok())
+                    .enumerate()
+                {
+                    let dios = [motor.dq.forward, motor.dq.reverse, motor.dq.rapid];
+                    let [fw, rev, rapid] =
+                        dios.map(|dio| bit_text(io.get_output_bit(dio.node, dio.pin)));
+                    let [fw_at, rev_at, rapid_at] = dios.map(|dio| dio.address(Io::Output));
+                    ui.label(format!("motor {i}"));
+                    ui.label(format!("fw {fw}  rev {rev}  rapid {rapid}"));
+                    ui.label(format!("{fw_at} {rev_at} {rapid_at}"));
+                    ui.end_row();
+                }
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("auto");
+                mode_widget(ui, &mut cmd, entity, auto, &mut jog);
+            });
+            ui.horizontal(|ui| {
+                let reset = ui
+                    .button("Reset")
+                    .on_hover_text("stop the station and re-evaluate if it is ready");
+                if reset.clicked() {
+                    cmd.trigger(StopRunning(entity));
+                }
+                if ui.button("Clear slot").clicked() {
+                    for idx in pos.span(zones) {
+                        if let Some(slot) = reg.details.get_mut(idx) {
+                            *slot = None;
                         }
+                        if let Some(id) = reg.pallets.get_mut(idx).and_then(Option::take) {
+                            cmd.trigger(RemovePallet(id));
+                        }
+                    }
+                }
+                let empty = !reg.occupied(pos.as_usize());
+                if ui
+                    .add_enabled(empty, egui::Button::new("Load pallet"))
+                    .clicked()
+                {
+                    cmd.trigger(LoadPallet {
+                        station: entity,
+                        with_detail: true,
+                    });
+                }
+            });
+        });
+    if !open {
+        faceplate.0 = None;
+    }
+    Ok(())
+}
+
+fn program_monitor(
+    mut contexts: EguiContexts,
+    programs: Query<(&Name, &StProgram)>,
+    wasm_programs: Query<(&Name, &WasmProgram)>,
+    tags: Res<TagTable>,
+    io: Res<IoDevices>,
+) -> Result {
+    if programs.is_empty() && wasm_programs.is_empty() && tags.iter().next().is_none() {
+        return Ok(());
+    }
+    egui::Window::new("Programs").show(contexts.ctx_mut()?, |ui| {
+        for (name, program) in programs {
+            ui.collapsing(format!("{name} ({})", program.name()), |ui| {
+                if let Some(fault) = program.fault() {
+                    ui.colored_label(egui::Color32::RED, format!("fault at {fault}"));
+                }
+                egui::Grid::new(name.as_str()).striped(true).show(ui, |ui| {
+                    for (var, value) in program.values() {
+                        ui.label(var);
+                        ui.label(value.to_string());
                         ui.end_row();
                     }
                 });
             });
+        }
+        for (name, program) in wasm_programs {
+            ui.horizontal(|ui| {
+                ui.label(format!("{name} (wasm)"));
+                match program.fault() {
+                    Some(fault) => ui.colored_label(egui::Color32::RED, fault),
+                    None => ui.label(format!("{} scans", program.scans())),
+                };
+            });
+        }
+        ui.collapsing("Tags", |ui| {
+            let mut rows: Vec<_> = tags.iter().collect();
+            rows.sort_by_key(|(name, _)| name.as_str());
+            egui::Grid::new("tag table").striped(true).show(ui, |ui| {
+                for (name, tag) in rows {
+                    ui.label(name.as_str());
+                    match tag {
+                        Tag::Input(dio) => ui.label(dio.address(Io::Input).to_string()),
+                        Tag::Output(dio) => ui.label(dio.address(Io::Output).to_string()),
+                        Tag::Memory(value) => ui.label(value.type_name()),
+                    };
+                    let value = tags.read(name, &io).map(|value| value.to_string());
+                    ui.label(value.unwrap_or_else(|err| err.to_string()));
+                    ui.end_row();
+                }
+            });
         });
+    });
     Ok(())
 }
 
-fn io_widget(
-    cmd: &mut Commands,
-    ui: &mut egui::Ui,
-    hash_map: &mut HashMap<NodeId, IOStore>,
-    header: &'static str,
-    kind: Io,
-) {
-    for (address, store) in hash_map.iter_mut() {
-        ui.collapsing(format!("{} Device: {}", header, address.0), |ui| {
-            let bytes = store.state.chunks_exact_mut(8);
-            for (address, byte) in bytes.enumerate() {
-                ui.horizontal_top(|ui| {
-                    ui.collapsing(format!("B {address}"), |ui| {
-                        for (ix, mut bit) in byte.iter_mut().enumerate() {
-                            ui.horizontal(|ui| {
-                                // ui.label(format!("bit: {ix}"));
-                                if ui.checkbox(&mut bit, format!(".{ix}")).changed() {
-                                    cmd.trigger(UIOveride {
-                                        address: (address as u32).into(),
-                                        pin: DioPin(ix as u16),
-                                        value: *bit,
-                                        kind,
-                                    });
-                                }
-                            });
+fn ladder_view(mut contexts: EguiContexts, programs: Query<(&Name, &LadderProgram)>) -> Result {
+    if programs.is_empty() {
+        return Ok(());
+    }
+    egui::Window::new("Ladder").show(contexts.ctx_mut()?, |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (name, program) in programs {
+                ui.collapsing(name.as_str(), |ui| {
+                    if let Some(fault) = program.fault() {
+                        ui.colored_label(egui::Color32::RED, fault);
+                    }
+                    for rung in program.rungs() {
+                        if let Some(label) = &rung.label {
+                            ui.label(egui::RichText::new(label).small());
+                        }
+                        ui.horizontal(|ui| {
+                            ui.monospace(format!("{:>3} ||", rung.line));
+                            rung_elements(ui, &rung.elements);
+                            ui.monospace("||");
+                        });
+                    }
+                });
+            }
+        });
+    });
+    Ok(())
+}
+
+/// Elements in series, branches stacked vertically, green where power flows
+fn rung_elements(ui: &mut egui::Ui, elements: &[Element]) {
+    for element in elements {
+        let text = match &element.kind {
+            ElementKind::Contact { operand, negated } => {
+                format!(
+                    "-|{}|- {}",
+                    if *negated { "/" } else { " " },
+                    operand.label()
+                )
+            }
+            ElementKind::Coil { operand, kind } => {
+                let symbol = match kind {
+                    CoilKind::Normal => " ",
+                    CoilKind::Set => "S",
+                    CoilKind::Reset => "R",
+                };
+                format!("-({symbol})- {}", operand.label())
+            }
+            ElementKind::OnDelay { name, preset } => format!("[TON {name} {preset:?}]"),
+            ElementKind::OffDelay { name, preset } => format!("[TOF {name} {preset:?}]"),
+            ElementKind::Pulse { name, preset } => format!("[TP {name} {preset:?}]"),
+            ElementKind::Counter { name, preset } => format!("[CTU {name} {preset}]"),
+            ElementKind::Reset { name } => format!("[RES {name}]"),
+            ElementKind::Branch(paths) => {
+                ui.vertical(|ui| {
+                    for path in paths {
+                        ui.horizontal(|ui| rung_elements(ui, path));
+                    }
+                });
+                continue;
+            }
+        };
+        let color = if element.energized {
+            egui::Color32::GREEN
+        } else {
+            egui::Color32::GRAY
+        };
+        ui.label(egui::RichText::new(text).monospace().color(color));
+    }
+}
+
+fn sequence_view(
+    mut contexts: EguiContexts,
+    charts: Query<(&Name, &SfcProgram)>,
+    time: Res<Time>,
+) -> Result {
+    if charts.is_empty() {
+        return Ok(());
+    }
+    let now = time.elapsed();
+    egui::Window::new("Sequences").show(contexts.ctx_mut()?, |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (name, chart) in charts {
+                let active = chart.active_steps().collect::<Vec<_>>().join(", ");
+                ui.collapsing(format!("{name}: {active}"), |ui| {
+                    if let Some(fault) = chart.fault() {
+                        ui.colored_label(egui::Color32::RED, fault);
+                    }
+                    if !chart.enabled {
+                        ui.label("disabled");
+                    }
+                    for (idx, step) in chart.steps().iter().enumerate() {
+                        let text = if chart.is_active(idx) {
+                            let time = chart.step_time(idx, now).as_secs_f32();
+                            egui::RichText::new(format!("[{}] {time:.1} s", step.name))
+                                .color(egui::Color32::GREEN)
+                        } else {
+                            egui::RichText::new(format!(" {} ", step.name))
+                        };
+                        ui.monospace(text);
+                        for transition in chart.transitions() {
+                            if transition.from.first() != Some(&idx) {
+                                continue;
+                            }
+                            let to: Vec<_> = transition
+                                .to
+                                .iter()
+                                .map(|to| chart.steps()[*to].name.as_str())
+                                .collect();
+                            ui.label(format!("    -> {} : {}", to.join(", "), transition.text));
+                        }
+                    }
+                });
+            }
+        });
+    });
+    Ok(())
+}
+
+fn load_errors(mut contexts: EguiContexts, errors: Res<LoadErrors>) -> Result {
+    if errors.0.is_empty() {
+        return Ok(());
+    }
+    egui::Window::new("Load errors").show(contexts.ctx_mut()?, |ui| {
+        for (path, err) in errors.0.iter() {
+            ui.label(path.display().to_string());
+            ui.colored_label(egui::Color32::RED, err);
+            ui.separator();
+        }
+        ui.label("files are loaded again when they change");
+    });
+    Ok(())
+}
+
+/// Node status and timing, changes are kept in the layout
+fn fieldbus_panel(
+    mut cmd: Commands,
+    mut contexts: EguiContexts,
+    mut bus: ResMut<Fieldbus>,
+    mut layout: ResMut<Layout>,
+    mut io: ResMut<IoDevices>,
+    time: Res<Time<Fixed>>,
+) -> Result {
+    let now = time.elapsed();
+    // edits are applied after drawing, so the layout only changes when edited
+    let mut edits = Vec::new();
+    egui::Window::new("Fieldbus").show(contexts.ctx_mut()?, |ui| {
+        egui::Grid::new("fieldbus nodes")
+            .striped(true)
+            .show(ui, |ui| {
+                for head in [
+                    "node",
+                    "module",
+                    "status",
+                    "cycle ms",
+                    "delay ms",
+                    "offline inputs",
+                    "filter on ms",
+                    "filter off ms",
+                    "age ms",
+                    "",
+                ] {
+                    ui.label(head);
+                }
+                ui.end_row();
+                for (i, module) in layout.modules.iter().enumerate() {
+                    let Some(node) = bus.nodes.get(&module.node) else {
+                        continue;
+                    };
+                    ui.label(format!("N{}", module.node.0));
+                    ui.label(&module.name);
+                    if node.online() {
+                        ui.colored_label(egui::Color32::GREEN, "online");
+                    } else {
+                        ui.colored_label(egui::Color32::RED, "OFFLINE");
+                    }
+                    let mut config = module.bus;
+                    for duration in [&mut config.cycle, &mut config.delay] {
+                        let mut ms = duration.as_secs_f64() * 1000.0;
+                        let drag = egui::DragValue::new(&mut ms).range(0.0..=1000.0).speed(0.5);
+                        if ui.add(drag).changed() {
+                            *duration = Duration::from_secs_f64(ms / 1000.0);
                         }
+                    }
+                    ui.horizontal(|ui| {
+                        let offline = &mut config.offline_inputs;
+                        ui.selectable_value(offline, OfflineInputs::Freeze, "freeze");
+                        ui.selectable_value(offline, OfflineInputs::Zero, "zero");
                     });
-                    let byte: u8 = byte.bytes().next().unwrap().unwrap();
-                    ui.label(format!("{:#04X}", byte))
+                    let mut filter = module.filter;
+                    for duration in [&mut filter.on_delay, &mut filter.off_delay] {
+                        let mut ms = duration.as_millis() as u64;
+                        let drag = egui::DragValue::new(&mut ms).range(0..=1000);
+                        if ui.add(drag).changed() {
+                            *duration = Duration::from_millis(ms);
+                        }
+                    }
+                    if config != module.bus || filter != module.filter {
+                        edits.push((i, config, filter));
+                    }
+                    match node.last_update {
+                        Some(at) => ui.label(format!("{}", now.saturating_sub(at).as_millis())),
+                        None => ui.label("-"),
+                    };
+                    let (text, online) = if node.online() {
+                        ("unplug", false)
+                    } else {
+                        ("plug in", true)
+                    };
+                    if ui.button(text).clicked() {
+                        cmd.trigger(SetNodeOnline {
+                            node: module.node,
+                            online,
+                        });
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+    for (i, config, filter) in edits {
+        let module = &mut layout.modules[i];
+        module.bus = config;
+        module.filter = filter;
+        if let Some(node) = bus.nodes.get_mut(&module.node) {
+            node.config = config;
+        }
+        if let Some(store) = io.digital_inputs.get_mut(&module.node) {
+            store.filter = filter;
+        }
+    }
+    Ok(())
+}
+
+fn time_panel(
+    mut cmd: Commands,
+    mut contexts: EguiContexts,
+    virtual_time: Res<Time<Virtual>>,
+    clock: Res<SimClock>,
+) -> Result {
+    egui::Window::new("Simulation").show(contexts.ctx_mut()?, |ui| {
+        ui.label(format!(
+            "scan {}  t = {:.2} s  seed {:#x}",
+            clock.ticks(),
+            virtual_time.elapsed_secs(),
+            clock.seed
+        ));
+        ui.horizontal(|ui| {
+            let paused = virtual_time.is_paused();
+            if ui
+                .button(if paused { "Resume (P)" } else { "Pause (P)" })
+                .clicked()
+            {
+                cmd.trigger(if paused {
+                    TimeControl::Resume
+                } else {
+                    TimeControl::Pause
+                });
+            }
+            if ui
+                .add_enabled(paused, egui::Button::new("Step (.)"))
+                .clicked()
+            {
+                cmd.trigger(TimeControl::Step);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("speed [ ]");
+            let current = virtual_time.relative_speed();
+            for speed in SPEEDS {
+                let label = format!("{speed}x");
+                if ui.selectable_label(current == speed, label).clicked() {
+                    cmd.trigger(TimeControl::SetSpeed(speed));
+                }
+            }
+        });
+    });
+    Ok(())
+}
+
+fn layout_editor_panel(
+    mut cmd: Commands,
+    mut contexts: EguiContexts,
+    mode: Res<State<SimMode>>,
+    mut next_mode: ResMut<NextState<SimMode>>,
+    mut layout: ResMut<Layout>,
+    mut editor: ResMut<LayoutEditor>,
+    tags: Res<TagTable>,
+) -> Result {
+    egui::Window::new("Layout").show(contexts.ctx_mut()?, |ui| {
+        let editing = *mode.get() == SimMode::Editing;
+        ui.horizontal(|ui| {
+            if ui.selectable_label(!editing, "Run").clicked() {
+                next_mode.set(SimMode::Running);
+            }
+            if ui.selectable_label(editing, "Edit").clicked() {
+                next_mode.set(SimMode::Editing);
+            }
+        });
+        if !editing {
+            return;
+        }
+        ui.horizontal(|ui| {
+            let tools = [
+                (EditTool::Place, "place"),
+                (EditTool::Rotate, "rotate"),
+                (EditTool::Link, "link"),
+                (EditTool::Delete, "delete"),
+            ];
+            for (tool, label) in tools {
+                if ui.selectable_value(&mut editor.tool, tool, label).changed() {
+                    editor.link_from = None;
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("place");
+            let place = editor.place;
+            if ui
+                .selectable_label(place == StationKind::Transfer, "transfer")
+                .clicked()
+            {
+                editor.place = StationKind::Transfer;
+            }
+            let accumulation = matches!(place, StationKind::Accumulation(_));
+            if ui.selectable_label(accumulation, "accumulation").clicked() && !accumulation {
+                editor.place = StationKind::Accumulation(4);
+            }
+            let curve = matches!(place, StationKind::Segment(ConveyorPath::Curve { .. }));
+            if ui.selectable_label(curve, "curve").clicked() && !curve {
+                // a quarter turn on half a cell ends on the grid again
+                editor.place = StationKind::Segment(ConveyorPath::Curve {
+                    radius: layout.cell_size / 2.0,
+                    angle: FRAC_PI_2,
+                });
+            }
+            let incline = matches!(place, StationKind::Segment(ConveyorPath::Incline { .. }));
+            if ui.selectable_label(incline, "incline").clicked() && !incline {
+                editor.place = StationKind::Segment(ConveyorPath::Incline {
+                    length: layout.cell_size,
+                    rise: 0.3,
+                });
+            }
+        });
+        ui.horizontal(|ui| match &mut editor.place {
+            StationKind::Transfer => (),
+            StationKind::Accumulation(zones) => {
+                let zone_range = 1..=MAX_ZONES as u8;
+                ui.add(
+                    egui::DragValue::new(zones)
+                        .range(zone_range)
+                        .suffix(" zones"),
+                );
+            }
+            StationKind::Segment(ConveyorPath::Curve { radius, angle }) => {
+                ui.add(
+                    egui::DragValue::new(radius)
+                        .range(0.5..=10.0)
+                        .suffix(" m radius"),
+                );
+                let mut degrees = angle.to_degrees();
+                let turn = egui::DragValue::new(&mut degrees)
+                    .range(-180.0..=180.0)
+                    .suffix("°, left positive");
+                if ui.add(turn).changed() && degrees != 0.0 {
+                    *angle = degrees.to_radians();
+                }
+            }
+            StationKind::Segment(ConveyorPath::Incline { length, rise }) => {
+                ui.add(
+                    egui::DragValue::new(length)
+                        .range(0.5..=10.0)
+                        .suffix(" m long"),
+                );
+                ui.add(egui::DragValue::new(rise).speed(0.05).suffix(" m rise"));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("allocate IO from node");
+            ui.add(egui::DragValue::new(&mut editor.io_node.0));
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut editor.path);
+            if ui.button("Save").clicked() {
+                editor.status = Some(match layout.save(&editor.path) {
+                    Ok(()) => format!("saved {}", editor.path),
+                    Err(err) => err.to_string(),
                 });
+                editor.modified = modified(&editor.path);
+            }
+            if ui.button("Load").clicked() {
+                match Layout::load(&editor.path) {
+                    Ok(loaded) => {
+                        *layout = loaded;
+                        editor.status = Some(format!("loaded {}", editor.path));
+                        editor.modified = modified(&editor.path);
+                        cmd.trigger(ApplyLayout);
+                    }
+                    Err(err) => editor.status = Some(err.to_string()),
+                }
+            }
+        });
+        ui.label(format!("{} stations", layout.stations.len()));
+        if let Some(status) = &editor.status {
+            ui.label(status);
+        }
+        ui.collapsing("IO modules", |ui| {
+            io_modules(ui, &mut cmd, &mut layout, &mut editor, &tags);
+        });
+    });
+    Ok(())
+}
+
+/// Declared modules, io conflicts and the io list export
+fn io_modules(
+    ui: &mut egui::Ui,
+    cmd: &mut Commands,
+    layout: &mut Layout,
+    editor: &mut LayoutEditor,
+    tags: &TagTable,
+) {
+    let mut resized = false;
+    let mut remove = None;
+    egui::Grid::new("io modules").striped(true).show(ui, |ui| {
+        for head in ["node", "name", "inputs", "outputs", ""] {
+            ui.label(head);
+        }
+        ui.end_row();
+        for (i, module) in layout.modules.iter_mut().enumerate() {
+            ui.label(format!("N{}", module.node.0));
+            ui.text_edit_singleline(&mut module.name);
+            let pins = 0..=MAX_MODULE_PINS;
+            // rebuilding the world on every step of a drag is too slow
+            let done = |response: egui::Response| response.drag_stopped() || response.lost_focus();
+            resized |= done(ui.add(egui::DragValue::new(&mut module.inputs).range(pins.clone())));
+            resized |= done(ui.add(egui::DragValue::new(&mut module.outputs).range(pins)));
+            if ui.small_button("x").clicked() {
+                remove = Some(i);
+            }
+            ui.end_row();
+        }
+    });
+    if let Some(i) = remove {
+        layout.modules.remove(i);
+        resized = true;
+    }
+    let node = editor.io_node;
+    let declared = find_module(&layout.modules, node).is_some();
+    let add = egui::Button::new(format!("add module N{}", node.0));
+    if ui.add_enabled(!declared, add).clicked() {
+        layout.modules.push(IoModule {
+            node,
+            name: format!("N{}", node.0),
+            inputs: 32,
+            outputs: 32,
+            bus: default(),
+            filter: default(),
+            pin_filters: Vec::new(),
+        });
+        resized = true;
+    }
+    if resized {
+        cmd.trigger(ApplyLayout);
+    }
+
+    let conflicts = layout.io_conflicts();
+    if conflicts.is_empty() {
+        ui.label("no io conflicts");
+    }
+    for conflict in conflicts.iter().take(20) {
+        ui.colored_label(egui::Color32::RED, conflict.to_string());
+    }
+    if conflicts.len() > 20 {
+        ui.label(format!("and {} more", conflicts.len() - 20));
+    }
+    if ui.button(format!("Export {IO_LIST_FILE}")).clicked() {
+        let written = std::fs::write(IO_LIST_FILE, layout.io_list_csv(tags));
+        editor.status = Some(match written {
+            Ok(()) => format!("wrote {IO_LIST_FILE}"),
+            Err(err) => format!("cant write {IO_LIST_FILE}: {err}"),
+        });
+    }
+}
+
+fn safety_panel(
+    mut cmd: Commands,
+    mut contexts: EguiContexts,
+    relay: Res<SafetyRelay>,
+    estops: Query<(Entity, &Name, &EStop)>,
+) -> Result {
+    egui::Window::new("Safety").show(contexts.ctx_mut()?, |ui| {
+        let (text, color) = match relay.state() {
+            SafetyState::Ok => ("power on", egui::Color32::GREEN),
+            SafetyState::Tripped => ("TRIPPED", egui::Color32::RED),
+            SafetyState::Acknowledged => ("acknowledged, reset required", egui::Color32::YELLOW),
+        };
+        ui.colored_label(color, text);
+        if let Some(cause) = &relay.cause {
+            ui.label(format!("cause: {cause}"));
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Acknowledge").clicked() {
+                cmd.trigger(SafetyAck);
+            }
+            if ui.button("Reset").clicked() {
+                cmd.trigger(SafetyReset);
             }
         });
+        for (entity, name, estop) in estops {
+            ui.horizontal(|ui| {
+                ui.label(name.as_str());
+                let (label, pressed) = if estop.pressed {
+                    ("pull out", false)
+                } else {
+                    ("press", true)
+                };
+                if ui.button(label).clicked() {
+                    cmd.trigger(EStopActuate { entity, pressed });
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn auto_checkbox(ui: &mut egui::Ui, cmd: &mut Commands, entity: Entity, auto: &AutoMode) {
+    let mut auto_on = **auto;
+    if ui.checkbox(&mut auto_on, "").changed() {
+        cmd.trigger(SwitchMode {
+            entity,
+            auto: auto_on,
+        });
     }
 }
+
+/// Auto checkbox and hold-to-run jog buttons of a station
+fn mode_widget(
+    ui: &mut egui::Ui,
+    cmd: &mut Commands,
+    entity: Entity,
+    auto: &AutoMode,
+    jog: &mut Mut<ManualJog>,
+) {
+    auto_checkbox(ui, cmd, entity, auto);
+    ui.horizontal(|ui| {
+        let manual = !**auto;
+        let rev = ui
+            .add_enabled(manual, egui::Button::new("<<"))
+            .is_pointer_button_down_on();
+        let fw = ui
+            .add_enabled(manual, egui::Button::new(">>"))
+            .is_pointer_button_down_on();
+        let wanted = match (rev, fw) {
+            (true, false) => Some(Direction::Reverse),
+            (false, true) => Some(Direction::Forward),
+            _ => None,
+        };
+        jog.set_if_neq(ManualJog(wanted));
+    });
+}